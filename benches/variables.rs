@@ -0,0 +1,82 @@
+//! Times variable-heavy scripts on both backends.
+//!
+//! Run with `cargo bench`. Each script runs a few times on a fresh engine
+//! and the fastest run is reported, as the others mostly measure noise.
+
+use lox::engine::{Backend, Engine};
+use lox::output::BufferOutput;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+const SCRIPTS: &[(&str, &str)] = &[
+    (
+        "nested locals",
+        "{
+            var sum = 0;
+            for (var i = 0; i < 300000; i = i + 1) {
+                var a = i; var b = a + 1; var c = b + a;
+                { var d = c - b; sum = sum + d; }
+            }
+            print sum;
+        }",
+    ),
+    (
+        "closures",
+        "fun counter() {
+            var count = 0;
+            fun next() { count = count + 1; return count; }
+            return next;
+        }
+        fun run() {
+            var next = counter();
+            var total = 0;
+            for (var i = 0; i < 200000; i = i + 1) total = total + next();
+            return total;
+        }
+        print run();",
+    ),
+    (
+        "fib",
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+        print fib(22);",
+    ),
+    (
+        "methods",
+        "class Point {
+            init(x, y) { this.x = x; this.y = y; }
+            add(other) { return Point(this.x + other.x, this.y + other.y); }
+        }
+        {
+            var p = Point(0, 0);
+            var step = Point(1, 2);
+            for (var i = 0; i < 100000; i = i + 1) p = p.add(step);
+            print p.x + p.y;
+        }",
+    ),
+];
+
+fn time(source: &str, backend: Backend) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut engine = Engine::with_output(BufferOutput::new()).with_backend(backend);
+            let start = Instant::now();
+            engine.run(source).unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    println!("{:<16} {:>12} {:>12}", "script", "tree walker", "vm");
+
+    for (name, source) in SCRIPTS {
+        println!(
+            "{:<16} {:>10.1}ms {:>10.1}ms",
+            name,
+            time(source, Backend::TreeWalker).as_secs_f64() * 1000.0,
+            time(source, Backend::Vm).as_secs_f64() * 1000.0
+        );
+    }
+}
@@ -0,0 +1,870 @@
+use crate::expression::Visitable;
+use crate::expression::*;
+use crate::literal::*;
+use crate::span::*;
+use crate::statement::Visitable as VisitableStatement;
+use crate::statement::*;
+use crate::token::*;
+use crate::token_type::*;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// First bytes of every cache file.
+pub const MAGIC: &[u8; 4] = b"LOXC";
+
+/// Bumped whenever the encoding changes, including when syntax tree nodes
+/// or token types are added, removed or reordered.
+pub const FORMAT_VERSION: u16 = 2;
+
+/// How deeply statements and expressions may nest in a cached script, so
+/// a crafted file fails to decode instead of overflowing the stack. The
+/// same as `json::MAX_DEPTH`, which bounds the JSON import. A release
+/// build decodes a tree this deep within a 2 MiB thread stack.
+pub const MAX_TREE_DEPTH: usize = crate::json::MAX_DEPTH;
+
+// Magic, format version and checksum.
+const HEADER_LEN: usize = 4 + 2 + 4;
+
+/// A parsed script as `lox compile` stores it, so it can be run again
+/// without being scanned and parsed.
+///
+/// The file starts with `MAGIC`, the format version and a CRC-32 of the
+/// rest. Then come the path and checksum of the source, a table of every
+/// string used, and the statements. Numbers are LEB128 varints and strings
+/// are referred to by their index in the table.
+pub struct CachedScript {
+    pub statements: Vec<Box<Statement>>,
+    // Absolute path of the source the script was compiled from, if it was
+    // compiled from a file.
+    pub source_path: Option<PathBuf>,
+    pub source_checksum: u32,
+}
+
+impl CachedScript {
+    /// Whether `bytes` look like a cache file rather than source text.
+    pub fn is_cached(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    pub fn encode(
+        statements: &[Box<Statement>],
+        source: &str,
+        source_path: Option<&Path>,
+    ) -> Vec<u8> {
+        let mut writer = CacheWriter::default();
+        writer.statements(statements);
+
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&crc32(source.as_bytes()).to_le_bytes());
+        let path = source_path.map_or(String::new(), |path| path.to_string_lossy().into_owned());
+        write_string(&mut payload, &path);
+
+        write_varint(&mut payload, writer.strings.len() as u64);
+        for string in &writer.strings {
+            write_string(&mut payload, string);
+        }
+        payload.extend_from_slice(&writer.bytes);
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    /// Reads back what `encode` wrote, refusing files written by another
+    /// format version and files that were damaged since.
+    pub fn decode(bytes: &[u8]) -> Result<CachedScript, String> {
+        if bytes.len() < HEADER_LEN || !Self::is_cached(bytes) {
+            return Err(String::from("Not a compiled Lox script."));
+        }
+
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != FORMAT_VERSION {
+            return Err(format!(
+                "Compiled with format version {}, but this lox reads version {}. Compile the script again.",
+                version, FORMAT_VERSION
+            ));
+        }
+
+        let checksum = u32::from_le_bytes(bytes[6..10].try_into().expect("four bytes"));
+        let payload = &bytes[HEADER_LEN..];
+        if crc32(payload) != checksum {
+            return Err(String::from(
+                "Checksum mismatch, the file is corrupted. Compile the script again.",
+            ));
+        }
+
+        let mut reader = CacheReader {
+            bytes: payload,
+            position: 0,
+            strings: Vec::new(),
+            depth: 0,
+        };
+
+        let source_checksum = reader.u32()?;
+        let path = reader.string()?;
+        let count = reader.length()?;
+        for _ in 0..count {
+            let string = reader.string()?;
+            reader.strings.push(string);
+        }
+        let statements = reader.statements()?;
+
+        if reader.position != payload.len() {
+            return Err(String::from("Unexpected data after the last statement."));
+        }
+
+        Ok(CachedScript {
+            statements,
+            source_path: if path.is_empty() {
+                None
+            } else {
+                Some(PathBuf::from(path))
+            },
+            source_checksum,
+        })
+    }
+
+    /// The text of the source the script was compiled from, for pointing
+    /// at it in diagnostics. `None` when it is gone, and an error when it
+    /// has changed since, making this script stale.
+    pub fn current_source(&self) -> Result<Option<String>, String> {
+        let path = match &self.source_path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(_) => return Ok(None),
+        };
+
+        if crc32(source.as_bytes()) != self.source_checksum {
+            return Err(format!(
+                "{} has changed since it was compiled. Compile it again.",
+                path.display()
+            ));
+        }
+
+        Ok(Some(source))
+    }
+}
+
+/// The CRC-32 used by zip and PNG.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn write_string(bytes: &mut Vec<u8>, string: &str) {
+    write_varint(bytes, string.len() as u64);
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+// Tags telling nodes apart. Their values are part of the format.
+const PRINT: u8 = 0;
+const EXPRESSION: u8 = 1;
+const VAR: u8 = 2;
+const BLOCK: u8 = 3;
+const IF: u8 = 4;
+const WHILE: u8 = 5;
+const FUNCTION: u8 = 6;
+const RETURN: u8 = 7;
+const CLASS: u8 = 8;
+
+const ASSIGN: u8 = 0;
+const BINARY: u8 = 1;
+const LOGICAL: u8 = 2;
+const CALL: u8 = 3;
+const GET: u8 = 4;
+const SET: u8 = 5;
+const THIS: u8 = 6;
+const SUPER: u8 = 7;
+const GROUPING: u8 = 8;
+const LITERAL: u8 = 9;
+const UNARY: u8 = 10;
+const VARIABLE: u8 = 11;
+
+const NO_LITERAL: u8 = 0;
+const STRING: u8 = 1;
+const NUMBER: u8 = 2;
+const BOOLEAN: u8 = 3;
+const NIL: u8 = 4;
+
+/// Encodes statements and expressions, collecting the strings they use.
+#[derive(Default)]
+struct CacheWriter {
+    bytes: Vec<u8>,
+    strings: Vec<String>,
+    interned: HashMap<String, u32>,
+}
+
+impl CacheWriter {
+    fn statements(&mut self, statements: &[Box<Statement>]) {
+        write_varint(&mut self.bytes, statements.len() as u64);
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        self.span(&statement.span());
+        statement
+            .accept(self)
+            .unwrap_or_else(|never| match never {});
+    }
+
+    fn expression(&mut self, expression: &Expr) {
+        expression
+            .accept(self)
+            .unwrap_or_else(|never| match never {});
+
+        // Groupings and literals keep their span in the node rather than
+        // in a token, and the visitor isn't given it.
+        if let Expr::Grouping { span, .. } | Expr::Literal { span, .. } = expression {
+            self.span(span);
+        }
+    }
+
+    fn optional_expression(&mut self, expression: &Option<Box<Expr>>) {
+        match expression {
+            Some(expression) => {
+                self.bytes.push(1);
+                self.expression(expression);
+            }
+            None => self.bytes.push(0),
+        }
+    }
+
+    fn tag(&mut self, tag: u8) {
+        self.bytes.push(tag);
+    }
+
+    fn string(&mut self, string: &str) {
+        let index = match self.interned.get(string) {
+            Some(index) => *index,
+            None => {
+                let index = self.strings.len() as u32;
+                self.strings.push(string.to_string());
+                self.interned.insert(string.to_string(), index);
+                index
+            }
+        };
+
+        write_varint(&mut self.bytes, index as u64);
+    }
+
+    fn token(&mut self, token: &Token) {
+        let of_type = TokenType::ALL
+            .iter()
+            .position(|of_type| *of_type == token.of_type)
+            .expect("every token type is listed");
+        self.bytes.push(of_type as u8);
+        self.string(&token.lexeme);
+
+        match &token.literal {
+            Some(literal) => self.literal(literal),
+            None => self.bytes.push(NO_LITERAL),
+        }
+        self.span(&token.span);
+    }
+
+    fn tokens(&mut self, tokens: &[Token]) {
+        write_varint(&mut self.bytes, tokens.len() as u64);
+        for token in tokens {
+            self.token(token);
+        }
+    }
+
+    fn literal(&mut self, literal: &Literal) {
+        match literal {
+            Literal::String(s) => {
+                self.bytes.push(STRING);
+                self.string(s);
+            }
+            Literal::Number(x) => {
+                self.bytes.push(NUMBER);
+                self.bytes.extend_from_slice(&x.to_le_bytes());
+            }
+            Literal::Boolean(b) => {
+                self.bytes.push(BOOLEAN);
+                self.bytes.push(*b as u8);
+            }
+            Literal::None => self.bytes.push(NIL),
+        }
+    }
+
+    fn span(&mut self, span: &Span) {
+        for value in &[span.start, span.end, span.line, span.column] {
+            write_varint(&mut self.bytes, *value as u64);
+        }
+    }
+}
+
+impl ExprVisitor for CacheWriter {
+    type Value = ();
+    type Error = Infallible;
+
+    fn visit_assign_expression(
+        &mut self,
+        _id: usize,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<(), Infallible> {
+        self.tag(ASSIGN);
+        self.token(name);
+        self.expression(value);
+        Ok(())
+    }
+
+    fn visit_binary_expression(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<(), Infallible> {
+        self.tag(BINARY);
+        self.expression(left);
+        self.token(operator);
+        self.expression(right);
+        Ok(())
+    }
+
+    fn visit_logical_expression(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<(), Infallible> {
+        self.tag(LOGICAL);
+        self.expression(left);
+        self.token(operator);
+        self.expression(right);
+        Ok(())
+    }
+
+    fn visit_call_expression(
+        &mut self,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Box<Expr>],
+    ) -> Result<(), Infallible> {
+        self.tag(CALL);
+        self.expression(callee);
+        self.token(paren);
+        write_varint(&mut self.bytes, arguments.len() as u64);
+        for argument in arguments {
+            self.expression(argument);
+        }
+        Ok(())
+    }
+
+    fn visit_get_expression(&mut self, object: &Expr, name: &Token) -> Result<(), Infallible> {
+        self.tag(GET);
+        self.expression(object);
+        self.token(name);
+        Ok(())
+    }
+
+    fn visit_set_expression(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<(), Infallible> {
+        self.tag(SET);
+        self.expression(object);
+        self.token(name);
+        self.expression(value);
+        Ok(())
+    }
+
+    fn visit_this_expression(&mut self, _id: usize, keyword: &Token) -> Result<(), Infallible> {
+        self.tag(THIS);
+        self.token(keyword);
+        Ok(())
+    }
+
+    fn visit_super_expression(
+        &mut self,
+        _id: usize,
+        keyword: &Token,
+        method: &Token,
+    ) -> Result<(), Infallible> {
+        self.tag(SUPER);
+        self.token(keyword);
+        self.token(method);
+        Ok(())
+    }
+
+    fn visit_group_expression(&mut self, content: &Expr) -> Result<(), Infallible> {
+        self.tag(GROUPING);
+        self.expression(content);
+        Ok(())
+    }
+
+    fn visit_literal_expression(&mut self, literal: &Literal) -> Result<(), Infallible> {
+        self.tag(LITERAL);
+        self.literal(literal);
+        Ok(())
+    }
+
+    fn visit_unary_expression(&mut self, operator: &Token, right: &Expr) -> Result<(), Infallible> {
+        self.tag(UNARY);
+        self.token(operator);
+        self.expression(right);
+        Ok(())
+    }
+
+    fn visit_variable_expression(&mut self, _id: usize, name: &Token) -> Result<(), Infallible> {
+        self.tag(VARIABLE);
+        self.token(name);
+        Ok(())
+    }
+}
+
+impl StmtVisitor for CacheWriter {
+    type Value = ();
+    type Error = Infallible;
+
+    fn visit_var_stmt(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        initializer: &Option<Box<Expr>>,
+    ) -> Result<(), Infallible> {
+        self.tag(VAR);
+        self.token(name);
+        self.optional_expression(initializer);
+        Ok(())
+    }
+
+    fn visit_expression_stmt(&mut self, _stmt: &Statement, expr: &Expr) -> Result<(), Infallible> {
+        self.tag(EXPRESSION);
+        self.expression(expr);
+        Ok(())
+    }
+
+    fn visit_print_stmt(&mut self, _stmt: &Statement, expr: &Expr) -> Result<(), Infallible> {
+        self.tag(PRINT);
+        self.expression(expr);
+        Ok(())
+    }
+
+    fn visit_block_statement(
+        &mut self,
+        _stmt: &Statement,
+        statements: &[Box<Statement>],
+    ) -> Result<(), Infallible> {
+        self.tag(BLOCK);
+        self.statements(statements);
+        Ok(())
+    }
+
+    fn visit_if_statement(
+        &mut self,
+        _stmt: &Statement,
+        condition: &Expr,
+        then_branch: &Statement,
+        else_branch: &Option<Box<Statement>>,
+    ) -> Result<(), Infallible> {
+        self.tag(IF);
+        self.expression(condition);
+        self.statement(then_branch);
+        match else_branch {
+            Some(else_branch) => {
+                self.bytes.push(1);
+                self.statement(else_branch);
+            }
+            None => self.bytes.push(0),
+        }
+        Ok(())
+    }
+
+    fn visit_while_statement(
+        &mut self,
+        _stmt: &Statement,
+        condition: &Expr,
+        body: &Statement,
+    ) -> Result<(), Infallible> {
+        self.tag(WHILE);
+        self.expression(condition);
+        self.statement(body);
+        Ok(())
+    }
+
+    fn visit_function_statement(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        params: &[Token],
+        body: &Rc<[Box<Statement>]>,
+    ) -> Result<(), Infallible> {
+        self.tag(FUNCTION);
+        self.token(name);
+        self.tokens(params);
+        self.statements(body);
+        Ok(())
+    }
+
+    fn visit_return_statement(
+        &mut self,
+        _stmt: &Statement,
+        keyword: &Token,
+        value: &Option<Box<Expr>>,
+    ) -> Result<(), Infallible> {
+        self.tag(RETURN);
+        self.token(keyword);
+        self.optional_expression(value);
+        Ok(())
+    }
+
+    fn visit_class_statement(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        superclass: &Option<Box<Expr>>,
+        methods: &[Box<Statement>],
+    ) -> Result<(), Infallible> {
+        self.tag(CLASS);
+        self.token(name);
+        self.optional_expression(superclass);
+        self.statements(methods);
+        Ok(())
+    }
+}
+
+/// Decodes what `CacheWriter` encoded. Every read checks it stays inside
+/// the data, so a damaged file that got past the checksum still fails
+/// cleanly.
+struct CacheReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    strings: Vec<String>,
+    // How many statements and expressions are being decoded around the
+    // current one.
+    depth: usize,
+}
+
+impl CacheReader<'_> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or_else(|| String::from("Unexpected end of file."))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, count: usize) -> Result<&[u8], String> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| String::from("Unexpected end of file."))?;
+
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(String::from("Malformed number."))
+    }
+
+    fn length(&mut self) -> Result<usize, String> {
+        let length = self.varint()? as usize;
+
+        // Every item takes at least a byte, which rules out absurd lengths
+        // before anything is allocated for them.
+        if length > self.bytes.len() - self.position {
+            return Err(String::from("Unexpected end of file."));
+        }
+        Ok(length)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("four bytes")))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.length()?;
+        let bytes = self.take(length)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| String::from("Malformed string."))
+    }
+
+    fn interned(&mut self) -> Result<String, String> {
+        let index = self.varint()? as usize;
+
+        self.strings
+            .get(index)
+            .cloned()
+            .ok_or_else(|| format!("Unknown string {}.", index))
+    }
+
+    fn token(&mut self) -> Result<Token, String> {
+        let index = self.byte()?;
+        let of_type = *TokenType::ALL
+            .get(index as usize)
+            .ok_or_else(|| format!("Unknown token type {}.", index))?;
+        let lexeme = self.interned()?;
+
+        let literal = match self.byte()? {
+            NO_LITERAL => None,
+            tag => Some(self.literal(tag)?),
+        };
+
+        Ok(Token::with_span(of_type, lexeme, literal, self.span()?))
+    }
+
+    /// Reads the operator of a `kind` expression, rejecting tokens the
+    /// backends can't apply.
+    fn operator(
+        &mut self,
+        kind: &str,
+        is_operator: fn(TokenType) -> bool,
+    ) -> Result<Token, String> {
+        let operator = self.token()?;
+
+        if is_operator(operator.of_type) {
+            Ok(operator)
+        } else {
+            Err(format!(
+                "{:?} is not a {} operator.",
+                operator.of_type, kind
+            ))
+        }
+    }
+
+    fn tokens(&mut self) -> Result<Vec<Token>, String> {
+        let count = self.length()?;
+        (0..count).map(|_| self.token()).collect()
+    }
+
+    fn literal(&mut self, tag: u8) -> Result<Literal, String> {
+        match tag {
+            STRING => Ok(Literal::String(self.interned()?)),
+            NUMBER => {
+                let bytes = self.take(8)?;
+                Ok(Literal::Number(f64::from_le_bytes(
+                    bytes.try_into().expect("eight bytes"),
+                )))
+            }
+            BOOLEAN => Ok(Literal::Boolean(self.byte()? != 0)),
+            NIL => Ok(Literal::None),
+            tag => Err(format!("Unknown literal tag {}.", tag)),
+        }
+    }
+
+    fn span(&mut self) -> Result<Span, String> {
+        Ok(Span::new(
+            self.varint()? as usize,
+            self.varint()? as usize,
+            self.varint()? as usize,
+            self.varint()? as usize,
+        ))
+    }
+
+    fn optional_expression(&mut self) -> Result<Option<Box<Expr>>, String> {
+        match self.byte()? {
+            0 => Ok(None),
+            _ => self.expression().map(Some),
+        }
+    }
+
+    /// Decodes a node one level deeper than the current one.
+    fn nested<T>(&mut self, read: fn(&mut Self) -> Result<T, String>) -> Result<T, String> {
+        if self.depth == MAX_TREE_DEPTH {
+            return Err(String::from("Syntax tree nested too deeply."));
+        }
+
+        self.depth += 1;
+        let node = read(self);
+        self.depth -= 1;
+        node
+    }
+
+    fn expression(&mut self) -> Result<Box<Expr>, String> {
+        self.nested(Self::expression_node)
+    }
+
+    fn expression_node(&mut self) -> Result<Box<Expr>, String> {
+        let expression = match self.byte()? {
+            ASSIGN => Expr::Assign {
+                id: Expr::next_id(),
+                name: self.token()?,
+                value: self.expression()?,
+            },
+            BINARY => Expr::Binary {
+                left: self.expression()?,
+                operator: self.operator("binary", TokenType::is_binary_operator)?,
+                right: self.expression()?,
+            },
+            LOGICAL => Expr::Logical {
+                left: self.expression()?,
+                operator: self.operator("logical", TokenType::is_logical_operator)?,
+                right: self.expression()?,
+            },
+            CALL => Expr::Call {
+                callee: self.expression()?,
+                paren: self.token()?,
+                arguments: {
+                    let count = self.length()?;
+                    (0..count)
+                        .map(|_| self.expression())
+                        .collect::<Result<_, _>>()?
+                },
+            },
+            GET => Expr::Get {
+                object: self.expression()?,
+                name: self.token()?,
+            },
+            SET => Expr::Set {
+                object: self.expression()?,
+                name: self.token()?,
+                value: self.expression()?,
+            },
+            THIS => Expr::This {
+                id: Expr::next_id(),
+                keyword: self.token()?,
+            },
+            SUPER => Expr::Super {
+                id: Expr::next_id(),
+                keyword: self.token()?,
+                method: self.token()?,
+            },
+            GROUPING => Expr::Grouping {
+                expression: self.expression()?,
+                span: self.span()?,
+            },
+            LITERAL => {
+                let tag = self.byte()?;
+                Expr::Literal {
+                    literal: self.literal(tag)?,
+                    span: self.span()?,
+                }
+            }
+            UNARY => Expr::Unary {
+                operator: self.operator("unary", TokenType::is_unary_operator)?,
+                right: self.expression()?,
+            },
+            VARIABLE => Expr::Variable {
+                id: Expr::next_id(),
+                name: self.token()?,
+            },
+            tag => return Err(format!("Unknown expression tag {}.", tag)),
+        };
+
+        Ok(Box::new(expression))
+    }
+
+    #[allow(clippy::vec_box)]
+    fn statements(&mut self) -> Result<Vec<Box<Statement>>, String> {
+        let count = self.length()?;
+        (0..count).map(|_| self.statement()).collect()
+    }
+
+    fn statement(&mut self) -> Result<Box<Statement>, String> {
+        self.nested(Self::statement_node)
+    }
+
+    fn statement_node(&mut self) -> Result<Box<Statement>, String> {
+        let span = self.span()?;
+        let statement = match self.byte()? {
+            PRINT => Statement::Print {
+                expression: self.expression()?,
+                span,
+            },
+            EXPRESSION => Statement::Expression {
+                expression: self.expression()?,
+                span,
+            },
+            VAR => Statement::Variable {
+                name: self.token()?,
+                initializer: self.optional_expression()?,
+                span,
+            },
+            BLOCK => Statement::Block {
+                statements: self.statements()?,
+                span,
+            },
+            IF => Statement::If {
+                condition: self.expression()?,
+                then_branch: self.statement()?,
+                else_branch: match self.byte()? {
+                    0 => None,
+                    _ => Some(self.statement()?),
+                },
+                span,
+            },
+            WHILE => Statement::While {
+                condition: self.expression()?,
+                body: self.statement()?,
+                span,
+            },
+            FUNCTION => Statement::Function {
+                name: self.token()?,
+                params: self.tokens()?,
+                body: self.statements()?.into(),
+                span,
+            },
+            RETURN => Statement::Return {
+                keyword: self.token()?,
+                value: self.optional_expression()?,
+                span,
+            },
+            CLASS => Statement::Class {
+                name: self.token()?,
+                superclass: self.optional_expression()?,
+                methods: self.statements()?,
+                span,
+            },
+            tag => return Err(format!("Unknown statement tag {}.", tag)),
+        };
+
+        Ok(Box::new(statement))
+    }
+}
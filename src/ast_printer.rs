@@ -0,0 +1,322 @@
+use crate::expression::Visitable;
+use crate::expression::*;
+use crate::literal::*;
+use crate::statement::Visitable as VisitableStatement;
+use crate::statement::*;
+use crate::token::*;
+use std::convert::Infallible;
+use std::rc::Rc;
+
+/// How `AstPrinter` lays out the tree.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Layout {
+    /// Everything on one line, e.g. `(* (- 123) (group 45.67))`.
+    Lisp,
+    /// One node per line, children indented under their parent.
+    Tree,
+}
+
+/// Renders syntax trees as text, to see exactly what the parser produced.
+///
+/// Every node is a name followed by its children: `(+ 1 2)` in Lisp layout
+/// and
+///
+/// ```text
+/// +
+///   1
+///   2
+/// ```
+///
+/// in tree layout.
+pub struct AstPrinter {
+    layout: Layout,
+}
+
+impl AstPrinter {
+    pub fn new(layout: Layout) -> Self {
+        Self { layout }
+    }
+
+    pub fn print_expression(&mut self, expression: &Expr) -> String {
+        expression
+            .accept(self)
+            .unwrap_or_else(|never| match never {})
+    }
+
+    /// Renders each statement on its own line, or as its own tree.
+    pub fn print_statements(&mut self, statements: &[Box<Statement>]) -> String {
+        let mut output = String::new();
+
+        for statement in statements {
+            output.push_str(&self.print_statement(statement));
+            output.push('\n');
+        }
+
+        output
+    }
+
+    fn print_statement(&mut self, statement: &Statement) -> String {
+        statement
+            .accept(self)
+            .unwrap_or_else(|never| match never {})
+    }
+
+    fn node(&self, name: &str, children: Vec<String>) -> String {
+        if children.is_empty() {
+            return name.to_string();
+        }
+
+        match self.layout {
+            Layout::Lisp => format!("({} {})", name, children.join(" ")),
+            Layout::Tree => {
+                let mut output = name.to_string();
+                for child in children {
+                    for line in child.lines() {
+                        output.push_str("\n  ");
+                        output.push_str(line);
+                    }
+                }
+                output
+            }
+        }
+    }
+
+    fn function(&mut self, name: &Token, params: &[Token], body: &[Box<Statement>]) -> String {
+        let params = params.iter().map(|param| param.lexeme.clone()).collect();
+
+        let mut children = vec![name.lexeme.clone(), self.node("params", params)];
+        children.extend(body.iter().map(|statement| self.print_statement(statement)));
+
+        self.node("fun", children)
+    }
+}
+
+impl ExprVisitor for AstPrinter {
+    type Value = String;
+    type Error = Infallible;
+
+    fn visit_assign_expression(
+        &mut self,
+        _id: usize,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<String, Infallible> {
+        let value = self.print_expression(value);
+        Ok(self.node("=", vec![name.lexeme.clone(), value]))
+    }
+
+    fn visit_binary_expression(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<String, Infallible> {
+        let children = vec![self.print_expression(left), self.print_expression(right)];
+        Ok(self.node(&operator.lexeme, children))
+    }
+
+    fn visit_logical_expression(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<String, Infallible> {
+        let children = vec![self.print_expression(left), self.print_expression(right)];
+        Ok(self.node(&operator.lexeme, children))
+    }
+
+    fn visit_call_expression(
+        &mut self,
+        callee: &Expr,
+        _paren: &Token,
+        arguments: &[Box<Expr>],
+    ) -> Result<String, Infallible> {
+        let mut children = vec![self.print_expression(callee)];
+        children.extend(
+            arguments
+                .iter()
+                .map(|argument| self.print_expression(argument)),
+        );
+
+        Ok(self.node("call", children))
+    }
+
+    fn visit_get_expression(&mut self, object: &Expr, name: &Token) -> Result<String, Infallible> {
+        let object = self.print_expression(object);
+        Ok(self.node(".", vec![object, name.lexeme.clone()]))
+    }
+
+    fn visit_set_expression(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<String, Infallible> {
+        let object = self.print_expression(object);
+        let target = self.node(".", vec![object, name.lexeme.clone()]);
+        let value = self.print_expression(value);
+
+        Ok(self.node("=", vec![target, value]))
+    }
+
+    fn visit_this_expression(&mut self, _id: usize, keyword: &Token) -> Result<String, Infallible> {
+        Ok(keyword.lexeme.clone())
+    }
+
+    fn visit_super_expression(
+        &mut self,
+        _id: usize,
+        keyword: &Token,
+        method: &Token,
+    ) -> Result<String, Infallible> {
+        Ok(self.node(".", vec![keyword.lexeme.clone(), method.lexeme.clone()]))
+    }
+
+    fn visit_group_expression(&mut self, content: &Expr) -> Result<String, Infallible> {
+        let content = self.print_expression(content);
+        Ok(self.node("group", vec![content]))
+    }
+
+    fn visit_literal_expression(&mut self, literal: &Literal) -> Result<String, Infallible> {
+        Ok(match literal {
+            // Escaped the way the scanner reads it back.
+            Literal::String(s) => format!("{:?}", s),
+            Literal::Number(x) => format_number(*x),
+            Literal::Boolean(b) => b.to_string(),
+            Literal::None => String::from("nil"),
+        })
+    }
+
+    fn visit_unary_expression(
+        &mut self,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<String, Infallible> {
+        let right = self.print_expression(right);
+        Ok(self.node(&operator.lexeme, vec![right]))
+    }
+
+    fn visit_variable_expression(
+        &mut self,
+        _id: usize,
+        name: &Token,
+    ) -> Result<String, Infallible> {
+        Ok(name.lexeme.clone())
+    }
+}
+
+impl StmtVisitor for AstPrinter {
+    type Value = String;
+    type Error = Infallible;
+
+    fn visit_var_stmt(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        initializer: &Option<Box<Expr>>,
+    ) -> Result<String, Infallible> {
+        let mut children = vec![name.lexeme.clone()];
+        children.extend(initializer.iter().map(|value| self.print_expression(value)));
+
+        Ok(self.node("var", children))
+    }
+
+    fn visit_expression_stmt(
+        &mut self,
+        _stmt: &Statement,
+        expr: &Expr,
+    ) -> Result<String, Infallible> {
+        let expr = self.print_expression(expr);
+        Ok(self.node(";", vec![expr]))
+    }
+
+    fn visit_print_stmt(&mut self, _stmt: &Statement, expr: &Expr) -> Result<String, Infallible> {
+        let expr = self.print_expression(expr);
+        Ok(self.node("print", vec![expr]))
+    }
+
+    fn visit_block_statement(
+        &mut self,
+        _stmt: &Statement,
+        statements: &[Box<Statement>],
+    ) -> Result<String, Infallible> {
+        let children = statements
+            .iter()
+            .map(|statement| self.print_statement(statement))
+            .collect();
+
+        Ok(self.node("block", children))
+    }
+
+    fn visit_if_statement(
+        &mut self,
+        _stmt: &Statement,
+        condition: &Expr,
+        then_branch: &Statement,
+        else_branch: &Option<Box<Statement>>,
+    ) -> Result<String, Infallible> {
+        let mut children = vec![
+            self.print_expression(condition),
+            self.print_statement(then_branch),
+        ];
+        children.extend(
+            else_branch
+                .iter()
+                .map(|branch| self.print_statement(branch)),
+        );
+
+        Ok(self.node("if", children))
+    }
+
+    fn visit_while_statement(
+        &mut self,
+        _stmt: &Statement,
+        condition: &Expr,
+        body: &Statement,
+    ) -> Result<String, Infallible> {
+        let children = vec![self.print_expression(condition), self.print_statement(body)];
+        Ok(self.node("while", children))
+    }
+
+    fn visit_function_statement(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        params: &[Token],
+        body: &Rc<[Box<Statement>]>,
+    ) -> Result<String, Infallible> {
+        Ok(self.function(name, params, body))
+    }
+
+    fn visit_return_statement(
+        &mut self,
+        _stmt: &Statement,
+        _keyword: &Token,
+        value: &Option<Box<Expr>>,
+    ) -> Result<String, Infallible> {
+        let children = value
+            .iter()
+            .map(|value| self.print_expression(value))
+            .collect();
+        Ok(self.node("return", children))
+    }
+
+    fn visit_class_statement(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        superclass: &Option<Box<Expr>>,
+        methods: &[Box<Statement>],
+    ) -> Result<String, Infallible> {
+        let mut children = vec![name.lexeme.clone()];
+
+        if let Some(superclass) = superclass {
+            let superclass = self.print_expression(superclass);
+            children.push(self.node("<", vec![superclass]));
+        }
+
+        children.extend(methods.iter().map(|method| self.print_statement(method)));
+
+        Ok(self.node("class", children))
+    }
+}
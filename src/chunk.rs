@@ -0,0 +1,247 @@
+use crate::object::*;
+use crate::token::*;
+use crate::token_type::*;
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+/// One bytecode instruction with its operands.
+///
+/// Constant, global and property operands index the chunk's constant table.
+/// Local and upvalue operands are slots, and jumps are distances counted in
+/// instructions from the one after the jump.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OpCode {
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u8),
+    SetLocal(u8),
+    GetGlobal(u32),
+    DefineGlobal(u32),
+    SetGlobal(u32),
+    GetUpvalue(u8),
+    SetUpvalue(u8),
+    GetProperty(u32),
+    SetProperty(u32),
+    GetSuper(u32),
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump(u32),
+    JumpIfFalse(u32),
+    Loop(u32),
+    Call(u8),
+    // Indexes the chunk's functions rather than its constants.
+    Closure(u32),
+    CloseUpvalue,
+    Return,
+    Class(u32),
+    Inherit,
+    Method(u32),
+}
+
+/// A compiled sequence of instructions and the data they refer to.
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub constants: Vec<Object>,
+    // Functions declared inside this one, created by `OpCode::Closure`.
+    pub functions: Vec<Rc<Function>>,
+    // The source line of every instruction in `code`.
+    pub lines: Vec<usize>,
+    // The token each instruction that can fail at run time is reported
+    // at, by offset. Sorted, as offsets only ever grow.
+    tokens: Vec<(usize, Token)>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `op` and returns its offset.
+    pub fn write(&mut self, op: OpCode, line: usize) -> usize {
+        self.code.push(op);
+        self.lines.push(line);
+        self.code.len() - 1
+    }
+
+    /// Appends `op`, remembering that errors it raises are reported at
+    /// `token`.
+    pub fn write_at(&mut self, op: OpCode, token: &Token) -> usize {
+        let offset = self.write(op, token.span.line);
+        self.tokens.push((offset, token.clone()));
+        offset
+    }
+
+    /// Adds `value` to the constant table, or `None` when it is full.
+    pub fn add_constant(&mut self, value: Object) -> Option<u32> {
+        let index = u32::try_from(self.constants.len()).ok()?;
+        self.constants.push(value);
+        Some(index)
+    }
+
+    /// The token to report an error raised by the instruction at `offset`
+    /// at. Instructions written without one get a token that only knows
+    /// its line.
+    pub fn token_at(&self, offset: usize) -> Token {
+        match self
+            .tokens
+            .binary_search_by_key(&offset, |(offset, _)| *offset)
+        {
+            Ok(i) => self.tokens[i].1.clone(),
+            Err(_) => Token::new(TokenType::EOF, String::new(), None, self.lines[offset]),
+        }
+    }
+
+    /// Lists every instruction under a `== name ==` header.
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut listing = format!("== {} ==\n", name);
+
+        for offset in 0..self.code.len() {
+            listing.push_str(&self.disassemble_instruction(offset));
+            listing.push('\n');
+        }
+
+        listing
+    }
+
+    /// One line per instruction: its offset, its source line (or `|` when
+    /// that is the same as the previous instruction's), its name and its
+    /// operands.
+    pub fn disassemble_instruction(&self, offset: usize) -> String {
+        let line = if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
+            String::from("   |")
+        } else {
+            format!("{:4}", self.lines[offset])
+        };
+
+        let constant = |name: &str, index: u32| {
+            format!(
+                "{:<16} {:4} '{}'",
+                name, index, self.constants[index as usize]
+            )
+        };
+        let slot = |name: &str, slot: u8| format!("{:<16} {:4}", name, slot);
+        let jump = |name: &str, target: usize| format!("{:<16} {:4} -> {}", name, offset, target);
+
+        let instruction = match self.code[offset] {
+            OpCode::Constant(index) => constant("OP_CONSTANT", index),
+            OpCode::Nil => String::from("OP_NIL"),
+            OpCode::True => String::from("OP_TRUE"),
+            OpCode::False => String::from("OP_FALSE"),
+            OpCode::Pop => String::from("OP_POP"),
+            OpCode::GetLocal(index) => slot("OP_GET_LOCAL", index),
+            OpCode::SetLocal(index) => slot("OP_SET_LOCAL", index),
+            OpCode::GetGlobal(index) => constant("OP_GET_GLOBAL", index),
+            OpCode::DefineGlobal(index) => constant("OP_DEFINE_GLOBAL", index),
+            OpCode::SetGlobal(index) => constant("OP_SET_GLOBAL", index),
+            OpCode::GetUpvalue(index) => slot("OP_GET_UPVALUE", index),
+            OpCode::SetUpvalue(index) => slot("OP_SET_UPVALUE", index),
+            OpCode::GetProperty(index) => constant("OP_GET_PROPERTY", index),
+            OpCode::SetProperty(index) => constant("OP_SET_PROPERTY", index),
+            OpCode::GetSuper(index) => constant("OP_GET_SUPER", index),
+            OpCode::Equal => String::from("OP_EQUAL"),
+            OpCode::Greater => String::from("OP_GREATER"),
+            OpCode::GreaterEqual => String::from("OP_GREATER_EQUAL"),
+            OpCode::Less => String::from("OP_LESS"),
+            OpCode::LessEqual => String::from("OP_LESS_EQUAL"),
+            OpCode::Add => String::from("OP_ADD"),
+            OpCode::Subtract => String::from("OP_SUBTRACT"),
+            OpCode::Multiply => String::from("OP_MULTIPLY"),
+            OpCode::Divide => String::from("OP_DIVIDE"),
+            OpCode::Not => String::from("OP_NOT"),
+            OpCode::Negate => String::from("OP_NEGATE"),
+            OpCode::Print => String::from("OP_PRINT"),
+            OpCode::Jump(distance) => jump("OP_JUMP", offset + 1 + distance as usize),
+            OpCode::JumpIfFalse(distance) => {
+                jump("OP_JUMP_IF_FALSE", offset + 1 + distance as usize)
+            }
+            OpCode::Loop(distance) => jump("OP_LOOP", offset + 1 - distance as usize),
+            OpCode::Call(count) => slot("OP_CALL", count),
+            OpCode::Closure(index) => {
+                let function = &self.functions[index as usize];
+                let mut text = format!("{:<16} {:4} {}", "OP_CLOSURE", index, function);
+                for capture in &function.captures {
+                    text.push_str(&format!(
+                        "\n{:04}    |                     {} {}",
+                        offset,
+                        if capture.is_local { "local" } else { "upvalue" },
+                        capture.index
+                    ));
+                }
+                text
+            }
+            OpCode::CloseUpvalue => String::from("OP_CLOSE_UPVALUE"),
+            OpCode::Return => String::from("OP_RETURN"),
+            OpCode::Class(index) => constant("OP_CLASS", index),
+            OpCode::Inherit => String::from("OP_INHERIT"),
+            OpCode::Method(index) => constant("OP_METHOD", index),
+        };
+
+        format!("{:04} {} {}", offset, line, instruction)
+    }
+}
+
+/// Where a closure finds one of the variables it captures when it is
+/// created: a local slot of the enclosing function, or one of the enclosing
+/// closure's own upvalues.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Capture {
+    pub index: u8,
+    pub is_local: bool,
+}
+
+/// A function compiled to bytecode. The top-level script is a function
+/// with an empty name.
+#[derive(Default)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub captures: Vec<Capture>,
+    pub chunk: Chunk,
+}
+
+impl Function {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// The chunk of this function followed by those of every function
+    /// declared in it.
+    pub fn disassemble(&self) -> String {
+        let mut listing = self.chunk.disassemble(&self.to_string());
+
+        for function in &self.chunk.functions {
+            listing.push('\n');
+            listing.push_str(&function.disassemble());
+        }
+
+        listing
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "<script>")
+        } else {
+            write!(f, "<fn {}>", self.name)
+        }
+    }
+}
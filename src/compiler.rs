@@ -0,0 +1,737 @@
+use crate::chunk::*;
+use crate::expression::Visitable;
+use crate::expression::*;
+use crate::literal::*;
+use crate::lox_error::*;
+use crate::object::*;
+use crate::statement::Visitable as VisitableStatement;
+use crate::statement::*;
+use crate::token::*;
+use crate::token_type::*;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    // How many blocks deep the local was declared.
+    depth: usize,
+    // Whether a closure captured it, so it has to be moved off the stack
+    // when it goes out of scope.
+    is_captured: bool,
+}
+
+/// What the compiler knows about the function it is in the middle of.
+struct FunctionState {
+    function: Function,
+    kind: FunctionType,
+    // Slot 0 holds the function itself, or `this` in methods.
+    locals: Vec<Local>,
+    scope_depth: usize,
+    // Constant table indexes of the names used so far, so each is stored
+    // once.
+    names: HashMap<String, u32>,
+}
+
+impl FunctionState {
+    fn new(name: &str, kind: FunctionType) -> Self {
+        let slot_zero = match kind {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            FunctionType::Script | FunctionType::Function => "",
+        };
+
+        Self {
+            function: Function::new(name),
+            kind,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                is_captured: false,
+            }],
+            scope_depth: 0,
+            names: HashMap::new(),
+        }
+    }
+}
+
+/// Compiles resolved syntax trees to bytecode for the `Vm`.
+///
+/// Local variables live in stack slots worked out here, the way the
+/// `Resolver` works out scope distances for the interpreter, and globals are
+/// looked up by name. Programs are expected to have passed the resolver, so
+/// the only errors left to find are the limits of the bytecode format.
+pub struct Compiler {
+    // The function being compiled last, enclosing functions before it.
+    functions: Vec<FunctionState>,
+    // Line of the last token seen, for instructions without one.
+    line: usize,
+}
+
+impl Compiler {
+    fn new() -> Self {
+        Self {
+            functions: vec![FunctionState::new("", FunctionType::Script)],
+            line: 1,
+        }
+    }
+
+    /// Compiles a whole script. Running it returns nil.
+    pub fn compile(statements: &[Box<Statement>]) -> Result<Rc<Function>, LoxError> {
+        let mut compiler = Compiler::new();
+
+        for statement in statements {
+            compiler.statement(statement)?;
+        }
+        compiler.emit(OpCode::Nil);
+        compiler.emit(OpCode::Return);
+
+        Ok(Rc::new(compiler.end_function()))
+    }
+
+    /// Compiles a lone expression into a script returning its value.
+    pub fn compile_expression(expression: &Expr) -> Result<Rc<Function>, LoxError> {
+        let mut compiler = Compiler::new();
+
+        compiler.expression(expression)?;
+        compiler.emit(OpCode::Return);
+
+        Ok(Rc::new(compiler.end_function()))
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), LoxError> {
+        statement.accept(self)
+    }
+
+    fn expression(&mut self, expression: &Expr) -> Result<(), LoxError> {
+        // Literals have no token to take the line from.
+        if let Expr::Literal { span, .. } = expression {
+            self.line = span.line;
+        }
+
+        expression.accept(self)
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("there is always a function")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current().function.chunk
+    }
+
+    fn emit(&mut self, op: OpCode) -> usize {
+        let line = self.line;
+        self.chunk().write(op, line)
+    }
+
+    /// Emits an instruction that reports errors at `token`.
+    fn emit_at(&mut self, op: OpCode, token: &Token) -> usize {
+        self.line = token.span.line;
+        self.chunk().write_at(op, token)
+    }
+
+    fn emit_constant(&mut self, value: Object) -> Result<(), LoxError> {
+        let index = self.make_constant(value)?;
+        self.emit(OpCode::Constant(index));
+        Ok(())
+    }
+
+    fn make_constant(&mut self, value: Object) -> Result<u32, LoxError> {
+        match self.chunk().add_constant(value) {
+            Some(index) => Ok(index),
+            None => Err(self.error(None, "Too many constants in one chunk.")),
+        }
+    }
+
+    /// The constant holding the lexeme of `name`.
+    fn name_constant(&mut self, name: &Token) -> Result<u32, LoxError> {
+        if let Some(index) = self.current().names.get(&name.lexeme) {
+            return Ok(*index);
+        }
+
+        let index = self.make_constant(Object::Str(name.lexeme.clone()))?;
+        self.current().names.insert(name.lexeme.clone(), index);
+        Ok(index)
+    }
+
+    /// Emits a forward jump to be filled in by `patch_jump`.
+    fn emit_jump(&mut self, jump: fn(u32) -> OpCode) -> usize {
+        self.emit(jump(u32::MAX))
+    }
+
+    /// Points the jump at `offset` to the next instruction emitted.
+    fn patch_jump(&mut self, offset: usize) -> Result<(), LoxError> {
+        let distance = u32::try_from(self.chunk().code.len() - offset - 1)
+            .map_err(|_| self.error(None, "Too much code to jump over."))?;
+
+        let chunk = self.chunk();
+        chunk.code[offset] = match chunk.code[offset] {
+            OpCode::Jump(_) => OpCode::Jump(distance),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(distance),
+            op => unreachable!("{:?} is not a forward jump", op),
+        };
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, start: usize) -> Result<(), LoxError> {
+        let distance = u32::try_from(self.chunk().code.len() + 1 - start)
+            .map_err(|_| self.error(None, "Loop body too large."))?;
+
+        self.emit(OpCode::Loop(distance));
+        Ok(())
+    }
+
+    /// A compile error, reported at `token` when there is one to blame.
+    fn error(&self, token: Option<&Token>, message: &str) -> LoxError {
+        let token = token
+            .cloned()
+            .unwrap_or_else(|| Token::new(TokenType::EOF, String::new(), None, self.line));
+
+        LoxError::SyntaxErrors(vec![ParseError::SyntaxError(token, message.to_string())])
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    /// Drops the locals of the innermost block, closing over those a
+    /// closure captured.
+    fn end_scope(&mut self) {
+        let state = self.current();
+        state.scope_depth -= 1;
+
+        let depth = state.scope_depth;
+        let mut ops = Vec::new();
+        while let Some(local) = state.locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+
+            ops.push(if local.is_captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            });
+            state.locals.pop();
+        }
+
+        for op in ops {
+            self.emit(op);
+        }
+    }
+
+    /// Adds a local in the slot at the top of the stack, where its value
+    /// already is.
+    fn add_local(&mut self, name: &Token) -> Result<(), LoxError> {
+        if self.current().locals.len() > u8::MAX as usize {
+            return Err(self.error(Some(name), "Too many local variables in function."));
+        }
+
+        let state = self.current();
+        let depth = state.scope_depth;
+        state.locals.push(Local {
+            name: name.lexeme.clone(),
+            depth,
+            is_captured: false,
+        });
+        Ok(())
+    }
+
+    /// Binds the value on top of the stack to `name`: a new local inside a
+    /// block, a global otherwise.
+    fn define_variable(&mut self, name: &Token) -> Result<(), LoxError> {
+        if self.current().scope_depth > 0 {
+            return self.add_local(name);
+        }
+
+        let index = self.name_constant(name)?;
+        self.emit_at(OpCode::DefineGlobal(index), name);
+        Ok(())
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<u8> {
+        self.functions[function]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+            .map(|slot| slot as u8)
+    }
+
+    /// Finds `name` in a function enclosing `function`, threading it
+    /// through every function in between as an upvalue.
+    fn resolve_upvalue(&mut self, function: usize, name: &Token) -> Result<Option<u8>, LoxError> {
+        if function == 0 {
+            return Ok(None);
+        }
+
+        if let Some(slot) = self.resolve_local(function - 1, &name.lexeme) {
+            self.functions[function - 1].locals[slot as usize].is_captured = true;
+            return self.add_upvalue(function, slot, true, name).map(Some);
+        }
+
+        match self.resolve_upvalue(function - 1, name)? {
+            Some(index) => self.add_upvalue(function, index, false, name).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(
+        &mut self,
+        function: usize,
+        index: u8,
+        is_local: bool,
+        name: &Token,
+    ) -> Result<u8, LoxError> {
+        let capture = Capture { index, is_local };
+        let captures = &mut self.functions[function].function.captures;
+
+        if let Some(existing) = captures.iter().position(|c| *c == capture) {
+            return Ok(existing as u8);
+        }
+
+        if captures.len() > u8::MAX as usize {
+            return Err(self.error(Some(name), "Too many closure variables in function."));
+        }
+
+        captures.push(capture);
+        Ok((captures.len() - 1) as u8)
+    }
+
+    /// The instructions reading and writing the variable `name` refers to
+    /// from here.
+    fn variable_ops(&mut self, name: &Token) -> Result<(OpCode, OpCode), LoxError> {
+        let current = self.functions.len() - 1;
+
+        if let Some(slot) = self.resolve_local(current, &name.lexeme) {
+            return Ok((OpCode::GetLocal(slot), OpCode::SetLocal(slot)));
+        }
+
+        if let Some(index) = self.resolve_upvalue(current, name)? {
+            return Ok((OpCode::GetUpvalue(index), OpCode::SetUpvalue(index)));
+        }
+
+        let index = self.name_constant(name)?;
+        Ok((OpCode::GetGlobal(index), OpCode::SetGlobal(index)))
+    }
+
+    fn named_variable(&mut self, name: &Token) -> Result<(), LoxError> {
+        let (get, _) = self.variable_ops(name)?;
+        self.emit_at(get, name);
+        Ok(())
+    }
+
+    /// Compiles a function body and emits the closure creating it.
+    fn function(
+        &mut self,
+        name: &Token,
+        params: &[Token],
+        body: &[Box<Statement>],
+        kind: FunctionType,
+    ) -> Result<(), LoxError> {
+        self.functions.push(FunctionState::new(&name.lexeme, kind));
+        self.begin_scope();
+
+        let result = self.function_body(params, body);
+        let function = self.end_function();
+        result?;
+
+        let index = u32::try_from(self.chunk().functions.len())
+            .map_err(|_| self.error(Some(name), "Too many functions in one chunk."))?;
+
+        self.chunk().functions.push(Rc::new(function));
+        self.emit_at(OpCode::Closure(index), name);
+        Ok(())
+    }
+
+    fn function_body(&mut self, params: &[Token], body: &[Box<Statement>]) -> Result<(), LoxError> {
+        for param in params {
+            self.current().function.arity += 1;
+            self.add_local(param)?;
+        }
+
+        for statement in body {
+            self.statement(statement)?;
+        }
+
+        self.emit_return();
+        Ok(())
+    }
+
+    /// Returns nil, or `this` from an initializer.
+    fn emit_return(&mut self) {
+        if self.current().kind == FunctionType::Initializer {
+            self.emit(OpCode::GetLocal(0));
+        } else {
+            self.emit(OpCode::Nil);
+        }
+
+        self.emit(OpCode::Return);
+    }
+
+    fn end_function(&mut self) -> Function {
+        self.functions
+            .pop()
+            .expect("there is always a function")
+            .function
+    }
+}
+
+impl StmtVisitor for Compiler {
+    type Value = ();
+    type Error = LoxError;
+
+    fn visit_var_stmt(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        initializer: &Option<Box<Expr>>,
+    ) -> Result<(), LoxError> {
+        match initializer {
+            Some(initializer) => self.expression(initializer)?,
+            None => {
+                self.emit(OpCode::Nil);
+            }
+        }
+
+        self.define_variable(name)
+    }
+
+    fn visit_expression_stmt(&mut self, _stmt: &Statement, expr: &Expr) -> Result<(), LoxError> {
+        self.expression(expr)?;
+        self.emit(OpCode::Pop);
+        Ok(())
+    }
+
+    fn visit_print_stmt(&mut self, _stmt: &Statement, expr: &Expr) -> Result<(), LoxError> {
+        self.expression(expr)?;
+        self.emit(OpCode::Print);
+        Ok(())
+    }
+
+    fn visit_block_statement(
+        &mut self,
+        _stmt: &Statement,
+        statements: &[Box<Statement>],
+    ) -> Result<(), LoxError> {
+        self.begin_scope();
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        self.end_scope();
+
+        Ok(())
+    }
+
+    fn visit_if_statement(
+        &mut self,
+        _stmt: &Statement,
+        condition: &Expr,
+        then_branch: &Statement,
+        else_branch: &Option<Box<Statement>>,
+    ) -> Result<(), LoxError> {
+        self.expression(condition)?;
+
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit(OpCode::Pop);
+        self.statement(then_branch)?;
+
+        let else_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(then_jump)?;
+        self.emit(OpCode::Pop);
+
+        if let Some(else_branch) = else_branch {
+            self.statement(else_branch)?;
+        }
+
+        self.patch_jump(else_jump)
+    }
+
+    fn visit_while_statement(
+        &mut self,
+        _stmt: &Statement,
+        condition: &Expr,
+        body: &Statement,
+    ) -> Result<(), LoxError> {
+        let loop_start = self.chunk().code.len();
+        self.expression(condition)?;
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit(OpCode::Pop);
+        self.statement(body)?;
+        self.emit_loop(loop_start)?;
+
+        self.patch_jump(exit_jump)?;
+        self.emit(OpCode::Pop);
+        Ok(())
+    }
+
+    fn visit_function_statement(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        params: &[Token],
+        body: &Rc<[Box<Statement>]>,
+    ) -> Result<(), LoxError> {
+        // A local function is in scope in its own body, so it can recurse.
+        // The closure lands in the slot declared for it.
+        if self.current().scope_depth > 0 {
+            self.add_local(name)?;
+            return self.function(name, params, body, FunctionType::Function);
+        }
+
+        self.function(name, params, body, FunctionType::Function)?;
+        self.define_variable(name)
+    }
+
+    fn visit_return_statement(
+        &mut self,
+        _stmt: &Statement,
+        keyword: &Token,
+        value: &Option<Box<Expr>>,
+    ) -> Result<(), LoxError> {
+        self.line = keyword.span.line;
+
+        match value {
+            Some(value) => {
+                self.expression(value)?;
+                self.emit(OpCode::Return);
+            }
+            None => self.emit_return(),
+        }
+
+        Ok(())
+    }
+
+    fn visit_class_statement(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        superclass: &Option<Box<Expr>>,
+        methods: &[Box<Statement>],
+    ) -> Result<(), LoxError> {
+        let index = self.name_constant(name)?;
+        self.emit_at(OpCode::Class(index), name);
+        self.define_variable(name)?;
+
+        // Methods of a subclass close over an extra scope binding `super`
+        // to the superclass, which stays on the stack as its local.
+        if let Some(superclass) = superclass {
+            self.expression(superclass)?;
+
+            self.begin_scope();
+            let keyword =
+                Token::with_span(TokenType::SUPER, String::from("super"), None, name.span);
+            self.add_local(&keyword)?;
+
+            self.named_variable(name)?;
+            self.emit_at(OpCode::Inherit, name);
+        }
+
+        self.named_variable(name)?;
+        for method in methods {
+            if let Statement::Function {
+                name, params, body, ..
+            } = method.as_ref()
+            {
+                let kind = if name.lexeme == "init" {
+                    FunctionType::Initializer
+                } else {
+                    FunctionType::Method
+                };
+
+                self.function(name, params, body, kind)?;
+                let index = self.name_constant(name)?;
+                self.emit_at(OpCode::Method(index), name);
+            }
+        }
+        self.emit(OpCode::Pop);
+
+        if superclass.is_some() {
+            self.end_scope();
+        }
+        Ok(())
+    }
+}
+
+impl ExprVisitor for Compiler {
+    type Value = ();
+    type Error = LoxError;
+
+    fn visit_assign_expression(
+        &mut self,
+        _id: usize,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<(), LoxError> {
+        self.expression(value)?;
+
+        let (_, set) = self.variable_ops(name)?;
+        self.emit_at(set, name);
+        Ok(())
+    }
+
+    fn visit_binary_expression(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<(), LoxError> {
+        self.expression(left)?;
+        self.expression(right)?;
+
+        let op = match operator.of_type {
+            TokenType::PLUS => OpCode::Add,
+            TokenType::MINUS => OpCode::Subtract,
+            TokenType::STAR => OpCode::Multiply,
+            TokenType::SLASH => OpCode::Divide,
+            TokenType::GREATER => OpCode::Greater,
+            TokenType::GREATER_EQUAL => OpCode::GreaterEqual,
+            TokenType::LESS => OpCode::Less,
+            TokenType::LESS_EQUAL => OpCode::LessEqual,
+            TokenType::EQUAL_EQUAL => OpCode::Equal,
+            TokenType::BANG_EQUAL => {
+                self.emit_at(OpCode::Equal, operator);
+                OpCode::Not
+            }
+            other => {
+                let message = format!("{:?} is not a binary operator.", other);
+                return Err(self.error(Some(operator), &message));
+            }
+        };
+
+        self.emit_at(op, operator);
+        Ok(())
+    }
+
+    /// Leaves the left operand on the stack and skips the right one when
+    /// it decides the result.
+    fn visit_logical_expression(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<(), LoxError> {
+        self.expression(left)?;
+
+        let end_jump = if operator.of_type == TokenType::OR {
+            let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+            let end_jump = self.emit_jump(OpCode::Jump);
+            self.patch_jump(else_jump)?;
+            end_jump
+        } else {
+            self.emit_jump(OpCode::JumpIfFalse)
+        };
+
+        self.emit(OpCode::Pop);
+        self.expression(right)?;
+        self.patch_jump(end_jump)
+    }
+
+    fn visit_call_expression(
+        &mut self,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Box<Expr>],
+    ) -> Result<(), LoxError> {
+        self.expression(callee)?;
+        for argument in arguments {
+            self.expression(argument)?;
+        }
+
+        let count = u8::try_from(arguments.len())
+            .map_err(|_| self.error(Some(paren), "Can't have more than 255 arguments."))?;
+        self.emit_at(OpCode::Call(count), paren);
+        Ok(())
+    }
+
+    fn visit_get_expression(&mut self, object: &Expr, name: &Token) -> Result<(), LoxError> {
+        self.expression(object)?;
+
+        let index = self.name_constant(name)?;
+        self.emit_at(OpCode::GetProperty(index), name);
+        Ok(())
+    }
+
+    fn visit_set_expression(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<(), LoxError> {
+        self.expression(object)?;
+        self.expression(value)?;
+
+        let index = self.name_constant(name)?;
+        self.emit_at(OpCode::SetProperty(index), name);
+        Ok(())
+    }
+
+    fn visit_this_expression(&mut self, _id: usize, keyword: &Token) -> Result<(), LoxError> {
+        self.named_variable(keyword)
+    }
+
+    fn visit_super_expression(
+        &mut self,
+        _id: usize,
+        keyword: &Token,
+        method: &Token,
+    ) -> Result<(), LoxError> {
+        let this = Token::with_span(TokenType::THIS, String::from("this"), None, keyword.span);
+        self.named_variable(&this)?;
+        self.named_variable(keyword)?;
+
+        let index = self.name_constant(method)?;
+        self.emit_at(OpCode::GetSuper(index), method);
+        Ok(())
+    }
+
+    fn visit_group_expression(&mut self, content: &Expr) -> Result<(), LoxError> {
+        self.expression(content)
+    }
+
+    fn visit_literal_expression(&mut self, literal: &Literal) -> Result<(), LoxError> {
+        match literal {
+            Literal::None => {
+                self.emit(OpCode::Nil);
+            }
+            Literal::Boolean(true) => {
+                self.emit(OpCode::True);
+            }
+            Literal::Boolean(false) => {
+                self.emit(OpCode::False);
+            }
+            _ => self.emit_constant(Object::from_literal(literal))?,
+        }
+
+        Ok(())
+    }
+
+    fn visit_unary_expression(&mut self, operator: &Token, right: &Expr) -> Result<(), LoxError> {
+        self.expression(right)?;
+
+        match operator.of_type {
+            TokenType::MINUS => self.emit_at(OpCode::Negate, operator),
+            TokenType::BANG => self.emit_at(OpCode::Not, operator),
+            other => {
+                let message = format!("{:?} is not a unary operator.", other);
+                return Err(self.error(Some(operator), &message));
+            }
+        };
+
+        Ok(())
+    }
+
+    fn visit_variable_expression(&mut self, _id: usize, name: &Token) -> Result<(), LoxError> {
+        self.named_variable(name)
+    }
+}
@@ -0,0 +1,157 @@
+use crate::lox_error::*;
+use crate::token::*;
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// An error located in the source, ready to be shown to a user.
+///
+/// Rendered the way compilers do it:
+///
+/// ```text
+/// syntax error: Expect ';' after value.
+///  --> script.lox:1:8
+///   |
+/// 1 | print 1
+///   |        ^
+/// ```
+pub struct Diagnostic {
+    pub kind: &'static str,
+    pub message: String,
+    // Where the error happened, if it happened anywhere in particular.
+    pub token: Option<Token>,
+}
+
+impl Diagnostic {
+    pub fn new(kind: &'static str, message: &str, token: &Token) -> Self {
+        Self {
+            kind,
+            message: message.to_string(),
+            token: Some(token.clone()),
+        }
+    }
+
+    /// The diagnostics for `error`, one per syntax error when there are
+    /// several.
+    pub fn all(error: &LoxError) -> Vec<Diagnostic> {
+        match error {
+            LoxError::RuntimeError(token, message) => {
+                vec![Diagnostic::new("runtime error", message, token)]
+            }
+            LoxError::BindingError(token, message) => {
+                vec![Diagnostic::new("binding error", message, token)]
+            }
+            LoxError::SyntaxErrors(errors) => errors.iter().map(Diagnostic::from).collect(),
+            LoxError::IoError(e) => vec![Diagnostic {
+                kind: "error",
+                message: e.to_string(),
+                token: None,
+            }],
+        }
+    }
+
+    /// Renders the diagnostic against `source`, the text the token was
+    /// scanned from, using ANSI colors when `color` is set.
+    pub fn render(&self, file_name: &str, source: &str, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{}{}{}", style, text, RESET)
+            } else {
+                text.to_string()
+            }
+        };
+
+        let mut output = format!(
+            "{}{}\n",
+            paint(RED, &format!("{}:", self.kind)),
+            paint(BOLD, &format!(" {}", self.message)),
+        );
+
+        let token = match &self.token {
+            Some(token) => token,
+            None => {
+                output.push_str(&format!(" {} {}\n", paint(BLUE, "-->"), file_name));
+                return output;
+            }
+        };
+        let span = token.span;
+
+        // Tokens made up by the interpreter, or scanned from an earlier
+        // REPL line, don't point into `source`.
+        let in_source =
+            span.column > 0 && source.get(span.start..span.end) == Some(&token.lexeme[..]);
+
+        if !in_source {
+            output.push_str(&format!(
+                " {} {}:{}\n",
+                paint(BLUE, "-->"),
+                file_name,
+                span.line
+            ));
+            return output;
+        }
+
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[span.start..]
+            .find('\n')
+            .map_or(source.len(), |i| span.start + i);
+        let line_text = source[line_start..line_end].trim_end_matches('\r');
+
+        // Tabs are kept so the caret lines up however wide they show.
+        let padding: String = source[line_start..span.start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let underline_end = span.end.min(line_end);
+        let width = source[span.start..underline_end].chars().count().max(1);
+
+        let line_number = span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        output.push_str(&format!(
+            "{}{} {}:{}:{}\n",
+            gutter,
+            paint(BLUE, "-->"),
+            file_name,
+            span.line,
+            span.column
+        ));
+        output.push_str(&format!("{} {}\n", gutter, paint(BLUE, "|")));
+        output.push_str(&format!(
+            "{} {}\n",
+            paint(BLUE, &format!("{} |", line_number)),
+            line_text
+        ));
+        output.push_str(&format!(
+            "{} {} {}{}\n",
+            gutter,
+            paint(BLUE, "|"),
+            padding,
+            paint(RED, &"^".repeat(width))
+        ));
+
+        output
+    }
+}
+
+/// Renders every diagnostic for `error`, separated by blank lines.
+pub fn render_error(error: &LoxError, file_name: &str, source: &str, color: bool) -> String {
+    let rendered: Vec<String> = Diagnostic::all(error)
+        .iter()
+        .map(|diagnostic| diagnostic.render(file_name, source, color))
+        .collect();
+
+    rendered.join("\n")
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        match error {
+            ParseError::SyntaxError(token, message) => {
+                Diagnostic::new("syntax error", message, token)
+            }
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use crate::compiler::*;
+use crate::expression::*;
+use crate::gc::*;
+use crate::interpreter::*;
+use crate::lox_error::*;
+use crate::native_function::*;
+use crate::object::*;
+use crate::optimizer::*;
+use crate::output::*;
+use crate::parser::*;
+use crate::resolver::*;
+use crate::scanner::*;
+use crate::statement::*;
+use crate::vm::*;
+use std::fs;
+use std::path::Path;
+
+/// How an `Engine` runs scripts. Both give the same results.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Backend {
+    /// Walks the syntax tree directly.
+    TreeWalker,
+    /// Compiles to bytecode and runs that on a stack machine. Faster.
+    ///
+    /// A function can have at most 255 local variables in scope at once,
+    /// parameters included, and capture at most 256 variables from
+    /// enclosing functions. Going over either is a compile error at the
+    /// variable's name. The tree walker has no such limits.
+    Vm,
+}
+
+/// An embeddable Lox interpreter.
+///
+/// Globals persist between runs, so an `Engine` can be fed a script a piece
+/// at a time. Failures come back as `LoxError`s and nothing is printed to
+/// stderr; scripts still write `print` output to stdout.
+///
+/// Both backends stop recursion at `vm::FRAMES_MAX` calls with a "Stack
+/// overflow." error. The tree walker gets there on the native stack, so
+/// hosts that run deeply recursive scripts on it should give the thread a
+/// large stack: around 8 MiB in release builds and several times that in
+/// debug builds.
+///
+/// ```
+/// use lox::Engine;
+///
+/// let mut engine = Engine::new();
+/// engine.set_global("width", 3.0);
+/// engine.run("var area = width * width;").unwrap();
+/// assert_eq!(engine.get_global("area").and_then(|v| v.as_number()), Some(9.0));
+/// ```
+pub struct Engine {
+    // Holds the globals and output for either backend.
+    interpreter: Interpreter,
+    backend: Backend,
+    // Whether scripts go through the `optimizer` before running.
+    optimize: bool,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self {
+            interpreter: Interpreter::new(),
+            backend: Backend::TreeWalker,
+            optimize: true,
+        }
+    }
+
+    /// Creates an engine whose scripts print to `output` instead of stdout.
+    pub fn with_output<O: Output + 'static>(output: O) -> Self {
+        Self {
+            interpreter: Interpreter::with_output(Box::new(output)),
+            backend: Backend::TreeWalker,
+            optimize: true,
+        }
+    }
+
+    /// Runs scripts on `backend` from now on. The tree walker is the
+    /// default.
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Turns constant folding and dead branch elimination on or off. They
+    /// are on by default and never change what a script does.
+    pub fn with_optimizations(mut self, enabled: bool) -> Self {
+        self.optimize = enabled;
+        self
+    }
+
+    /// Collects garbage once `threshold` environments and objects are being
+    /// tracked, instead of `gc::DEFAULT_THRESHOLD`. The collector adapts
+    /// as the heap grows, so this mostly matters to hosts that want cycles
+    /// freed early or collections to run less often.
+    pub fn with_gc_threshold(mut self, threshold: usize) -> Self {
+        self.interpreter.heap.set_threshold(threshold);
+        self
+    }
+
+    /// Frees everything scripts left unreachable now, without waiting for
+    /// the threshold, and returns how many environments and objects that
+    /// was.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.interpreter.heap.collect()
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.interpreter.heap.stats()
+    }
+
+    /// The sink `print` writes to, for hosts that want to report errors
+    /// alongside script output.
+    pub fn output(&mut self) -> &mut dyn Output {
+        self.interpreter.output()
+    }
+
+    /// Runs `source` as a script.
+    pub fn run(&mut self, source: &str) -> Result<(), LoxError> {
+        let statements = self.parse(source)?;
+        self.execute(&statements)
+    }
+
+    /// Runs a script that was parsed already, such as one loaded from an
+    /// `ast_cache` file.
+    pub fn run_statements(&mut self, statements: &[Box<Statement>]) -> Result<(), LoxError> {
+        Resolver::new(&mut self.interpreter).resolve(statements)?;
+        self.execute(statements)
+    }
+
+    fn execute(&mut self, statements: &[Box<Statement>]) -> Result<(), LoxError> {
+        match self.backend {
+            Backend::TreeWalker => self.interpreter.interpret(statements),
+            Backend::Vm => {
+                let script = Compiler::compile(statements)?;
+                Vm::new(&mut self.interpreter).run(script).map(|_| ())
+            }
+        }
+    }
+
+    /// Reads and runs the script at `path`, ignoring a leading `#!` line.
+    pub fn run_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LoxError> {
+        let source = fs::read_to_string(path).map_err(LoxError::IoError)?;
+        self.run(skip_shebang(&source))
+    }
+
+    /// Like `run`, but when `source` is a single expression statement its
+    /// value is returned instead of being thrown away.
+    pub fn eval(&mut self, source: &str) -> Result<Option<Object>, LoxError> {
+        let statements = self.parse(source)?;
+
+        if let [statement] = statements.as_slice() {
+            if let Statement::Expression { expression, .. } = statement.as_ref() {
+                return match self.backend {
+                    Backend::TreeWalker => self.interpreter.evaluate(expression).map(Some),
+                    Backend::Vm => {
+                        let script = Compiler::compile_expression(expression)?;
+                        Vm::new(&mut self.interpreter).run(script).map(Some)
+                    }
+                };
+            }
+        }
+
+        match self.backend {
+            Backend::TreeWalker => self.interpreter.interpret(&statements).map(|_| None),
+            Backend::Vm => {
+                let script = Compiler::compile(&statements)?;
+                Vm::new(&mut self.interpreter).run(script).map(|_| None)
+            }
+        }
+    }
+
+    /// Compiles `source` without running it and lists the bytecode of the
+    /// script and every function in it.
+    pub fn disassemble(&mut self, source: &str) -> Result<String, LoxError> {
+        let statements = self.parse(source)?;
+        Ok(Compiler::compile(&statements)?.disassemble())
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Object> {
+        self.interpreter.globals.values.get(name).cloned()
+    }
+
+    /// Every global, natives included, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Object)> {
+        let mut globals: Vec<(String, Object)> = self
+            .interpreter
+            .globals
+            .values
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    /// Forgets everything scripts have defined. Output goes to the same
+    /// place as before.
+    pub fn reset(&mut self) {
+        self.interpreter.reset();
+    }
+
+    /// Defines or overwrites the global `name`.
+    pub fn set_global<T: Into<Object>>(&mut self, name: &str, value: T) {
+        self.interpreter.globals.define(name, &value.into());
+    }
+
+    /// Exposes a Rust function to scripts as the global `native.name`.
+    pub fn define_native(&mut self, native: NativeFunction) {
+        self.interpreter.define_native(native);
+    }
+
+    /// Scans, parses, resolves and optimizes `source` without running it.
+    #[allow(clippy::vec_box)]
+    pub fn parse(&mut self, source: &str) -> Result<Vec<Box<Statement>>, LoxError> {
+        let statements = parse(source)?;
+        Resolver::new(&mut self.interpreter).resolve(&statements)?;
+
+        if self.optimize {
+            Ok(optimize(statements))
+        } else {
+            Ok(statements)
+        }
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Scans and parses `source`, failing with every syntax error found.
+#[allow(clippy::vec_box)]
+pub fn parse(source: &str) -> Result<Vec<Box<Statement>>, LoxError> {
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens().to_vec();
+
+    let (statements, parse_errors) = Parser::new(tokens).parse();
+    let mut errors = std::mem::take(&mut scanner.errors);
+    if !errors.is_empty() || !parse_errors.is_empty() {
+        errors.extend(parse_errors);
+        errors.sort_by_key(|ParseError::SyntaxError(token, _)| token.span.start);
+        return Err(LoxError::SyntaxErrors(errors));
+    }
+
+    Ok(statements)
+}
+
+/// Scans and parses `source` as a single expression with nothing after it,
+/// not even a `;`.
+pub fn parse_expression(source: &str) -> Result<Box<Expr>, LoxError> {
+    let mut scanner = Scanner::new(source.to_string());
+    let mut parser = Parser::new(scanner.scan_tokens().to_vec());
+
+    let mut errors = std::mem::take(&mut scanner.errors);
+    match parser.parse_expression() {
+        Ok(_) if !parser.is_at_end() => errors.push(ParseError::SyntaxError(
+            parser.peek().clone(),
+            "Expect end of expression.".to_string(),
+        )),
+        Ok(expression) if errors.is_empty() => return Ok(expression),
+        Ok(_) => {}
+        Err(error) => errors.push(error),
+    }
+
+    errors.sort_by_key(|ParseError::SyntaxError(token, _)| token.span.start);
+    Err(LoxError::SyntaxErrors(errors))
+}
+
+/// Blanks out a leading `#!` line so scripts can be run directly, while
+/// keeping the newline so line numbers stay correct.
+pub fn skip_shebang(source: &str) -> &str {
+    if source.starts_with("#!") {
+        match source.find('\n') {
+            Some(newline) => &source[newline..],
+            None => "",
+        }
+    } else {
+        source
+    }
+}
@@ -1,55 +1,120 @@
+use crate::lox_error::*;
 use crate::object::*;
 use std::collections::HashMap;
 use crate::token::*;
 use std::cell::RefCell;
 use std::rc::Rc;
 
-#[derive(Clone, Debug)]
-pub struct Environment {
+/// The global scope. Globals can be used before they are declared and
+/// redefined freely, so they are looked up by name when they are used.
+#[derive(Clone, Debug, Default)]
+pub struct Globals {
     pub values: HashMap<String, Object>,
+}
+
+impl Globals {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn define(&mut self, name: &str, value: &Object) {
+        self.values.insert(name.to_string(), value.clone());
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Object, LoxError> {
+        self.values
+            .get(&name.lexeme)
+            .cloned()
+            .ok_or_else(|| undefined_variable(name))
+    }
+
+    pub fn assign(&mut self, name: &Token, value: Object) -> Result<(), LoxError> {
+        match self.values.get_mut(&name.lexeme) {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            }
+            None => Err(undefined_variable(name)),
+        }
+    }
+}
+
+/// One local scope. Its variables live in the slots the resolver gave them,
+/// numbered in the order they are declared, so they are found without
+/// looking at their names.
+///
+/// The outermost environment has no `enclosing` scope and holds nothing:
+/// what is declared there goes to `Globals` instead.
+#[derive(Clone, Debug, Default)]
+pub struct Environment {
+    pub values: Vec<Object>,
     pub enclosing: Option<Rc<RefCell<Environment>>>
 }
 
 impl Environment {
     pub fn new() -> Self {
-        Self {
-            values: HashMap::new(),
-            enclosing: None
-        }
+        Self::default()
     }
-    
+
     pub fn with_ref(environment: Rc<RefCell<Environment>>) -> Environment {
         Environment {
-            values: HashMap::new(),
+            values: Vec::new(),
             enclosing: Some(environment)
         }
     }
 
-    pub fn define(&mut self, name: &str, value: &Object) {
-        self.values.insert(name.to_string(), value.clone());
+    pub fn is_global(&self) -> bool {
+        self.enclosing.is_none()
     }
-    
-    pub fn assign(&mut self, name: &Token, value: Object) {
-        let variable = name.lexeme();
-        
-        match self.values.insert(variable, value.clone()) {
-            Some(_) => {},
-            None => {
-                if let Some(enclosing) = &self.enclosing {
-                    (*enclosing.borrow_mut()).assign(name, value.clone());
-                } else {
-                    panic!("Variable not found in scope.")
-                }
-            }
+
+    /// Declares the variable in the next slot.
+    pub fn define(&mut self, value: Object) {
+        self.values.push(value);
+    }
+
+    /// Reads `slot` of the scope exactly `distance` hops up the chain, as
+    /// worked out by the resolver.
+    pub fn get_at(&self, distance: usize, slot: usize) -> Object {
+        if distance == 0 {
+            self.values[slot].clone()
+        } else {
+            self.ancestor(distance).borrow().values[slot].clone()
         }
     }
-    
-    pub fn get(&mut self, name: Token) -> Object {
-        if let Some(value) = self.values.get(&name.lexeme()) {
-            value.clone()
-        } else if let Some(enclosing) = &self.enclosing {
-            return (*enclosing.borrow_mut()).get(name.clone())
+
+    pub fn assign_at(&mut self, distance: usize, slot: usize, value: Object) {
+        if distance == 0 {
+            self.values[slot] = value;
+        } else {
+            self.ancestor(distance).borrow_mut().values[slot] = value;
+        }
+    }
+
+    fn ancestor(&self, distance: usize) -> Rc<RefCell<Environment>> {
+        let mut environment = Rc::clone(
+            self.enclosing
+                .as_ref()
+                .expect("Resolved scope is deeper than the environment chain."),
+        );
+
+        for _ in 1..distance {
+            let enclosing = Rc::clone(
+                environment
+                    .borrow()
+                    .enclosing
+                    .as_ref()
+                    .expect("Resolved scope is deeper than the environment chain."),
+            );
+            environment = enclosing;
         }
-        else { Object::Nil }
+
+        environment
     }
 }
+
+fn undefined_variable(name: &Token) -> LoxError {
+    LoxError::RuntimeError(
+        name.clone(),
+        format!("Undefined variable '{}'.", name.lexeme),
+    )
+}
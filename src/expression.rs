@@ -1,29 +1,33 @@
-use crate::object::*;
+use crate::span::*;
 use crate::token::*;
 use crate::literal::*;
-use crate::lox_error::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum Expr {
-    //Logical {
-    //    left: Box<Expr>,
-    //    operator: Token,
-    //    right: Box<Expr>
-    //},
+    Logical {
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>
+    },
     Binary {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
     },
     Variable {
+        id: usize,
         name: Token
     },
+    // Spans the parentheses as well as the expression.
     Grouping {
         expression: Box<Expr>,
+        span: Span,
     },
 
     Literal {
         literal: Literal,
+        span: Span,
     },
 
     Unary {
@@ -32,67 +36,181 @@ pub enum Expr {
     },
 
     Assign {
+        id: usize,
         name: Token,
         value: Box<Expr>
+    },
+
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Box<Expr>>,
+    },
+
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
+
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
+
+    This {
+        id: usize,
+        keyword: Token,
+    },
+
+    Super {
+        id: usize,
+        keyword: Token,
+        method: Token,
+    },
+}
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl Expr {
+    /// Returns a fresh id for a node the resolver has to tell apart from
+    /// every other node, even ones parsed from an earlier REPL line.
+    pub fn next_id() -> usize {
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The region of source this expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                left.span().to(&right.span())
+            }
+            Expr::Variable { name, .. } => name.span,
+            Expr::Grouping { span, .. } | Expr::Literal { span, .. } => *span,
+            Expr::Unary { operator, right } => operator.span.to(&right.span()),
+            Expr::Assign { name, value, .. } => name.span.to(&value.span()),
+            Expr::Call { callee, paren, .. } => callee.span().to(&paren.span),
+            Expr::Get { object, name } => object.span().to(&name.span),
+            Expr::Set { object, value, .. } => object.span().to(&value.span()),
+            Expr::This { keyword, .. } => keyword.span,
+            Expr::Super { keyword, method, .. } => keyword.span.to(&method.span),
+        }
     }
 }
+
+/// A pass over expressions.
+///
+/// `Value` is what each node produces, e.g. an `Object` when evaluating or a
+/// `String` when printing, and `Error` is how the pass fails. Passes that
+/// cannot fail use `std::convert::Infallible`.
 pub trait ExprVisitor {
     type Value;
-    
+    type Error;
+
     fn visit_assign_expression(
         &mut self,
+        id: usize,
         name: &Token,
-        value: &Box<Expr>
-    ) -> Result<Self::Value, LoxError>;
+        value: &Expr
+    ) -> Result<Self::Value, Self::Error>;
 
     fn visit_binary_expression(
         &mut self,
-        left: &Box<Expr>,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<Self::Value, Self::Error>;
+
+    fn visit_logical_expression(
+        &mut self,
+        left: &Expr,
         operator: &Token,
-        right: &Box<Expr>,
-    ) -> Result<Self::Value, LoxError>;
+        right: &Expr,
+    ) -> Result<Self::Value, Self::Error>;
+
+    fn visit_call_expression(
+        &mut self,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Box<Expr>],
+    ) -> Result<Self::Value, Self::Error>;
+
+    fn visit_get_expression(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+    ) -> Result<Self::Value, Self::Error>;
+
+    fn visit_set_expression(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<Self::Value, Self::Error>;
+
+    fn visit_this_expression(
+        &mut self,
+        id: usize,
+        keyword: &Token,
+    ) -> Result<Self::Value, Self::Error>;
+
+    fn visit_super_expression(
+        &mut self,
+        id: usize,
+        keyword: &Token,
+        method: &Token,
+    ) -> Result<Self::Value, Self::Error>;
 
     fn visit_group_expression(
         &mut self,
-        content: &Box<Expr>,
-    ) -> Result<Self::Value, LoxError>;
+        content: &Expr,
+    ) -> Result<Self::Value, Self::Error>;
 
     fn visit_literal_expression(
         &mut self,
         literal: &Literal,
-    ) -> Result<Self::Value, LoxError>;
+    ) -> Result<Self::Value, Self::Error>;
 
     fn visit_unary_expression(
         &mut self,
         operator: &Token,
-        right: &Box<Expr>,
-    ) -> Result<Self::Value, LoxError>;
+        right: &Expr,
+    ) -> Result<Self::Value, Self::Error>;
 
     fn visit_variable_expression(
         &mut self,
+        id: usize,
         name: &Token
-    ) -> Result<Self::Value, LoxError>;
+    ) -> Result<Self::Value, Self::Error>;
 }
 
 pub trait Visitable {
-    fn accept(&self, visitor: &mut dyn ExprVisitor<Value = Object>) -> Result<Object, LoxError>;
+    fn accept<V: ExprVisitor + ?Sized>(&self, visitor: &mut V) -> Result<V::Value, V::Error>;
 }
 
 
 impl Visitable for Expr {
-    fn accept(&self, expr: &mut dyn ExprVisitor<Value=Object>) -> Result<Object, LoxError> {
+    fn accept<V: ExprVisitor + ?Sized>(&self, expr: &mut V) -> Result<V::Value, V::Error> {
         match self {
             Expr::Binary {
                 left,
                operator,
                 right,
-            } => expr.visit_binary_expression(&left, &operator, &right),
-            Expr::Grouping { expression } => expr.visit_group_expression(&expression),
-            Expr::Literal { literal } => expr.visit_literal_expression(&literal),
-            //Expr::Logical {left, operator, right} => expr.visit_logical_expression(&self, &left, &operator, &right),
-            Expr::Assign {name, value} => expr.visit_assign_expression(&name, &value),
-            Expr::Unary {operator, right } => expr.visit_unary_expression( &operator, &right),
-            Expr::Variable { name } => expr.visit_variable_expression(&name),
+            } => expr.visit_binary_expression(left, operator, right),
+            Expr::Grouping { expression, .. } => expr.visit_group_expression(expression),
+            Expr::Literal { literal, .. } => expr.visit_literal_expression(literal),
+            Expr::Logical {left, operator, right} => expr.visit_logical_expression(left, operator, right),
+            Expr::Assign {id, name, value} => expr.visit_assign_expression(*id, name, value),
+            Expr::Unary {operator, right } => expr.visit_unary_expression( operator, right),
+            Expr::Variable { id, name } => expr.visit_variable_expression(*id, name),
+            Expr::Call { callee, paren, arguments } => {
+                expr.visit_call_expression(callee, paren, arguments)
+            }
+            Expr::Get { object, name } => expr.visit_get_expression(object, name),
+            Expr::Set { object, name, value } => expr.visit_set_expression(object, name, value),
+            Expr::This { id, keyword } => expr.visit_this_expression(*id, keyword),
+            Expr::Super { id, keyword, method } => {
+                expr.visit_super_expression(*id, keyword, method)
+            }
         }
     }
 }
@@ -0,0 +1,465 @@
+//! Cycle collection for both backends.
+//!
+//! Values are reference counted, which frees everything except reference
+//! cycles. The `Heap` finds those by trial deletion rather than by a
+//! mark-sweep rooted at the interpreter's environment stack:
+//!
+//! - The tree walker has no environment stack to start from. The scopes
+//!   of the blocks and calls being run are held in Rust locals, in
+//!   `Interpreter::execute_block` and the frames above it.
+//! - Hosts and half-evaluated expressions hold values that aren't
+//!   recorded anywhere either.
+//! - Listing all of that would mean threading a root set through both
+//!   backends and the embedding API.
+//!
+//! Reference counts already see every holder. A tracked object whose count
+//! is higher than the references the heap can account for is therefore
+//! held from outside, and it is treated as a root. From there a collection
+//! is an ordinary mark and sweep: whatever the roots lead to is kept, and
+//! the rest is emptied so reference counting can free it.
+
+use crate::environment::*;
+use crate::lox_class::*;
+use crate::lox_function::*;
+use crate::lox_instance::*;
+use crate::object::*;
+use crate::vm::*;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+/// How many environments and objects a heap tracks before it first checks
+/// whether a collection is worth it.
+pub const DEFAULT_THRESHOLD: usize = 4096;
+
+/// What a heap has done so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GcStats {
+    /// Environments and objects the heap has started tracking.
+    pub tracked: usize,
+    pub collections: usize,
+    /// Everything collections found unreachable and freed.
+    pub freed: usize,
+    /// Tracked environments and objects alive after the last collection.
+    pub live: usize,
+}
+
+impl fmt::Display for GcStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} tracked, {} collections, {} freed, {} live",
+            self.tracked, self.collections, self.freed, self.live
+        )
+    }
+}
+
+/// Keeps track of the environments, instances and other mutable objects the
+/// interpreter and the VM allocate, and frees the ones that are only kept
+/// alive by reference cycles, such as a closure stored in the scope it
+/// captures or an instance holding one of its own bound methods.
+///
+/// Most objects are freed as soon as their last reference goes, without the
+/// heap's help. Collections mark from every object referenced from outside
+/// the heap: the scopes being run, the globals, the VM stack and anything
+/// a host holds. See the module docs for how these are found.
+///
+/// A collection runs when the number of tracked objects still alive reaches
+/// half the threshold, or what survived the last collection if that is
+/// more.
+#[derive(Debug)]
+pub struct Heap {
+    tracked: Vec<Tracked>,
+    threshold: usize,
+    next_collection: usize,
+    stats: GcStats,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self::with_threshold(DEFAULT_THRESHOLD)
+    }
+
+    pub fn with_threshold(threshold: usize) -> Self {
+        Self {
+            tracked: Vec::new(),
+            threshold,
+            next_collection: threshold,
+            stats: GcStats::default(),
+        }
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn set_threshold(&mut self, threshold: usize) {
+        self.threshold = threshold;
+        self.next_collection = threshold.max(self.stats.live * 2);
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// Tracks `environment` once a function declared in it closes over it.
+    /// Scopes nothing captures can't be part of a cycle, so they are left to
+    /// reference counting, and the scopes around a captured one are found
+    /// through it. The scope binding `this` to a method is not tracked
+    /// either: any cycle through it goes through the instance.
+    pub fn track_environment(&mut self, environment: &Rc<RefCell<Environment>>) {
+        if !environment.borrow().is_global() {
+            self.track(Tracked::Environment(Rc::downgrade(environment)));
+        }
+    }
+
+    pub fn instance(&mut self, instance: LoxInstance) -> Rc<RefCell<LoxInstance>> {
+        let instance = Rc::new(RefCell::new(instance));
+        self.track(Tracked::Instance(Rc::downgrade(&instance)));
+        instance
+    }
+
+    pub fn upvalue(&mut self, upvalue: Upvalue) -> Rc<RefCell<Upvalue>> {
+        let upvalue = Rc::new(RefCell::new(upvalue));
+        self.track(Tracked::Upvalue(Rc::downgrade(&upvalue)));
+        upvalue
+    }
+
+    pub fn vm_class(&mut self, class: VmClass) -> Rc<VmClass> {
+        let class = Rc::new(class);
+        self.track(Tracked::VmClass(Rc::downgrade(&class)));
+        class
+    }
+
+    pub fn vm_instance(&mut self, instance: VmInstance) -> Rc<RefCell<VmInstance>> {
+        let instance = Rc::new(RefCell::new(instance));
+        self.track(Tracked::VmInstance(Rc::downgrade(&instance)));
+        instance
+    }
+
+    fn track(&mut self, tracked: Tracked) {
+        self.tracked.push(tracked);
+        self.stats.tracked += 1;
+
+        if self.tracked.len() >= self.next_collection {
+            // Reference counting frees most things without help, so only
+            // trace when much of what was tracked is still around.
+            self.tracked.retain(Tracked::is_live);
+
+            if self.tracked.len() >= self.next_collection / 2 {
+                self.collect();
+            }
+        }
+    }
+
+    /// Frees everything only reachable from itself and returns how many
+    /// environments and objects that was.
+    ///
+    /// Nothing is collected while one of the tracked objects is mutably
+    /// borrowed, as what it refers to can't be seen then.
+    pub fn collect(&mut self) -> usize {
+        // Scopes with several functions in them are tracked once for each.
+        let mut seen = HashSet::new();
+        self.tracked
+            .retain(|tracked| tracked.is_live() && seen.insert(tracked.address()));
+
+        let freed = match Graph::build(&self.tracked) {
+            Some(graph) => graph.sweep(),
+            None => 0,
+        };
+
+        // Dropping the graph let go of the garbage.
+        self.tracked.retain(Tracked::is_live);
+
+        self.stats.collections += 1;
+        self.stats.freed += freed;
+        self.stats.live = self.tracked.len();
+        self.next_collection = self.threshold.max(self.stats.live * 2);
+
+        freed
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A weak reference to something the heap allocated. Only objects that can
+/// change after they are created can close a cycle, so they are the only
+/// ones tracked.
+#[derive(Debug)]
+enum Tracked {
+    Environment(Weak<RefCell<Environment>>),
+    Instance(Weak<RefCell<LoxInstance>>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+    VmClass(Weak<VmClass>),
+    VmInstance(Weak<RefCell<VmInstance>>),
+}
+
+impl Tracked {
+    fn is_live(&self) -> bool {
+        match self {
+            Tracked::Environment(weak) => weak.strong_count() > 0,
+            Tracked::Instance(weak) => weak.strong_count() > 0,
+            Tracked::Upvalue(weak) => weak.strong_count() > 0,
+            Tracked::VmClass(weak) => weak.strong_count() > 0,
+            Tracked::VmInstance(weak) => weak.strong_count() > 0,
+        }
+    }
+
+    fn address(&self) -> usize {
+        match self {
+            Tracked::Environment(weak) => weak.as_ptr() as *const () as usize,
+            Tracked::Instance(weak) => weak.as_ptr() as *const () as usize,
+            Tracked::Upvalue(weak) => weak.as_ptr() as *const () as usize,
+            Tracked::VmClass(weak) => weak.as_ptr() as *const () as usize,
+            Tracked::VmInstance(weak) => weak.as_ptr() as *const () as usize,
+        }
+    }
+
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            Tracked::Environment(weak) => weak.upgrade().map(Node::Environment),
+            Tracked::Instance(weak) => weak.upgrade().map(Node::Instance),
+            Tracked::Upvalue(weak) => weak.upgrade().map(Node::Upvalue),
+            Tracked::VmClass(weak) => weak.upgrade().map(Node::VmClass),
+            Tracked::VmInstance(weak) => weak.upgrade().map(Node::VmInstance),
+        }
+    }
+}
+
+/// Anything that refers to other heap objects, tracked or not.
+enum Node {
+    Environment(Rc<RefCell<Environment>>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    Function(Rc<LoxFunction>),
+    Class(Rc<LoxClass>),
+    Closure(Rc<Closure>),
+    Upvalue(Rc<RefCell<Upvalue>>),
+    BoundMethod(Rc<BoundMethod>),
+    VmClass(Rc<VmClass>),
+    VmInstance(Rc<RefCell<VmInstance>>),
+}
+
+impl Node {
+    fn from_object(object: &Object) -> Option<Node> {
+        match object {
+            Object::Function(function) => Some(Node::Function(Rc::clone(function))),
+            Object::Class(class) => Some(Node::Class(Rc::clone(class))),
+            Object::Instance(instance) => Some(Node::Instance(Rc::clone(instance))),
+            Object::Closure(closure) => Some(Node::Closure(Rc::clone(closure))),
+            Object::BoundMethod(method) => Some(Node::BoundMethod(Rc::clone(method))),
+            Object::VmClass(class) => Some(Node::VmClass(Rc::clone(class))),
+            Object::VmInstance(instance) => Some(Node::VmInstance(Rc::clone(instance))),
+            Object::Boolean(_) | Object::Number(_) | Object::Str(_) | Object::Native(_) => None,
+            Object::Nil => None,
+        }
+    }
+
+    fn address(&self) -> usize {
+        match self {
+            Node::Environment(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Instance(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Function(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Class(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Closure(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Upvalue(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::BoundMethod(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::VmClass(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::VmInstance(rc) => Rc::as_ptr(rc) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Environment(rc) => Rc::strong_count(rc),
+            Node::Instance(rc) => Rc::strong_count(rc),
+            Node::Function(rc) => Rc::strong_count(rc),
+            Node::Class(rc) => Rc::strong_count(rc),
+            Node::Closure(rc) => Rc::strong_count(rc),
+            Node::Upvalue(rc) => Rc::strong_count(rc),
+            Node::BoundMethod(rc) => Rc::strong_count(rc),
+            Node::VmClass(rc) => Rc::strong_count(rc),
+            Node::VmInstance(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    /// Every reference this node holds, once per reference, or `None` when
+    /// it is mutably borrowed.
+    fn children(&self) -> Option<Vec<Node>> {
+        let objects = |values: &mut dyn Iterator<Item = &Object>| -> Vec<Node> {
+            values.filter_map(Node::from_object).collect()
+        };
+
+        let children = match self {
+            Node::Environment(environment) => {
+                let environment = environment.try_borrow().ok()?;
+                let mut children = objects(&mut environment.values.iter());
+                if let Some(enclosing) = &environment.enclosing {
+                    children.push(Node::Environment(Rc::clone(enclosing)));
+                }
+                children
+            }
+            Node::Instance(instance) => {
+                let instance = instance.try_borrow().ok()?;
+                let mut children = objects(&mut instance.fields.values());
+                children.push(Node::Class(Rc::clone(&instance.class)));
+                children
+            }
+            Node::Function(function) => vec![Node::Environment(Rc::clone(&function.closure))],
+            Node::Class(class) => {
+                let mut children: Vec<Node> = class
+                    .methods
+                    .values()
+                    .map(|method| Node::Function(Rc::clone(method)))
+                    .collect();
+                if let Some(superclass) = &class.superclass {
+                    children.push(Node::Class(Rc::clone(superclass)));
+                }
+                children
+            }
+            Node::Closure(closure) => closure
+                .upvalues
+                .iter()
+                .map(|upvalue| Node::Upvalue(Rc::clone(upvalue)))
+                .collect(),
+            Node::Upvalue(upvalue) => match &*upvalue.try_borrow().ok()? {
+                Upvalue::Closed(value) => Node::from_object(value).into_iter().collect(),
+                Upvalue::Open(_) => Vec::new(),
+            },
+            Node::BoundMethod(method) => {
+                let mut children: Vec<Node> =
+                    Node::from_object(&method.receiver).into_iter().collect();
+                children.push(Node::Closure(Rc::clone(&method.method)));
+                children
+            }
+            Node::VmClass(class) => class
+                .methods
+                .try_borrow()
+                .ok()?
+                .values()
+                .map(|method| Node::Closure(Rc::clone(method)))
+                .collect(),
+            Node::VmInstance(instance) => {
+                let instance = instance.try_borrow().ok()?;
+                let mut children = objects(&mut instance.fields.values());
+                children.push(Node::VmClass(Rc::clone(&instance.class)));
+                children
+            }
+        };
+
+        Some(children)
+    }
+
+    /// Lets go of everything this node refers to.
+    fn clear(&self) {
+        match self {
+            Node::Environment(environment) => {
+                let mut environment = environment.borrow_mut();
+                environment.values.clear();
+                environment.enclosing = None;
+            }
+            Node::Instance(instance) => instance.borrow_mut().fields.clear(),
+            Node::Upvalue(upvalue) => *upvalue.borrow_mut() = Upvalue::Closed(Object::Nil),
+            Node::VmClass(class) => class.methods.borrow_mut().clear(),
+            Node::VmInstance(instance) => instance.borrow_mut().fields.clear(),
+            // These never change, so they can't be part of a cycle on their
+            // own. Clearing the tracked objects around them is enough.
+            Node::Function(_) | Node::Class(_) | Node::Closure(_) | Node::BoundMethod(_) => {}
+        }
+    }
+}
+
+/// Everything reachable from the tracked objects, with a single strong
+/// reference to each node so the counts stay predictable.
+struct Graph {
+    nodes: Vec<Node>,
+    edges: Vec<Vec<usize>>,
+    index: HashMap<usize, usize>,
+    // The tracked objects come first in `nodes`, and this many of them.
+    tracked: usize,
+}
+
+impl Graph {
+    fn build(tracked: &[Tracked]) -> Option<Graph> {
+        let mut graph = Graph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            index: HashMap::new(),
+            tracked: 0,
+        };
+
+        for node in tracked.iter().filter_map(Tracked::upgrade) {
+            graph.insert(node);
+        }
+        graph.tracked = graph.nodes.len();
+
+        while graph.edges.len() < graph.nodes.len() {
+            let children = graph.nodes[graph.edges.len()].children()?;
+            let edges = children
+                .into_iter()
+                .map(|child| graph.insert(child))
+                .collect();
+            graph.edges.push(edges);
+        }
+
+        Some(graph)
+    }
+
+    // Drops `node` if it is in the graph already.
+    fn insert(&mut self, node: Node) -> usize {
+        let address = node.address();
+
+        match self.index.get(&address) {
+            Some(&index) => index,
+            None => {
+                self.nodes.push(node);
+                self.index.insert(address, self.nodes.len() - 1);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    /// Marks what the roots lead to, clears the rest and returns how many
+    /// of the tracked objects that was. Untracked environments in the
+    /// garbage are emptied too, but not counted.
+    fn sweep(self) -> usize {
+        let mut internal = vec![0; self.nodes.len()];
+        for edges in &self.edges {
+            for &child in edges {
+                internal[child] += 1;
+            }
+        }
+
+        // The graph holds one reference to every node itself.
+        let mut marked: Vec<bool> = self
+            .nodes
+            .iter()
+            .zip(&internal)
+            .map(|(node, &internal)| node.strong_count() > internal + 1)
+            .collect();
+
+        let mut pending: Vec<usize> = (0..self.nodes.len()).filter(|&i| marked[i]).collect();
+        while let Some(node) = pending.pop() {
+            for &child in &self.edges[node] {
+                if !marked[child] {
+                    marked[child] = true;
+                    pending.push(child);
+                }
+            }
+        }
+
+        let mut freed = 0;
+        for (i, node) in self.nodes.iter().enumerate().filter(|&(i, _)| !marked[i]) {
+            node.clear();
+            if i < self.tracked {
+                freed += 1;
+            }
+        }
+
+        freed
+    }
+}
@@ -1,48 +1,80 @@
 use crate::environment::*;
 use crate::expression::Visitable;
 use crate::expression::*;
+use crate::gc::*;
 use crate::literal::*;
+use crate::lox_callable::*;
+use crate::lox_class::*;
 use crate::lox_error::*;
+use crate::lox_function::*;
+use crate::lox_instance::*;
+use crate::native_function::*;
 use crate::object::*;
+use crate::output::*;
 use crate::statement::Visitable as VisitableStatement;
 use crate::statement::*;
 use crate::token::*;
 use crate::token_type::*;
+use crate::vm::FRAMES_MAX;
 use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::rc::Rc;
 
+/// Scope depth and slot of every resolved local variable, keyed by
+/// expression id. Each script resolved gets a table of its own, shared by
+/// the functions it declares, so the table goes once the script has run
+/// and none of its functions are left.
+pub type Locals = Rc<HashMap<usize, (usize, usize)>>;
+
 pub struct Interpreter {
+    pub globals: Globals,
     pub environment: Rc<RefCell<Environment>>,
+    // Resolutions for the code running now: those of the last script
+    // resolved, or of the function being called.
+    locals: Locals,
+    // Receives everything `print` writes.
+    output: Box<dyn Output>,
+    // Calls to functions and classes that have not returned yet.
+    frames: usize,
+    // Tracks what either backend allocates so reference cycles get freed.
+    pub heap: Heap,
 }
 
 impl StmtVisitor for Interpreter {
-    type Value = Object;
+    type Value = Option<Object>;
+    type Error = LoxError;
     fn visit_block_statement(
         &mut self,
         _stmt: &Statement,
-        statements: &Vec<Box<Statement>>,
-    ) -> Option<Object> {
+        statements: &[Box<Statement>],
+    ) -> Result<Option<Object>, LoxError> {
         let env_ref = Rc::clone(&self.environment);
 
         self.execute_block(
             statements,
             Rc::new(RefCell::new(Environment::with_ref(env_ref))),
-        );
-        None
+        )
     }
     fn visit_expression_stmt(
         &mut self,
         _stmt: &Statement,
-        expression: &Box<Expr>,
-    ) -> Option<Object> {
-        self.evaluate(expression).ok();
-        None
+        expression: &Expr,
+    ) -> Result<Option<Object>, LoxError> {
+        self.evaluate(expression)?;
+        Ok(None)
     }
 
-    fn visit_print_stmt(&mut self, _stmt: &Statement, expression: &Box<Expr>) -> Option<Object> {
-        let value = self.evaluate(expression);
-        println!("{:?}", value);
-        None
+    fn visit_print_stmt(
+        &mut self,
+        _stmt: &Statement,
+        expression: &Expr,
+    ) -> Result<Option<Object>, LoxError> {
+        let value = self.evaluate(expression)?;
+        self.output
+            .write_out(&format!("{}\n", value))
+            .map_err(LoxError::IoError)?;
+        Ok(None)
     }
 
     fn visit_var_stmt(
@@ -50,127 +82,439 @@ impl StmtVisitor for Interpreter {
         _stmt: &Statement,
         name: &Token,
         initializer: &Option<Box<Expr>>,
-    ) -> Option<Object> {
-        let mut value = Object::Nil;
+    ) -> Result<Option<Object>, LoxError> {
+        let value = match initializer {
+            Some(initializer) => self.evaluate(initializer)?,
+            None => Object::Nil,
+        };
+
+        self.define(name, value);
+        Ok(None)
+    }
+
+    fn visit_if_statement(
+        &mut self,
+        _stmt: &Statement,
+        condition: &Expr,
+        then_branch: &Statement,
+        else_branch: &Option<Box<Statement>>,
+    ) -> Result<Option<Object>, LoxError> {
+        if self.evaluate(condition)?.is_truthy() {
+            self.execute(then_branch)
+        } else if let Some(else_branch) = else_branch {
+            self.execute(else_branch)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn visit_while_statement(
+        &mut self,
+        _stmt: &Statement,
+        condition: &Expr,
+        body: &Statement,
+    ) -> Result<Option<Object>, LoxError> {
+        while self.evaluate(condition)?.is_truthy() {
+            if let Some(value) = self.execute(body)? {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    fn visit_function_statement(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        params: &[Token],
+        body: &Rc<[Box<Statement>]>,
+    ) -> Result<Option<Object>, LoxError> {
+        self.heap.track_environment(&self.environment);
+        let function = LoxFunction::new(
+            name,
+            params,
+            body,
+            Rc::clone(&self.environment),
+            &self.locals,
+            false,
+        );
+
+        self.define(name, Object::Function(Rc::new(function)));
+        Ok(None)
+    }
+
+    /// Unwinds to the enclosing call by handing the value back up through
+    /// every statement being executed.
+    fn visit_return_statement(
+        &mut self,
+        _stmt: &Statement,
+        _keyword: &Token,
+        value: &Option<Box<Expr>>,
+    ) -> Result<Option<Object>, LoxError> {
+        let value = match value {
+            Some(value) => self.evaluate(value)?,
+            None => Object::Nil,
+        };
+
+        Ok(Some(value))
+    }
+
+    fn visit_class_statement(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        superclass: &Option<Box<Expr>>,
+        methods: &[Box<Statement>],
+    ) -> Result<Option<Object>, LoxError> {
+        let superclass = match superclass {
+            Some(superclass) => match self.evaluate(superclass)? {
+                Object::Class(class) => Some(class),
+                _ => {
+                    return Err(LoxError::RuntimeError(
+                        name.clone(),
+                        String::from("Superclass must be a class."),
+                    ))
+                }
+            },
+            None => None,
+        };
+
+        // Methods of a subclass close over an extra scope binding `super`.
+        let enclosing = Rc::clone(&self.environment);
+        if let Some(superclass) = &superclass {
+            let mut environment = Environment::with_ref(Rc::clone(&enclosing));
+            environment.define(Object::Class(Rc::clone(superclass)));
+            self.environment = Rc::new(RefCell::new(environment));
+        }
 
-        if let Some(initializer) = initializer {
-            if let Ok(initializer) = self.evaluate(initializer) {
-                value = initializer;
+        self.heap.track_environment(&self.environment);
+        let mut class_methods = HashMap::new();
+
+        for method in methods {
+            if let Statement::Function {
+                name, params, body, ..
+            } = method.as_ref()
+            {
+                let function = LoxFunction::new(
+                    name,
+                    params,
+                    body,
+                    Rc::clone(&self.environment),
+                    &self.locals,
+                    name.lexeme == "init",
+                );
+                class_methods.insert(name.lexeme.clone(), Rc::new(function));
             }
         }
-        self.environment.borrow_mut().define(&name.lexeme, &value);
-        None
+
+        self.environment = enclosing;
+
+        let class = LoxClass::new(&name.lexeme, superclass, class_methods);
+        self.define(name, Object::Class(Rc::new(class)));
+        Ok(None)
     }
 }
 
 impl ExprVisitor for Interpreter {
     type Value = Object;
+    type Error = LoxError;
+
+    /// Short-circuits, returning whichever operand decided the result.
+    fn visit_logical_expression(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<Self::Value, LoxError> {
+        let mut left = self.evaluate(left)?;
+
+        if operator.of_type == TokenType::OR {
+            if left.is_truthy() {
+                return Ok(left);
+            }
+        } else if !left.is_truthy() {
+            return Ok(left);
+        }
+
+        self.evaluate(right)
+    }
 
-    //fn visit_logical_expression(
-    //    &mut self,
-    //    _expr: &Expr,
-    //    left: &Box<Expr>,
-    //    operator: &Token,
-    //    right: &Box<Expr>,
-    //) -> Result<Self::Value, LoxError> {
-    //    let left_expr = self.evaluate(left).unwrap();
-    //    let is_truthy: Object = self.is_truthy(left_expr.clone());
-
-    //    if operator.of_type == TokenType::OR {
-    //        match is_truthy {
-    //            Object::Boolean(true) => Ok(left_expr),
-    //            _ => self.evaluate(right)
-    //        }
-    //    } else {
-    //        match is_truthy {
-    //            Object::Boolean(false) => Ok(left_expr),
-    //            _ => self.evaluate(right),
-    //        }
-    //    }
-    //}
     fn visit_assign_expression(
         &mut self,
+        id: usize,
         name: &Token,
-        value: &Box<Expr>,
+        value: &Expr,
     ) -> Result<Self::Value, LoxError> {
         let value = self.evaluate(value)?;
-        
-        self.environment.borrow_mut().assign(name, value.clone());
 
-        return Ok(value);
+        match self.locals.get(&id) {
+            Some(&(distance, slot)) => {
+                self.environment
+                    .borrow_mut()
+                    .assign_at(distance, slot, value.clone())
+            }
+            None => self.globals.assign(name, value.clone())?,
+        }
+
+        Ok(value)
     }
-    fn visit_variable_expression(&mut self, name: &Token) -> Result<Self::Value, LoxError> {
-        Ok(self.environment.borrow_mut().get(name.clone()))
+    fn visit_call_expression(
+        &mut self,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Box<Expr>],
+    ) -> Result<Self::Value, LoxError> {
+        let callee = self.evaluate(callee)?;
+
+        let mut values = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            values.push(self.evaluate(argument)?);
+        }
+
+        let function: Rc<dyn LoxCallable> = match callee {
+            Object::Function(function) => function,
+            Object::Class(class) => class,
+            Object::Native(native) => {
+                check_arity(paren, native.arity, values.len())?;
+                return native
+                    .call(self, &values)
+                    .map_err(|message| LoxError::RuntimeError(paren.clone(), message));
+            }
+            _ => {
+                return Err(LoxError::RuntimeError(
+                    paren.clone(),
+                    String::from("Can only call functions and classes."),
+                ))
+            }
+        };
+
+        check_arity(paren, function.arity(), values.len())?;
+
+        // Counting the script, like the VM does.
+        if self.frames + 1 == FRAMES_MAX {
+            return Err(LoxError::RuntimeError(
+                paren.clone(),
+                String::from("Stack overflow."),
+            ));
+        }
+
+        self.frames += 1;
+        let result = function.call(self, values);
+        self.frames -= 1;
+
+        result
+    }
+
+    fn visit_get_expression(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+    ) -> Result<Self::Value, LoxError> {
+        match self.evaluate(object)? {
+            Object::Instance(instance) => LoxInstance::get(&instance, name),
+            _ => Err(LoxError::RuntimeError(
+                name.clone(),
+                String::from("Only instances have properties."),
+            )),
+        }
+    }
+
+    fn visit_set_expression(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<Self::Value, LoxError> {
+        // Both sides are evaluated before the check, as the VM does.
+        let object = self.evaluate(object)?;
+        let value = self.evaluate(value)?;
+
+        let instance = match object {
+            Object::Instance(instance) => instance,
+            _ => {
+                return Err(LoxError::RuntimeError(
+                    name.clone(),
+                    String::from("Only instances have fields."),
+                ))
+            }
+        };
+
+        instance.borrow_mut().set(name, value.clone());
+        Ok(value)
+    }
+
+    fn visit_this_expression(
+        &mut self,
+        id: usize,
+        keyword: &Token,
+    ) -> Result<Self::Value, LoxError> {
+        self.look_up_variable(id, keyword)
+    }
+
+    /// Finds the method on the superclass of the class `super` appears in,
+    /// bound to the current `this`.
+    fn visit_super_expression(
+        &mut self,
+        id: usize,
+        _keyword: &Token,
+        method: &Token,
+    ) -> Result<Self::Value, LoxError> {
+        let (distance, slot) = *self
+            .locals
+            .get(&id)
+            .expect("'super' is always resolved to a local scope.");
+
+        let superclass = self.environment.borrow().get_at(distance, slot);
+        // `this` is alone in the scope just inside the one binding `super`.
+        let object = self.environment.borrow().get_at(distance - 1, 0);
+
+        let found = match (superclass, object) {
+            (Object::Class(superclass), Object::Instance(instance)) => superclass
+                .find_method(&method.lexeme)
+                .map(|found| found.bind(&instance)),
+            _ => None,
+        };
+
+        match found {
+            Some(found) => Ok(Object::Function(Rc::new(found))),
+            None => Err(LoxError::RuntimeError(
+                method.clone(),
+                format!("Undefined property '{}'.", method.lexeme),
+            )),
+        }
+    }
+
+    fn visit_variable_expression(
+        &mut self,
+        id: usize,
+        name: &Token,
+    ) -> Result<Self::Value, LoxError> {
+        self.look_up_variable(id, name)
     }
     fn visit_binary_expression(
         &mut self,
-        left: &Box<Expr>,
+        left: &Expr,
         operator: &Token,
-        right: &Box<Expr>,
+        right: &Expr,
     ) -> Result<Self::Value, LoxError> {
         let left = self.evaluate(left)?;
         let right = self.evaluate(right)?;
 
-        match operator.of_type {
-            TokenType::MINUS => Ok(left - right),
-            TokenType::SLASH => Ok(left / right),
-            TokenType::STAR => Ok(left * right),
-            TokenType::PLUS => left + right,
-            TokenType::GREATER => Ok(Object::Boolean(left > right)),
-            TokenType::GREATER_EQUAL => Ok(Object::Boolean(left >= right)),
-            TokenType::LESS => Ok(Object::Boolean(left < right)),
-            TokenType::LESS_EQUAL => Ok(Object::Boolean(left <= right)),
-            TokenType::EQUAL_EQUAL => Ok(Object::Boolean(left == right)),
-            TokenType::BANG_EQUAL => Ok(Object::Boolean(left != right)),
-            _ => unreachable!(),
-        }
+        binary_operation(left, operator.of_type, right)
+            .map_err(|message| LoxError::RuntimeError(operator.clone(), message))
     }
 
-    fn visit_group_expression(
-        &mut self,
-        content: &Box<Expr>,
-    ) -> Result<Self::Value, LoxError> {
+    fn visit_group_expression(&mut self, content: &Expr) -> Result<Self::Value, LoxError> {
         self.evaluate(content)
     }
 
-    fn visit_literal_expression(
-        &mut self,
-        literal: &Literal,
-    ) -> Result<Self::Value, LoxError> {
-        return Ok(Object::from_literal(literal));
+    fn visit_literal_expression(&mut self, literal: &Literal) -> Result<Self::Value, LoxError> {
+        Ok(Object::from_literal(literal))
     }
 
     fn visit_unary_expression(
         &mut self,
         operator: &Token,
-        right: &Box<Expr>,
+        right: &Expr,
     ) -> Result<Self::Value, LoxError> {
         let mut right = self.evaluate(right)?;
 
         match operator.of_type {
-            TokenType::MINUS => match right {
-                Object::Number(n) => Ok(Object::Number(-n)),
-                _ => unimplemented!(),
-            },
+            TokenType::MINUS => {
+                (-right).map_err(|message| LoxError::RuntimeError(operator.clone(), message))
+            }
             TokenType::BANG => Ok(Object::Boolean(!right.is_truthy())),
-            _ => unreachable!(),
+            other => Err(LoxError::RuntimeError(
+                operator.clone(),
+                format!("{:?} is not a unary operator.", other),
+            )),
         }
     }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        Interpreter {
+        Self::with_output(Box::new(StdOutput))
+    }
+
+    /// Creates an interpreter whose `print` statements write to `output`.
+    pub fn with_output(output: Box<dyn Output>) -> Self {
+        let mut interpreter = Interpreter {
+            globals: Globals::new(),
             environment: Rc::new(RefCell::new(Environment::new())),
+            locals: Locals::default(),
+            output,
+            frames: 0,
+            heap: Heap::new(),
+        };
+
+        for native in natives() {
+            interpreter.define_native(native);
         }
+
+        interpreter
+    }
+
+    /// Makes `native` callable from scripts as a global, replacing any
+    /// existing global of the same name.
+    pub fn define_native(&mut self, native: NativeFunction) {
+        let name = native.name;
+        self.globals
+            .define(name, &Object::Native(Rc::new(native)));
     }
-    pub fn evaluate(&mut self, expr: &Box<Expr>) -> Result<Object, LoxError> {
+
+    /// Throws away every global, resolved local and environment, leaving
+    /// the interpreter as `with_output` created it but for its collection
+    /// threshold.
+    pub fn reset(&mut self) {
+        let output = std::mem::replace(&mut self.output, Box::new(StdOutput));
+        let threshold = self.heap.threshold();
+
+        *self = Interpreter::with_output(output);
+        self.heap.set_threshold(threshold);
+    }
+
+    pub fn output(&mut self) -> &mut dyn Output {
+        self.output.as_mut()
+    }
+
+    /// Starts the table the resolver records the next script's variables
+    /// in. Functions declared earlier keep the table they were resolved in.
+    pub fn begin_resolution(&mut self) {
+        self.locals = Locals::default();
+    }
+
+    /// Records that the variable expression `id` refers to the binding in
+    /// `slot` of the scope `depth` scopes out from where it is used.
+    pub fn resolve(&mut self, id: usize, depth: usize, slot: usize) {
+        Rc::make_mut(&mut self.locals).insert(id, (depth, slot));
+    }
+
+    fn look_up_variable(&mut self, id: usize, name: &Token) -> Result<Object, LoxError> {
+        match self.locals.get(&id) {
+            Some(&(distance, slot)) => Ok(self.environment.borrow().get_at(distance, slot)),
+            None => self.globals.get(name),
+        }
+    }
+
+    /// Declares `name` in the current scope, which is the global one
+    /// outside of any block or function.
+    fn define(&mut self, name: &Token, value: Object) {
+        let mut environment = self.environment.borrow_mut();
+
+        if environment.is_global() {
+            self.globals.define(&name.lexeme, &value);
+        } else {
+            environment.define(value);
+        }
+    }
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<Object, LoxError> {
         expr.accept(self)
     }
 
-    pub fn interpret(&mut self, stmt: &Vec<Box<Statement>>) -> Result<(), LoxError> {
+    pub fn interpret(&mut self, stmt: &[Box<Statement>]) -> Result<(), LoxError> {
         for statement in stmt {
-            self.execute(statement);
+            self.execute(statement)?;
         }
 
         Ok(())
@@ -178,22 +522,108 @@ impl Interpreter {
     pub fn is_truthy(&mut self, result: Object) -> Object {
         match result {
             Object::Nil => Object::Boolean(false),
-            Object::Number(_) => Object::Boolean(true),
-            Object::Str(_) => Object::Boolean(true),
             Object::Boolean(value) => Object::Boolean(value),
+            _ => Object::Boolean(true),
         }
     }
-    pub fn execute(&mut self, stmt: &Box<Statement>) -> Option<Object> {
+    pub fn execute(&mut self, stmt: &Statement) -> Result<Option<Object>, LoxError> {
         stmt.accept(self)
     }
-    pub fn execute_block(&mut self, stmt: &Vec<Box<Statement>>, env: Rc<RefCell<Environment>>) {
-        let previous = Rc::clone(&self.environment);
-        self.environment = env;
 
+    /// Executes `stmt` inside `env`, restoring the current environment
+    /// afterwards even when a statement fails. Stops early with the value of
+    /// the first `return` reached.
+    pub fn execute_block(
+        &mut self,
+        stmt: &[Box<Statement>],
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Option<Object>, LoxError> {
+        let previous = std::mem::replace(&mut self.environment, env);
+
+        let mut result = Ok(None);
         for statement in stmt {
-            self.execute(statement);
+            result = self.execute(statement);
+
+            if !matches!(result, Ok(None)) {
+                break;
+            }
         }
 
         self.environment = previous;
+        result
+    }
+
+    /// Like `execute_block`, for the body of a function resolved in
+    /// `locals`.
+    pub fn execute_body(
+        &mut self,
+        body: &[Box<Statement>],
+        env: Rc<RefCell<Environment>>,
+        locals: &Locals,
+    ) -> Result<Option<Object>, LoxError> {
+        let previous = std::mem::replace(&mut self.locals, Rc::clone(locals));
+        let result = self.execute_block(body, env);
+
+        self.locals = previous;
+        result
+    }
+}
+
+/// Fails with the same message for user functions, classes and natives when
+/// a call passes the wrong number of arguments.
+fn check_arity(paren: &Token, arity: usize, count: usize) -> Result<(), LoxError> {
+    if count != arity {
+        return Err(LoxError::RuntimeError(
+            paren.clone(),
+            format!("Expected {} arguments but got {}.", arity, count),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Frees what scripts left in reference cycles, which would outlive the
+/// interpreter otherwise. Values a host still holds are kept.
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        self.globals.values.clear();
+        self.environment = Rc::new(RefCell::new(Environment::new()));
+        self.heap.collect();
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Applies a binary operator to two values, failing with the message to
+/// report when their types don't fit it.
+pub fn binary_operation(
+    left: Object,
+    operator: TokenType,
+    right: Object,
+) -> Result<Object, String> {
+    match operator {
+        TokenType::MINUS => left - right,
+        TokenType::SLASH => left / right,
+        TokenType::STAR => left * right,
+        TokenType::PLUS => left + right,
+        TokenType::GREATER => left
+            .compare(&right)
+            .map(|order| Object::Boolean(order == Some(Ordering::Greater))),
+        TokenType::GREATER_EQUAL => left.compare(&right).map(|order| {
+            Object::Boolean(matches!(order, Some(Ordering::Greater | Ordering::Equal)))
+        }),
+        TokenType::LESS => left
+            .compare(&right)
+            .map(|order| Object::Boolean(order == Some(Ordering::Less))),
+        TokenType::LESS_EQUAL => left.compare(&right).map(|order| {
+            Object::Boolean(matches!(order, Some(Ordering::Less | Ordering::Equal)))
+        }),
+        TokenType::EQUAL_EQUAL => Ok(Object::Boolean(left == right)),
+        TokenType::BANG_EQUAL => Ok(Object::Boolean(left != right)),
+        other => Err(format!("{:?} is not a binary operator.", other)),
     }
 }
@@ -0,0 +1,1772 @@
+//! A tree-walking interpreter for the Lox language.
+//!
+//! Grammar:
+//!
+//! ```text
+//! expression     → assignment ;
+//! assignment     → ( call "." )? IDENTIFIER "=" assignment
+//!                | logic_or ;
+//! logic_or       → logic_and ( "or" logic_and )* ;
+//! logic_and      → equality ( "and" equality )* ;
+//! equality       → comparison ( ( "!=" | "==" ) comparison )* ;
+//! comparison     → addition ( ( ">" | ">=" | "<" | "<=" ) addition )* ;
+//! addition       → multiplication ( ( "-" | "+" ) multiplication )* ;
+//! multiplication → unary ( ( "/" | "*" ) unary )* ;
+//! unary          → ( "!" | "-" ) unary
+//!                | call ;
+//! call           → primary ( "(" arguments? ")" | "." IDENTIFIER )* ;
+//! primary        → NUMBER | STRING | "false" | "true" | "nil" | "this"
+//!                | IDENTIFIER | "super" "." IDENTIFIER
+//!                | "(" expression ")" ;
+//! ```
+
+pub mod ast_cache;
+pub mod ast_printer;
+pub mod chunk;
+pub mod compiler;
+pub mod diagnostic;
+pub mod engine;
+pub mod environment;
+pub mod expression;
+pub mod gc;
+pub mod interpreter;
+pub mod json;
+pub mod literal;
+pub mod lox_callable;
+pub mod lox_class;
+pub mod lox_error;
+pub mod lox_function;
+pub mod lox_instance;
+pub mod native_function;
+pub mod object;
+pub mod optimizer;
+pub mod output;
+pub mod parser;
+pub mod repl;
+pub mod resolver;
+pub mod scanner;
+pub mod span;
+pub mod statement;
+pub mod syntax_json;
+pub mod token;
+pub mod token_type;
+pub mod vm;
+
+pub use engine::Engine;
+pub use lox_error::{LoxError, ParseError};
+pub use object::Object;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_cache::*;
+    use crate::ast_printer::*;
+    use crate::diagnostic::*;
+    use crate::engine::*;
+    use crate::environment::*;
+    use crate::interpreter::*;
+    use crate::json::*;
+    use crate::literal::*;
+    use crate::lox_error::*;
+    use crate::object::*;
+    use crate::optimizer::*;
+    use crate::output::*;
+    use crate::parser::*;
+    use crate::repl::*;
+    use crate::resolver::*;
+    use crate::scanner::*;
+    use crate::span::*;
+    use crate::statement::*;
+    use crate::syntax_json::*;
+    use crate::token::*;
+    use crate::token_type::*;
+    use std::any::Any;
+    use std::cell::RefCell;
+    use std::rc::{Rc, Weak};
+
+    #[test]
+    fn define_test() {
+        let mut globals = Globals::new();
+
+        let definitions = vec![
+            (
+                Token::new(TokenType::IDENTIFIER, String::from("a"), None, 1),
+                Object::Number(10.0),
+            ),
+            (
+                Token::new(TokenType::IDENTIFIER, String::from("b"), None, 1),
+                Object::Str(String::from("Hello world")),
+            ),
+            (
+                Token::new(TokenType::IDENTIFIER, String::from("c"), None, 1),
+                Object::Boolean(false),
+            ),
+        ];
+
+        for (name, value) in definitions {
+            globals.define(&name.lexeme, &value);
+            assert_eq!(globals.get(&name).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn scanner_test() {
+        let input = "(+);".to_string();
+
+        let mut scanner = Scanner::new(input);
+
+        scanner.scan_tokens();
+
+        assert_eq!(
+            scanner.tokens_helper(),
+            vec!(
+                Token {
+                    of_type: TokenType::LEFT_PAREN,
+                    lexeme: String::from("("),
+                    literal: None,
+                    span: Span::new(0, 1, 1, 1),
+                },
+                Token {
+                    of_type: TokenType::PLUS,
+                    lexeme: String::from("+"),
+                    literal: None,
+                    span: Span::new(1, 2, 1, 2),
+                },
+                Token {
+                    of_type: TokenType::RIGHT_PAREN,
+                    lexeme: String::from(")"),
+                    literal: None,
+                    span: Span::new(2, 3, 1, 3),
+                },
+                Token {
+                    of_type: TokenType::SEMICOLON,
+                    lexeme: String::from(";"),
+                    literal: None,
+                    span: Span::new(3, 4, 1, 4),
+                },
+                Token {
+                    of_type: TokenType::EOF,
+                    lexeme: String::from(""),
+                    literal: None,
+                    span: Span::new(4, 4, 1, 5),
+                },
+            )
+        );
+    }
+
+    #[test]
+    fn free_form_code_test() {
+        let input = "/".to_string();
+        let mut scanner = Scanner::new(input);
+
+        scanner.scan_tokens();
+
+        assert_eq!(
+            scanner.tokens_helper(),
+            vec!(
+                Token {
+                    of_type: TokenType::SLASH,
+                    lexeme: String::from("/"),
+                    literal: None,
+                    span: Span::new(0, 1, 1, 1),
+                },
+                Token {
+                    of_type: TokenType::EOF,
+                    lexeme: String::from(""),
+                    literal: None,
+                    span: Span::new(1, 1, 1, 2),
+                },
+            )
+        );
+    }
+
+    #[test]
+    fn number_parsing_test() {
+        let input = "1+2".to_string();
+        let mut scanner = Scanner::new(input);
+
+        scanner.scan_tokens();
+
+        assert_eq!(
+            scanner.tokens_helper(),
+            vec!(
+                Token {
+                    of_type: TokenType::NUMBER,
+                    lexeme: String::from("1"),
+                    literal: Some(Literal::Number(1.0)),
+                    span: Span::new(0, 1, 1, 1),
+                },
+                Token {
+                    of_type: TokenType::PLUS,
+                    lexeme: String::from("+"),
+                    literal: None,
+                    span: Span::new(1, 2, 1, 2),
+                },
+                Token {
+                    of_type: TokenType::NUMBER,
+                    lexeme: String::from("2"),
+                    literal: Some(Literal::Number(2.0)),
+                    span: Span::new(2, 3, 1, 3),
+                },
+                Token {
+                    of_type: TokenType::EOF,
+                    lexeme: String::from(""),
+                    literal: None,
+                    span: Span::new(3, 3, 1, 4),
+                },
+            )
+        );
+    }
+    #[test]
+    fn evaluation_test() {
+        let input = vec![("1+2*3", Object::Number(7.0))];
+
+        for (expression, expected_result) in input {
+            let mut scanner = Scanner::new(expression.to_string());
+            let tokens = scanner.scan_tokens();
+
+            let mut parser = Parser::new(tokens.to_vec());
+
+            let expr = parser.parse_expression().unwrap();
+
+            let mut interpreter = Interpreter::new();
+            let result = interpreter.evaluate(&expr).unwrap();
+
+            assert_eq!(result, expected_result);
+        }
+    }
+
+    #[test]
+    fn expected_keywords_test() {
+        let mut scanner = Scanner::new("and for if while".to_string());
+
+        let tokens = scanner.scan_tokens();
+        let token_types: Vec<TokenType> = tokens.iter().map(|t| t.of_type).collect();
+
+        assert_eq!(
+            token_types,
+            vec![
+                TokenType::AND,
+                TokenType::FOR,
+                TokenType::IF,
+                TokenType::WHILE,
+                TokenType::EOF
+            ]
+        );
+    }
+
+    #[test]
+    fn shebang_test() {
+        assert_eq!(skip_shebang("#!/usr/bin/env lox\nprint 1;"), "\nprint 1;");
+        assert_eq!(skip_shebang("#!/usr/bin/env lox"), "");
+        assert_eq!(skip_shebang("print 1;"), "print 1;");
+    }
+
+    /// Runs `source` on the tree walker, checking that the VM, which also
+    /// runs the optimizer, prints the same and leaves the same globals
+    /// behind.
+    fn interpret_source(source: &str) -> Interpreter {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens();
+        let (statements, errors) = Parser::new(tokens.to_vec()).parse();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let output = BufferOutput::new();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        Resolver::new(&mut interpreter).resolve(&statements).unwrap();
+        interpreter.interpret(&statements).unwrap();
+
+        let vm_output = BufferOutput::new();
+        let mut vm = Engine::with_output(vm_output.clone()).with_backend(Backend::Vm);
+        vm.run(source).unwrap();
+
+        let shown = |globals: Vec<(String, Object)>| -> Vec<(String, String)> {
+            globals
+                .into_iter()
+                .map(|(name, value)| (name, value.to_string()))
+                .collect()
+        };
+        let mut globals: Vec<(String, Object)> = interpreter
+            .globals
+            .values
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(vm_output.out(), output.out(), "{}", source);
+        // Two calls to clock() seldom agree.
+        if !source.contains("clock()") {
+            assert_eq!(shown(vm.globals()), shown(globals), "{}", source);
+        }
+        interpreter
+    }
+
+    fn global(interpreter: &Interpreter, name: &str) -> Object {
+        let token = Token::new(TokenType::IDENTIFIER, String::from(name), None, 1);
+        interpreter.globals.get(&token).unwrap()
+    }
+
+    #[test]
+    fn control_flow_test() {
+        let input = vec![
+            ("var a = 0; if (true) a = 1; else a = 2;", Object::Number(1.0)),
+            ("var a = 0; if (nil) a = 1; else a = 2;", Object::Number(2.0)),
+            ("var a = 0; if (0) a = 1;", Object::Number(1.0)),
+            ("var a = 0; while (a < 5) a = a + 1;", Object::Number(5.0)),
+            (
+                "var a = 0; for (var i = 0; i < 4; i = i + 1) a = a + i;",
+                Object::Number(6.0),
+            ),
+            (
+                "var a = 1; var i = 0; for (; i < 3;) { a = a * 2; i = i + 1; }",
+                Object::Number(8.0),
+            ),
+        ];
+
+        for (source, expected_result) in input {
+            let interpreter = interpret_source(source);
+
+            assert_eq!(global(&interpreter, "a"), expected_result);
+        }
+    }
+
+    #[test]
+    fn logical_expression_test() {
+        let input = vec![
+            ("nil or \"yes\"", Object::Str(String::from("yes"))),
+            ("1 or 2", Object::Number(1.0)),
+            ("false and 1", Object::Boolean(false)),
+            ("1 and nil", Object::Nil),
+            ("1 and 2 or 3", Object::Number(2.0)),
+            ("nil and 1 or 3", Object::Number(3.0)),
+            ("1 == 2 or 2 == 2", Object::Boolean(true)),
+        ];
+
+        for (expression, expected_result) in input {
+            let mut scanner = Scanner::new(expression.to_string());
+            let tokens = scanner.scan_tokens();
+            let expr = Parser::new(tokens.to_vec()).parse_expression().unwrap();
+
+            let result = Interpreter::new().evaluate(&expr).unwrap();
+
+            assert_eq!(result, expected_result);
+        }
+    }
+
+    #[test]
+    fn short_circuit_test() {
+        let interpreter = interpret_source("var a = 0; true or (a = 1); false and (a = 2);");
+
+        assert_eq!(global(&interpreter, "a"), Object::Number(0.0));
+    }
+
+    #[test]
+    fn identifier_starting_with_or_test() {
+        let mut scanner = Scanner::new("orange or".to_string());
+
+        let tokens = scanner.scan_tokens();
+        let token_types: Vec<TokenType> = tokens.iter().map(|t| t.of_type).collect();
+
+        assert_eq!(
+            token_types,
+            vec![TokenType::IDENTIFIER, TokenType::OR, TokenType::EOF]
+        );
+    }
+
+    #[test]
+    fn function_test() {
+        let input = vec![
+            ("fun add(a, b) { return a + b; } var a = add(1, 2);", Object::Number(3.0)),
+            ("fun f() { } var a = f();", Object::Nil),
+            (
+                "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } var a = fib(10);",
+                Object::Number(55.0),
+            ),
+            (
+                "fun f() { while (true) { return 1; } return 2; } var a = f();",
+                Object::Number(1.0),
+            ),
+            (
+                "fun counter() { var i = 0; fun inc() { i = i + 1; return i; } return inc; }
+                 var c = counter(); c(); var a = c();",
+                Object::Number(2.0),
+            ),
+        ];
+
+        for (source, expected_result) in input {
+            let interpreter = interpret_source(source);
+
+            assert_eq!(global(&interpreter, "a"), expected_result);
+        }
+
+        // Every function a declaration creates shares its body.
+        let interpreter =
+            interpret_source("fun make() { fun f() { print 1; } return f; } var a = make(); var b = make();");
+        match (global(&interpreter, "a"), global(&interpreter, "b")) {
+            (Object::Function(a), Object::Function(b)) => {
+                assert!(a != b && Rc::ptr_eq(&a.body, &b.body))
+            }
+            values => panic!("expected two functions, got {:?}", values),
+        }
+    }
+
+    #[test]
+    fn large_script_test() {
+        // More constants than fit in 16 bits, in a loop body too long for
+        // a 16-bit jump.
+        let count: u64 = 66_000;
+        let body: String = (1..=count).map(|i| format!("a = a + {};\n", i)).collect();
+        let source = format!("var a = 0;\nwhile (a == 0) {{\n{}}}\nprint a;", body);
+
+        for backend in &[Backend::TreeWalker, Backend::Vm] {
+            let output = BufferOutput::new();
+            let mut engine = Engine::with_output(output.clone()).with_backend(*backend);
+            engine.run(&source).unwrap();
+
+            assert_eq!(output.out(), format!("{}\n", count * (count + 1) / 2));
+        }
+
+        let locals: String = (0..256).map(|i| format!("var v{};", i)).collect();
+        let error = Engine::with_output(BufferOutput::new())
+            .with_backend(Backend::Vm)
+            .run(&format!("fun f() {{ {} }}", locals))
+            .unwrap_err();
+        match error {
+            LoxError::SyntaxErrors(errors) => {
+                let ParseError::SyntaxError(token, message) = &errors[0];
+                assert_eq!(message, "Too many local variables in function.");
+                assert_eq!((token.lexeme.as_str(), token.span.column), ("v255", 2200));
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn stack_overflow_test() {
+        // The tree walker recurses on the native stack, which needs to be
+        // far larger than a test thread's for a debug build.
+        let test = std::thread::Builder::new()
+            .stack_size(512 * 1024 * 1024)
+            .spawn(|| {
+                for backend in [Backend::TreeWalker, Backend::Vm] {
+                    let mut engine = Engine::with_output(BufferOutput::new()).with_backend(backend);
+                    engine
+                        .run("var depth = 0;\nfun f() { depth = depth + 1; f(); }")
+                        .unwrap();
+
+                    // Frames are given back when the error unwinds.
+                    for _ in 0..2 {
+                        match engine.run("depth = 0;\nf();") {
+                            Err(LoxError::RuntimeError(token, message)) => {
+                                assert_eq!(
+                                    (token.span.line, message.as_str()),
+                                    (2, "Stack overflow.")
+                                )
+                            }
+                            result => panic!("unbounded recursion ran to {:?}", result),
+                        }
+                        assert_eq!(
+                            engine.get_global("depth"),
+                            Some(Object::Number(4095.0)),
+                            "{:?}",
+                            backend
+                        );
+                    }
+                }
+            })
+            .unwrap();
+
+        test.join().unwrap();
+    }
+
+    #[test]
+    fn call_error_test() {
+        let input = vec![
+            ("fun f(a) {}\nf(1, 2);", ""),
+            ("var a = 1;\na();", ""),
+            ("print 1;\nclock(1);", "1\n"),
+            ("print 1;\nlen(1);", "1\n"),
+            ("print 1;\nnum(true);", "1\n"),
+        ];
+
+        for (source, printed) in input {
+            let mut scanner = Scanner::new(source.to_string());
+            let tokens = scanner.scan_tokens();
+            let (statements, errors) = Parser::new(tokens.to_vec()).parse();
+            assert!(errors.is_empty(), "{:?}", errors);
+
+            let output = BufferOutput::new();
+            let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+            let error = interpreter.interpret(&statements).unwrap_err();
+
+            assert!(error.to_string().contains("[line 2]"));
+            assert_eq!(output.out(), printed, "{}", source);
+        }
+    }
+
+    #[test]
+    fn resolved_closure_test() {
+        let interpreter = interpret_source(
+            "var a = \"global\"; var first; var second;
+             {
+                 fun show() { return a; }
+                 first = show();
+                 var a = \"block\";
+                 second = show();
+             }",
+        );
+
+        assert_eq!(global(&interpreter, "first"), Object::Str(String::from("global")));
+        assert_eq!(global(&interpreter, "second"), Object::Str(String::from("global")));
+    }
+
+    #[test]
+    fn local_slots_test() {
+        let interpreter = interpret_source(
+            "var result;
+             {
+                 var a = 1;
+                 var b = 2;
+                 {
+                     var a = 10;
+                     fun add(x) { b = b + x; return a + b; }
+                     class Box { init(v) { this.v = v; } get() { return Box; } }
+                     result = add(Box(3).v) + a;
+                 }
+                 result = result + a * 100 + b * 1000;
+             }",
+        );
+
+        assert_eq!(global(&interpreter, "result"), Object::Number(5125.0));
+
+        let outer = Rc::new(RefCell::new(Environment::new()));
+        let mut inner = Environment::with_ref(Rc::clone(&outer));
+        assert!(outer.borrow().is_global() && !inner.is_global());
+
+        outer.borrow_mut().define(Object::Number(1.0));
+        inner.define(Object::Nil);
+        inner.assign_at(1, 0, Object::Number(2.0));
+
+        assert_eq!(inner.get_at(1, 0), Object::Number(2.0));
+        assert_eq!(inner.get_at(0, 0), Object::Nil);
+    }
+
+    #[test]
+    fn resolver_error_test() {
+        let input = vec![
+            "{ var a = 1; var a = 2; }",
+            "{ var a = a; }",
+            "fun f(a, a) {}",
+            "return 1;",
+            "print this;",
+            "class A { init() { return 1; } }",
+            "class A < A {}",
+            "class A { f() { return super.f(); } }",
+            "fun f() { return super.f(); }",
+        ];
+
+        for source in input {
+            let mut scanner = Scanner::new(source.to_string());
+            let tokens = scanner.scan_tokens();
+            let (statements, errors) = Parser::new(tokens.to_vec()).parse();
+            assert!(errors.is_empty(), "{:?}", errors);
+
+            let mut interpreter = Interpreter::new();
+            let result = Resolver::new(&mut interpreter).resolve(&statements);
+
+            assert!(matches!(result, Err(LoxError::BindingError(_, _))), "{}", source);
+        }
+    }
+
+    #[test]
+    fn class_test() {
+        let input = vec![
+            (
+                "class Point { init(x, y) { this.x = x; this.y = y; } sum() { return this.x + this.y; } }
+                 var a = Point(1, 2).sum();",
+                Object::Number(3.0),
+            ),
+            (
+                "class Box {} var b = Box(); b.value = 4; var a = b.value;",
+                Object::Number(4.0),
+            ),
+            (
+                "class Counter { init() { this.n = 0; } inc() { this.n = this.n + 1; return this; } }
+                 var a = Counter().inc().inc().n;",
+                Object::Number(2.0),
+            ),
+            (
+                "class Cake { taste() { return this.flavor; } } var c = Cake(); c.flavor = \"lemon\";
+                 var taste = c.taste; var a = taste();",
+                Object::Str(String::from("lemon")),
+            ),
+        ];
+
+        for (source, expected_result) in input {
+            let interpreter = interpret_source(source);
+
+            assert_eq!(global(&interpreter, "a"), expected_result);
+        }
+    }
+
+    #[test]
+    fn initializer_returns_instance_test() {
+        let interpreter = interpret_source(
+            "class Greeter { init() { this.greeting = 1; return; } } var g = Greeter(); var a = g.init();",
+        );
+
+        assert_eq!(global(&interpreter, "a"), global(&interpreter, "g"));
+    }
+
+    #[test]
+    fn inheritance_test() {
+        let input = vec![
+            (
+                "class A { name() { return \"A\"; } } class B < A {} var a = B().name();",
+                Object::Str(String::from("A")),
+            ),
+            (
+                "class A { value() { return 1; } }
+                 class B < A { value() { return super.value() + 10; } }
+                 class C < B { value() { return super.value() + 100; } }
+                 var a = C().value();",
+                Object::Number(111.0),
+            ),
+            (
+                "class A { init(x) { this.x = x; } }
+                 class B < A { init(x) { super.init(x + 1); } }
+                 var a = B(1).x;",
+                Object::Number(2.0),
+            ),
+            (
+                "class A { who() { return this.name; } }
+                 class B < A { who() { var f = super.who; return f(); } }
+                 var b = B(); b.name = \"b\"; var a = b.who();",
+                Object::Str(String::from("b")),
+            ),
+        ];
+
+        for (source, expected_result) in input {
+            let interpreter = interpret_source(source);
+
+            assert_eq!(global(&interpreter, "a"), expected_result);
+        }
+    }
+
+    #[test]
+    fn inherit_from_non_class_test() {
+        let mut scanner = Scanner::new("var A = 1;\nclass B < A {}".to_string());
+        let tokens = scanner.scan_tokens();
+        let (statements, errors) = Parser::new(tokens.to_vec()).parse();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let mut interpreter = Interpreter::new();
+        Resolver::new(&mut interpreter).resolve(&statements).unwrap();
+        let error = interpreter.interpret(&statements).unwrap_err();
+
+        assert!(error.to_string().contains("Superclass must be a class."));
+    }
+
+    #[test]
+    fn floating_point_test() {
+        let input = vec![
+            ("1.5 + 2.25", Object::Number(3.75)),
+            ("7 / 2", Object::Number(3.5)),
+            ("0.1 * 3 > 0.3", Object::Boolean(true)),
+            ("1 / 0", Object::Number(f64::INFINITY)),
+            ("-1 / 0", Object::Number(f64::NEG_INFINITY)),
+        ];
+
+        for (expression, expected_result) in input {
+            let mut scanner = Scanner::new(expression.to_string());
+            let tokens = scanner.scan_tokens();
+            let expr = Parser::new(tokens.to_vec()).parse_expression().unwrap();
+
+            let result = Interpreter::new().evaluate(&expr).unwrap();
+
+            assert_eq!(result, expected_result);
+        }
+    }
+
+    #[test]
+    fn number_display_test() {
+        let input = vec![
+            (3.0, "3"),
+            (-2.5, "-2.5"),
+            (0.1, "0.1"),
+            (f64::NAN, "NaN"),
+            (f64::INFINITY, "inf"),
+            (f64::NEG_INFINITY, "-inf"),
+        ];
+
+        for (number, expected) in input {
+            assert_eq!(Object::Number(number).to_string(), expected);
+            assert_eq!(Literal::Number(number).to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn runtime_type_error_test() {
+        let input = vec![
+            ("1 - \"a\"", "-", "Operands must be numbers."),
+            ("true / 2", "/", "Operands must be numbers."),
+            ("nil * 2", "*", "Operands must be numbers."),
+            ("1 + \"a\"", "+", "Operands must be two numbers or two strings."),
+            ("\"a\" + nil", "+", "Operands must be two numbers or two strings."),
+            ("\"a\" < \"b\"", "<", "Operands must be numbers."),
+            ("-\"a\"", "-", "Operand must be a number."),
+            ("undefined", "undefined", "Undefined variable 'undefined'."),
+            ("undefined = 1", "undefined", "Undefined variable 'undefined'."),
+        ];
+
+        for (expression, expected_lexeme, expected_message) in input {
+            let mut scanner = Scanner::new(expression.to_string());
+            let tokens = scanner.scan_tokens();
+            let expr = Parser::new(tokens.to_vec()).parse_expression().unwrap();
+
+            match Interpreter::new().evaluate(&expr) {
+                Err(LoxError::RuntimeError(token, message)) => {
+                    assert_eq!(message, expected_message);
+                    assert_eq!(token.lexeme, expected_lexeme);
+                }
+                result => panic!("{} evaluated to {:?}", expression, result),
+            }
+        }
+    }
+
+    #[test]
+    fn runtime_error_does_not_abort_session_test() {
+        let mut engine = Engine::new();
+
+        assert!(matches!(
+            engine.run("var a = 1 + nil;"),
+            Err(LoxError::RuntimeError(_, _))
+        ));
+        engine.run("var b = 2;").unwrap();
+
+        assert_eq!(engine.get_global("b"), Some(Object::Number(2.0)));
+    }
+
+    #[test]
+    fn token_span_test() {
+        let mut scanner = Scanner::new("var a;\n  print 12.5 + a;".to_string());
+        let tokens = scanner.scan_tokens();
+
+        let input = vec![
+            (0, Span::new(0, 3, 1, 1)),
+            (1, Span::new(4, 5, 1, 5)),
+            (3, Span::new(9, 14, 2, 3)),
+            (4, Span::new(15, 19, 2, 9)),
+            (5, Span::new(20, 21, 2, 14)),
+        ];
+
+        for (index, expected_span) in input {
+            assert_eq!(tokens[index].span, expected_span);
+        }
+    }
+
+    #[test]
+    fn unicode_source_test() {
+        let source = "var café = 1; // ½\nprint café + \"naïve ☕\";";
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().clone();
+
+        assert_eq!(tokens[1].lexeme, "café");
+        assert_eq!(tokens[1].span, Span::new(4, 9, 1, 5));
+        assert_eq!(tokens[4].span, Span::new(13, 14, 1, 13));
+        assert_eq!(tokens[6].span, Span::new(27, 32, 2, 7));
+        assert_eq!(tokens[7].span, Span::new(33, 34, 2, 12));
+        assert_eq!(
+            tokens[8].literal,
+            Some(Literal::String(String::from("naïve ☕")))
+        );
+        for token in &tokens {
+            assert_eq!(&source[token.span.start..token.span.end], token.lexeme);
+        }
+
+        let interpreter =
+            interpret_source("var π = 3; var 名前 = \"ünï\" + \"cödé\"; var _ß2 = π;");
+        assert_eq!(global(&interpreter, "名前"), Object::from("ünïcödé"));
+        assert_eq!(global(&interpreter, "_ß2"), Object::Number(3.0));
+
+        let mut engine = Engine::with_output(BufferOutput::new());
+        let error = engine.run(source).unwrap_err();
+        assert_eq!(
+            render_error(&error, "test.lox", source, false),
+            "runtime error: Operands must be two numbers or two strings.\n \
+             --> test.lox:2:12\n  \
+             |\n\
+             2 | print café + \"naïve ☕\";\n  \
+             |            ^\n"
+        );
+    }
+
+    #[test]
+    fn string_escape_test() {
+        let interpreter = interpret_source(
+            "var a = \"tab\\there\\n\\\"q\\\" \\\\ \\u{1F600}\\u{e9}\\0\";
+             var b = r\"C:\\dir\\n\";
+             var c = \"one
+two\";",
+        );
+
+        assert_eq!(
+            global(&interpreter, "a"),
+            Object::from("tab\there\n\"q\" \\ \u{1F600}\u{e9}\0")
+        );
+        assert_eq!(global(&interpreter, "b"), Object::from("C:\\dir\\n"));
+        assert_eq!(global(&interpreter, "c"), Object::from("one\ntwo"));
+
+        // Lines inside strings are counted like any other.
+        let mut scanner = Scanner::new("\"one\ntwo\" x\nr\"\n\" y".to_string());
+        let tokens = scanner.scan_tokens();
+        assert_eq!(tokens[1].span, Span::new(10, 11, 2, 6));
+        assert_eq!(tokens[3].span, Span::new(17, 18, 4, 3));
+
+        let statements = parse("print \"a\\\"b\\n\\u{7f}\";").unwrap();
+        assert_eq!(
+            AstPrinter::new(Layout::Lisp).print_statements(&statements),
+            "(print \"a\\\"b\\n\\u{7f}\")\n"
+        );
+
+        let input = vec![
+            ("print \"a\\qb\";", "\\q", 9, "Invalid escape sequence."),
+            (
+                "print \"\\u{110000}\";",
+                "\\u{110000}",
+                8,
+                "Invalid Unicode escape, not a Unicode character.",
+            ),
+            (
+                "print \"\\u{12\";",
+                "\\u{12",
+                8,
+                "Invalid Unicode escape, expected 1 to 6 hex digits in '\\u{...}'.",
+            ),
+            ("print 1;\nprint \"abc;", "\"", 7, "Unterminated string."),
+            ("print r\"abc;", "\"", 8, "Unterminated string."),
+        ];
+
+        for (source, lexeme, column, message) in input {
+            match parse(source) {
+                Err(LoxError::SyntaxErrors(errors)) => {
+                    let ParseError::SyntaxError(token, error) = &errors[0];
+                    assert_eq!(
+                        (token.lexeme.as_str(), token.span.column, error.as_str()),
+                        (lexeme, column, message),
+                        "{}",
+                        source
+                    );
+                }
+                result => panic!("{} parsed to {:?}", source, result.err()),
+            }
+        }
+    }
+
+    #[test]
+    fn expression_span_test() {
+        let mut scanner = Scanner::new("(1 + 2) * foo(3)".to_string());
+        let tokens = scanner.scan_tokens();
+        let expr = Parser::new(tokens.to_vec()).parse_expression().unwrap();
+
+        assert_eq!(expr.span(), Span::new(0, 16, 1, 1));
+    }
+
+    #[test]
+    fn statement_span_test() {
+        let statements = parse("print 1;\n{}\nclass A { m() {} }").unwrap();
+
+        assert_eq!(statements[0].span(), Span::new(0, 8, 1, 1));
+        // Even a block with nothing in it covers its braces.
+        assert_eq!(statements[1].span(), Span::new(9, 11, 2, 1));
+        assert_eq!(statements[2].span(), Span::new(12, 30, 3, 1));
+        match statements[2].as_ref() {
+            Statement::Class { methods, .. } => {
+                assert_eq!(methods[0].span(), Span::new(22, 28, 3, 11))
+            }
+            _ => panic!("expected a class"),
+        }
+
+        assert!(JsonWriter::statement(&statements[1])
+            .to_string()
+            .contains("\"span\":{\"start\":9,\"end\":11,\"line\":2,\"column\":1}"));
+    }
+
+    #[test]
+    fn diagnostic_render_test() {
+        let source = "var a = 1;\nprint a +;";
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens();
+        let (_, errors) = Parser::new(tokens.to_vec()).parse();
+
+        assert_eq!(
+            Diagnostic::from(&errors[0]).render("test.lox", source, false),
+            "syntax error: Expected expression.\n \
+             --> test.lox:2:10\n  \
+             |\n\
+             2 | print a +;\n  \
+             |          ^\n"
+        );
+
+        // The caret is indented with the line's own tabs.
+        let source = "var a =\t\t@;";
+        match parse(source) {
+            Err(LoxError::SyntaxErrors(errors)) => assert_eq!(
+                Diagnostic::from(&errors[0]).render("test.lox", source, false),
+                "syntax error: Unexpected character.\n \
+                 --> test.lox:1:10\n  \
+                 |\n\
+                 1 | var a =\t\t@;\n  \
+                 |        \t\t^\n"
+            ),
+            result => panic!("expected syntax errors, got {:?}", result.err()),
+        }
+    }
+
+    #[test]
+    fn multiple_syntax_errors_test() {
+        let source = "var a = ;\nprint 1;\nfun f( {}\n{ print 2 }\nprint 3;";
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens();
+        let (statements, errors) = Parser::new(tokens.to_vec()).parse();
+
+        let lines: Vec<usize> = errors
+            .iter()
+            .map(|ParseError::SyntaxError(token, _)| token.span.line)
+            .collect();
+
+        assert_eq!(lines, vec![1, 3, 4]);
+        assert_eq!(statements.len(), 2);
+
+        // Stray characters are reported in order with the parser's errors.
+        match parse("print 1 @ 2;\nvar # = 3;") {
+            Err(LoxError::SyntaxErrors(errors)) => {
+                let errors: Vec<(&str, usize, usize, &str)> = errors
+                    .iter()
+                    .map(|ParseError::SyntaxError(token, message)| {
+                        (token.lexeme.as_str(), token.span.line, token.span.column, message.as_str())
+                    })
+                    .collect();
+                assert_eq!(
+                    errors,
+                    vec![
+                        ("@", 1, 9, "Unexpected character."),
+                        ("2", 1, 11, "Expect ';' after value."),
+                        ("#", 2, 5, "Unexpected character."),
+                        ("=", 2, 7, "Expect variable name."),
+                    ]
+                );
+            }
+            result => panic!("expected syntax errors, got {:?}", result.err()),
+        }
+    }
+
+    #[test]
+    fn native_function_test() {
+        let interpreter = interpret_source(
+            "var now = clock();
+             var t1 = type(nil); var t2 = type(1); var t3 = type(\"a\");
+             var t4 = type(clock); var t5 = type(true);
+             var s = str(2.5);
+             var n = num(\" 42 \");
+             var l = len(\"hello\");",
+        );
+
+        assert!(matches!(global(&interpreter, "now"), Object::Number(x) if x > 0.0));
+        assert_eq!(global(&interpreter, "t1"), Object::Str(String::from("nil")));
+        assert_eq!(global(&interpreter, "t2"), Object::Str(String::from("number")));
+        assert_eq!(global(&interpreter, "t3"), Object::Str(String::from("string")));
+        assert_eq!(global(&interpreter, "t4"), Object::Str(String::from("function")));
+        assert_eq!(global(&interpreter, "t5"), Object::Str(String::from("boolean")));
+        assert_eq!(global(&interpreter, "s"), Object::Str(String::from("2.5")));
+        assert_eq!(global(&interpreter, "n"), Object::Number(42.0));
+        assert_eq!(global(&interpreter, "l"), Object::Number(5.0));
+        assert_eq!(global(&interpreter, "clock").to_string(), "<native fn clock>");
+    }
+
+    #[test]
+    fn resolutions_per_script_test() {
+        let output = BufferOutput::new();
+        let mut engine = Engine::with_output(output.clone());
+        engine
+            .run(
+                "fun make() { var n = 0; fun next() { n = n + 1; return n; } return next; }
+                  var next = make();",
+            )
+            .unwrap();
+        for _ in 0..100 {
+            engine.run("{ var x = 1; print x + next(); }").unwrap();
+        }
+        engine.run("fun last() { var y = 1; return y; }").unwrap();
+
+        assert!(output.out().ends_with("100\n101\n"));
+        // Each function only keeps what was resolved along with it.
+        let resolved = |name: &str| match engine.get_global(name) {
+            Some(Object::Function(function)) => function.locals.len(),
+            other => panic!("{:?} is not a function", other),
+        };
+        assert_eq!((resolved("next"), resolved("last")), (4, 1));
+    }
+
+    #[test]
+    fn garbage_collection_test() {
+        let watch = |object: Option<Object>| -> Weak<dyn Any> {
+            let object: Rc<dyn Any> = match object {
+                Some(Object::Instance(instance)) => instance,
+                Some(Object::VmInstance(instance)) => instance,
+                Some(Object::Function(function)) => function,
+                Some(Object::Closure(closure)) => closure,
+                other => panic!("{:?} is not on the heap", other),
+            };
+            Rc::downgrade(&object)
+        };
+        let cycles =
+            "class Node { init() { this.self = this; this.get = this.method; } method() {} }
+                      fun make() { var f; fun g() { return f; } f = g; return g; }
+                      var node = Node();
+                      var f = make();";
+
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let mut engine = Engine::new().with_backend(backend);
+            engine.run(cycles).unwrap();
+            let node = watch(engine.get_global("node"));
+            let f = watch(engine.get_global("f"));
+
+            assert_eq!(engine.collect_garbage(), 0, "{:?}", backend);
+            engine.run("node = nil; f = nil;").unwrap();
+            assert!(node.upgrade().is_some() && f.upgrade().is_some());
+            // The instance, and the scope or upvalue `g` closes over.
+            assert_eq!(engine.collect_garbage(), 2, "{:?}", backend);
+            assert!(
+                node.upgrade().is_none() && f.upgrade().is_none(),
+                "{:?}",
+                backend
+            );
+
+            // Values a host holds on to are roots.
+            engine
+                .run(
+                    "class Named { init() { this.self = this; this.name = \"kept\"; } }
+                      var named = Named();",
+                )
+                .unwrap();
+            let named = engine.get_global("named").unwrap();
+            engine.run("named = nil;").unwrap();
+            engine.collect_garbage();
+            assert_eq!(named.to_string(), "Named instance");
+            let name = match &named {
+                Object::Instance(instance) => instance.borrow().fields["name"].clone(),
+                Object::VmInstance(instance) => instance.borrow().fields["name"].clone(),
+                _ => unreachable!(),
+            };
+            assert_eq!(name, Object::from("kept"));
+
+            // So is what only a live closure's scope, or a scope still
+            // running, refers to.
+            let output = BufferOutput::new();
+            let mut engine = Engine::with_output(output.clone()).with_backend(backend);
+            engine
+                .run(
+                    "class Box { init(name) { this.name = name; this.self = this; } }
+                     fun make() { var kept = Box(\"captured\"); fun get() { return kept; } return get; }
+                     var get = make();
+                     fun run() { var local = Box(\"running\"); gc(); return local.self.name; }
+                     gc();
+                     print get().self.name;
+                     print run();",
+                )
+                .unwrap();
+            assert_eq!(output.out(), "captured\nrunning\n", "{:?}", backend);
+
+            // Collecting all the time doesn't change what scripts do.
+            let output = BufferOutput::new();
+            let mut engine = Engine::with_output(output.clone())
+                .with_backend(backend)
+                .with_gc_threshold(1);
+            engine
+                .run(
+                    "fun counter() { var count = 0; fun next() { count = count + 1; return count; } return next; }
+                     class A { init(n) { this.n = n; this.self = this; } get() { return this.n; } }
+                     class B < A { get() { return super.get() * 2; } }
+                     var total = 0;
+                     for (var i = 0; i < 50; i = i + 1) {
+                         var next = counter();
+                         next();
+                         var b = B(i);
+                         b.again = b.get;
+                         total = total + next() + b.again();
+                     }
+                     print total;",
+                )
+                .unwrap();
+            assert_eq!(output.out(), "2550\n");
+            assert!(matches!(engine.eval("gc();"), Ok(Some(Object::Number(_)))));
+            let stats = engine.gc_stats();
+            assert!(stats.collections > 10 && stats.freed > 0, "{:?}", stats);
+
+            // Nothing outlives the interpreter.
+            engine.run(cycles).unwrap();
+            let node = watch(engine.get_global("node"));
+            engine.reset();
+            assert!(node.upgrade().is_none(), "{:?}", backend);
+            engine.run(cycles).unwrap();
+            let f = watch(engine.get_global("f"));
+            drop(engine);
+            assert!(f.upgrade().is_none(), "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn engine_test() {
+        let mut engine = Engine::new();
+        engine.set_global("width", 3.0);
+        engine.set_global("name", "box");
+
+        engine
+            .run("var area = width * width; var label = name + \"!\";")
+            .unwrap();
+
+        assert_eq!(engine.get_global("area").and_then(|v| v.as_number()), Some(9.0));
+        assert_eq!(
+            engine.get_global("label").as_ref().and_then(|v| v.as_str()),
+            Some("box!")
+        );
+        assert_eq!(engine.get_global("missing"), None);
+        assert_eq!(engine.eval("area + 1;").unwrap(), Some(Object::Number(10.0)));
+        assert_eq!(engine.eval("var x = 1;").unwrap(), None);
+
+        match engine.run("var = 1;\nprint ;") {
+            Err(LoxError::SyntaxErrors(errors)) => assert_eq!(errors.len(), 2),
+            result => panic!("expected syntax errors, got {:?}", result),
+        }
+
+        assert!(matches!(
+            engine.run_file("does/not/exist.lox"),
+            Err(LoxError::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn print_output_test() {
+        let input = vec![
+            ("print nil;", "nil\n"),
+            ("print true; print false;", "true\nfalse\n"),
+            ("print 3; print 2.5; print -0.5;", "3\n2.5\n-0.5\n"),
+            ("print 1 / 0;", "inf\n"),
+            ("print \"a\" + \"b\";", "ab\n"),
+            ("fun f() {} print f; print clock;", "<fn f>\n<native fn clock>\n"),
+            ("class A {} print A; print A();", "A\nA instance\n"),
+        ];
+
+        for (source, expected_output) in input {
+            let output = BufferOutput::new();
+            let mut engine = Engine::with_output(output.clone());
+            engine.run(source).unwrap();
+
+            assert_eq!(output.out(), expected_output, "{}", source);
+            assert_eq!(output.err(), "");
+        }
+    }
+
+    fn run_repl(input: &str) -> BufferOutput {
+        let output = BufferOutput::new();
+        let mut repl = Repl::new(Engine::with_output(output.clone()));
+        repl.run(input.as_bytes()).unwrap();
+        output
+    }
+
+    #[test]
+    fn repl_test() {
+        let input = vec![
+            ("1 + 2;\n", "> 3\n> \n"),
+            ("fun f(a) {\n  return a * 2;\n}\nf(4);\n", "> ... ... > 8\n> \n"),
+            ("print \"a\n b\";\n", "> ... a\n b\n> \n"),
+            ("var a = 1;\n:reset\n:env\n", "> > > clock = <native fn clock>\ngc = <native fn gc>\nlen = <native fn len>\nnum = <native fn num>\nstr = <native fn str>\ntype = <native fn type>\n> \n"),
+            (":tokens var a\n", "> 1:1 VAR var\n1:5 IDENTIFIER a\n1:6 EOF \n> \n"),
+            (":ast print 1 + 2;\n", "> (print (+ 1 2))\n> \n"),
+            (":tree -a;\n", "> ;\n  -\n    a\n> \n"),
+            (":ast 1 + 2 * 3\n", "> (+ 1 (* 2 3))\n> \n"),
+            (":tree -a\n", "> -\n  a\n> \n"),
+            (":quit\nprint 1;\n", "> "),
+            ("{\n", "> ... \n"),
+        ];
+
+        for (input, expected_output) in input {
+            assert_eq!(run_repl(input).out(), expected_output, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn repl_error_test() {
+        let output = run_repl("print x;\n:nope\n:load does/not/exist.lox\n:ast 1 2\nprint 1;\n");
+
+        assert!(output.err().contains("Undefined variable 'x'."));
+        assert!(output.err().contains("Expect ';' after expression"));
+        assert!(output.err().contains("Unknown command ':nope'."));
+        assert!(output.err().contains("Error reading does/not/exist.lox"));
+        assert!(output.out().ends_with("1\n> \n"));
+    }
+
+    #[test]
+    fn incomplete_input_test() {
+        let input = vec![
+            ("print 1;", false),
+            ("fun f() {", true),
+            ("fun f() { }", false),
+            ("print (1 +", true),
+            ("print \"abc", true),
+            ("print \"(\";", false),
+            ("print \"\\\"", true),
+            ("print \"\\\\\";", false),
+            ("print r\"\\\";", false),
+            ("print bar\"\\\";", true),
+            ("print _r\"\\\";", true),
+            ("print 1r\"\\\";", false),
+            ("// {", false),
+            ("{ // }", true),
+            ("}", false),
+        ];
+
+        for (source, expected) in input {
+            assert_eq!(is_incomplete(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn ast_printer_test() {
+        let input = vec![
+            ("-123 * (45.67);", "(; (* (- 123) (group 45.67)))"),
+            ("1 < 2 == 3 >= 4;", "(; (== (< 1 2) (>= 3 4)))"),
+            ("a = b or c and !d;", "(; (= a (or b (and c (! d)))))"),
+            ("print f(1, \"x\").y;", "(print (. (call f 1 \"x\") y))"),
+            ("a.b = nil;", "(; (= (. a b) nil))"),
+            ("var a; var b = true;", "(var a)\n(var b true)"),
+            ("if (a) { return; } else return 1;", "(if a (block return) (return 1))"),
+            ("while (x) x = x - 1;", "(while x (; (= x (- x 1))))"),
+            ("fun f() {} fun g(a, b) { print a; }", "(fun f params)\n(fun g (params a b) (print a))"),
+            (
+                "class B < A { m() { return super.m(this); } }",
+                "(class B (< A) (fun m params (return (call (. super m) this))))",
+            ),
+        ];
+
+        for (source, expected) in input {
+            let statements = parse(source).unwrap();
+
+            assert_eq!(
+                AstPrinter::new(Layout::Lisp).print_statements(&statements),
+                format!("{}\n", expected)
+            );
+        }
+    }
+
+    #[test]
+    fn ast_tree_layout_test() {
+        let statements = parse("print -123 * (45.67);").unwrap();
+
+        assert_eq!(
+            AstPrinter::new(Layout::Tree).print_statements(&statements),
+            "print\n  *\n    -\n      123\n    group\n      45.67\n"
+        );
+    }
+
+    #[test]
+    fn json_round_trip_test() {
+        let source = "var a = \"text\"; var b = nil;
+            class B < A { init(x) { this.x = x; } m() { return super.m() or !true; } }
+            fun f(p, q) { if (p <= 1) return p; else { while (q) q = q - 1.5; } }
+            print (f(1, 2)).y;
+            for (var i = 0; i < 3; i = i + 1) a.b = -i;";
+        let statements = parse(source).unwrap();
+
+        let json = JsonWriter::statements(&statements).to_string();
+        let read_back = JsonReader::statements(&Json::parse(&json).unwrap()).unwrap();
+
+        assert_eq!(
+            AstPrinter::new(Layout::Lisp).print_statements(&read_back),
+            AstPrinter::new(Layout::Lisp).print_statements(&statements)
+        );
+        assert_eq!(JsonWriter::statements(&read_back).to_string(), json);
+
+        let spans = |statements: &[Box<Statement>]| -> Vec<Span> {
+            statements.iter().map(|statement| statement.span()).collect()
+        };
+        assert_eq!(spans(&read_back), spans(&statements));
+    }
+
+    #[test]
+    fn token_json_test() {
+        let mut scanner = Scanner::new("x = 1.5;".to_string());
+        let tokens = scanner.scan_tokens();
+
+        let json = JsonWriter::tokens(tokens).to_string();
+        assert!(json.starts_with(
+            "[{\"type\":\"IDENTIFIER\",\"lexeme\":\"x\",\"literal\":\"x\",\
+             \"span\":{\"start\":0,\"end\":1,\"line\":1,\"column\":1}}"
+        ));
+        assert!(json.contains("\"type\":\"NUMBER\",\"lexeme\":\"1.5\",\"literal\":1.5"));
+
+        let read_back = JsonReader::tokens(&Json::parse(&json).unwrap()).unwrap();
+        assert_eq!(&read_back, tokens);
+    }
+
+    #[test]
+    fn json_test() {
+        let text = "{\"a\": [1, -2.5e3, true, false, null], \"b\": \"\\u00e9\\n\\\"\\ud83d\\ude00\"}";
+        let json = Json::parse(text).unwrap();
+
+        assert_eq!(json.get("a").and_then(|a| a.as_array()).map(|a| a.len()), Some(5));
+        assert_eq!(json.get("b").and_then(|b| b.as_str()), Some("\u{e9}\n\"\u{1f600}"));
+        assert_eq!(Json::parse(&json.to_string()).unwrap(), json);
+
+        for invalid in &["", "{", "[1,]", "{\"a\" 1}", "tru", "\"abc", "1 2"] {
+            assert!(Json::parse(invalid).is_err(), "{}", invalid);
+        }
+        for escape in &["\\u+041", "\\u00e", "\\ud83d\\u0041", "\\ud83d"] {
+            assert!(Json::parse(&format!("\"{}\"", escape)).is_err(), "{}", escape);
+        }
+
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(
+            Json::parse(&nested(MAX_DEPTH + 1)),
+            Err(format!("Invalid JSON at character {}: Too deeply nested.", MAX_DEPTH))
+        );
+
+        let bad_node = Json::parse("{\"kind\": \"Nope\"}").unwrap();
+        assert!(JsonReader::expression(&bad_node).is_err());
+        assert!(JsonReader::statement(&Json::parse("{}").unwrap()).is_err());
+    }
+
+    #[test]
+    fn json_operator_test() {
+        let json = JsonWriter::statements(&parse("print 1 + 2; print !true;").unwrap()).to_string();
+
+        for (from, to, message) in &[
+            ("PLUS", "DOT", "DOT is not a binary operator."),
+            ("BANG", "STAR", "STAR is not a unary operator."),
+        ] {
+            let edited = json.replace(
+                &format!("\"type\":\"{}\"", from),
+                &format!("\"type\":\"{}\"", to),
+            );
+            let result = JsonReader::statements(&Json::parse(&edited).unwrap());
+            assert_eq!(result.err().as_deref(), Some(*message));
+        }
+
+        // Trees built by hand are rejected when they run rather than
+        // panicking.
+        for backend in &[Backend::TreeWalker, Backend::Vm] {
+            let mut statements = parse("print 1 + 2;").unwrap();
+            if let Statement::Print { expression, .. } = statements[0].as_mut() {
+                if let crate::expression::Expr::Binary { operator, .. } = expression.as_mut() {
+                    operator.of_type = TokenType::DOT;
+                }
+            }
+
+            let mut engine = Engine::with_output(BufferOutput::new()).with_backend(*backend);
+            assert!(engine.run_statements(&statements).is_err());
+        }
+    }
+
+    #[test]
+    fn custom_visitor_test() {
+        use crate::expression::Visitable as VisitableExpression;
+        use crate::expression::*;
+
+        // Folds arithmetic on number literals and rejects anything else.
+        struct Arithmetic;
+
+        impl ExprVisitor for Arithmetic {
+            type Value = f64;
+            type Error = String;
+
+            fn visit_assign_expression(
+                &mut self,
+                _: usize,
+                _: &Token,
+                _: &Expr,
+            ) -> Result<f64, String> {
+                Err(String::from("assignment"))
+            }
+
+            fn visit_binary_expression(
+                &mut self,
+                left: &Expr,
+                operator: &Token,
+                right: &Expr,
+            ) -> Result<f64, String> {
+                let (left, right) = (left.accept(self)?, right.accept(self)?);
+                match operator.of_type {
+                    TokenType::PLUS => Ok(left + right),
+                    TokenType::MINUS => Ok(left - right),
+                    TokenType::STAR => Ok(left * right),
+                    TokenType::SLASH => Ok(left / right),
+                    _ => Err(operator.lexeme.clone()),
+                }
+            }
+
+            fn visit_logical_expression(
+                &mut self,
+                _: &Expr,
+                operator: &Token,
+                _: &Expr,
+            ) -> Result<f64, String> {
+                Err(operator.lexeme.clone())
+            }
+
+            fn visit_call_expression(
+                &mut self,
+                _: &Expr,
+                _: &Token,
+                _: &[Box<Expr>],
+            ) -> Result<f64, String> {
+                Err(String::from("call"))
+            }
+
+            fn visit_get_expression(&mut self, _: &Expr, name: &Token) -> Result<f64, String> {
+                Err(name.lexeme.clone())
+            }
+
+            fn visit_set_expression(
+                &mut self,
+                _: &Expr,
+                name: &Token,
+                _: &Expr,
+            ) -> Result<f64, String> {
+                Err(name.lexeme.clone())
+            }
+
+            fn visit_this_expression(&mut self, _: usize, keyword: &Token) -> Result<f64, String> {
+                Err(keyword.lexeme.clone())
+            }
+
+            fn visit_super_expression(
+                &mut self,
+                _: usize,
+                keyword: &Token,
+                _: &Token,
+            ) -> Result<f64, String> {
+                Err(keyword.lexeme.clone())
+            }
+
+            fn visit_group_expression(&mut self, content: &Expr) -> Result<f64, String> {
+                content.accept(self)
+            }
+
+            fn visit_literal_expression(&mut self, literal: &Literal) -> Result<f64, String> {
+                match literal {
+                    Literal::Number(x) => Ok(*x),
+                    _ => Err(String::from("literal")),
+                }
+            }
+
+            fn visit_unary_expression(
+                &mut self,
+                operator: &Token,
+                right: &Expr,
+            ) -> Result<f64, String> {
+                match operator.of_type {
+                    TokenType::MINUS => Ok(-right.accept(self)?),
+                    _ => Err(operator.lexeme.clone()),
+                }
+            }
+
+            fn visit_variable_expression(&mut self, _: usize, name: &Token) -> Result<f64, String> {
+                Err(name.lexeme.clone())
+            }
+        }
+
+        let input = vec![
+            ("1 + 2 * 3;", Ok(7.0)),
+            ("-(4 - 6) / 4;", Ok(0.5)),
+            ("1 + x;", Err(String::from("x"))),
+            ("1 < 2;", Err(String::from("<"))),
+        ];
+
+        for (source, expected) in input {
+            let statements = parse(source).unwrap();
+            let expression = match &*statements[0] {
+                Statement::Expression { expression, .. } => expression,
+                _ => panic!("expected an expression statement"),
+            };
+
+            assert_eq!(expression.accept(&mut Arithmetic), expected);
+        }
+    }
+
+    #[test]
+    fn backend_runtime_error_test() {
+        let input = vec![
+            "fun f(a) {}\nf(1, 2);",
+            "var a = 1;\na();",
+            "print 1;\nclock(1);",
+            "print 1;\nnum(true);",
+            "1 - \"a\";",
+            "\"a\" < \"b\";",
+            "-\"a\";",
+            "print undefined;",
+            "undefined = 1;",
+            "var A = 1;\nclass B < A {}",
+            "class A {}\nA().x;",
+            "class A {}\nA(1);",
+            "class A { init(a) {} }\nA();",
+            "var a = 1;\na.x = 2;",
+            "var a = true;\nprint a.x;",
+            "class A {}\nclass B < A { m() { return super.n(); } }\nB().m();",
+            "var a = 1;\nfun f() { print \"called\"; return 1; }\na.x = f();",
+        ];
+
+        // Both the error and what ran before it have to match.
+        for source in input {
+            let errors: Vec<(String, String, Span, String)> = [Backend::TreeWalker, Backend::Vm]
+                .iter()
+                .map(|backend| {
+                    let output = BufferOutput::new();
+                    let mut engine = Engine::with_output(output.clone()).with_backend(*backend);
+                    match engine.run(source) {
+                        Err(LoxError::RuntimeError(token, message)) => {
+                            (message, token.lexeme, token.span, output.out())
+                        }
+                        result => panic!("{} ran to {:?}", source, result),
+                    }
+                })
+                .collect();
+
+            assert_eq!(errors[0], errors[1], "{}", source);
+        }
+    }
+
+    #[test]
+    fn vm_test() {
+        let output = BufferOutput::new();
+        let mut engine = Engine::with_output(output.clone()).with_backend(Backend::Vm);
+
+        // Closures made in the same call share the variables they capture,
+        // which outlive the call.
+        engine
+            .run(
+                "fun pair() {
+                     var n = 0;
+                     fun up() { n = n + 1; return n; }
+                     fun get() { return n; }
+                     return up;
+                 }
+                 var up = pair();
+                 up(); up();
+                 print up();",
+            )
+            .unwrap();
+        assert_eq!(output.out(), "3\n");
+
+        assert!(engine.run("var a = 1 + nil;").is_err());
+        engine.run("var b = 2;").unwrap();
+        assert_eq!(engine.eval("b * 21;").unwrap(), Some(Object::Number(42.0)));
+
+        match engine.run("fun f() { f(); }\nf();") {
+            Err(LoxError::RuntimeError(_, message)) => assert_eq!(message, "Stack overflow."),
+            result => panic!("unbounded recursion ran to {:?}", result),
+        }
+
+        assert_eq!(
+            engine.disassemble("var a = 1;\nprint a + 2;").unwrap(),
+            "== <script> ==
+0000    1 OP_CONSTANT         0 '1'
+0001    | OP_DEFINE_GLOBAL    1 'a'
+0002    2 OP_GET_GLOBAL       1 'a'
+0003    | OP_CONSTANT         2 '2'
+0004    | OP_ADD
+0005    | OP_PRINT
+0006    | OP_NIL
+0007    | OP_RETURN
+"
+        );
+    }
+
+    #[test]
+    fn ast_cache_test() {
+        let source = "var a = \"text\"; var b = nil; var c = a;
+            class B < A { init(x) { this.x = x; } m() { return super.m() or !true; } }
+            fun f(p, q) { if (p <= 1) return p; else { while (q) q = q - 1.5; } }
+            print (f(1, 2)).y;
+            for (var i = 0; i < 3; i = i + 1) a.b = -i;";
+        let statements = parse(source).unwrap();
+
+        let bytes = CachedScript::encode(&statements, source, None);
+        assert!(CachedScript::is_cached(&bytes));
+        let read_back = CachedScript::decode(&bytes).unwrap();
+        assert_eq!(read_back.source_path, None);
+        assert_eq!(read_back.source_checksum, crc32(source.as_bytes()));
+        assert_eq!(
+            JsonWriter::statements(&read_back.statements).to_string(),
+            JsonWriter::statements(&statements).to_string()
+        );
+
+        let mut engine = Engine::with_output(BufferOutput::new());
+        let statements = CachedScript::decode(&bytes).unwrap().statements;
+        assert!(engine.run_statements(&statements[..1]).is_ok());
+        assert_eq!(engine.get_global("a"), Some(Object::from("text")));
+
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn ast_cache_rejects_damaged_files_test() {
+        let bytes = CachedScript::encode(&parse("print 1;").unwrap(), "print 1;", None);
+        let error = |bytes: &[u8]| CachedScript::decode(bytes).err().unwrap();
+
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(error(&corrupted).starts_with("Checksum mismatch"));
+
+        let mut newer = bytes.clone();
+        newer[4] += 1;
+        assert!(error(&newer).starts_with("Compiled with format version 3"));
+
+        assert_eq!(error(b"print 1;"), "Not a compiled Lox script.");
+        assert_eq!(error(&bytes[..8]), "Not a compiled Lox script.");
+
+        // Damage that happens to keep the checksum valid is still caught.
+        let mut truncated = bytes[..bytes.len() - 2].to_vec();
+        let checksum = crc32(&truncated[10..]).to_le_bytes();
+        truncated[6..10].copy_from_slice(&checksum);
+        assert_eq!(error(&truncated), "Unexpected end of file.");
+
+        // So is a tree the backends couldn't run.
+        let mut statements = parse("print -1;").unwrap();
+        if let Statement::Print { expression, .. } = statements[0].as_mut() {
+            if let crate::expression::Expr::Unary { operator, .. } = expression.as_mut() {
+                operator.of_type = TokenType::SEMICOLON;
+            }
+        }
+        let bytes = CachedScript::encode(&statements, "print -1;", None);
+        assert_eq!(error(&bytes), "SEMICOLON is not a unary operator.");
+    }
+
+    #[test]
+    fn ast_cache_depth_test() {
+        // Trees this deep take more stack than a test thread has in a debug
+        // build.
+        let test = std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(|| {
+                let nested = |depth: usize| {
+                    let source = format!("print {}1;", "-".repeat(depth));
+                    CachedScript::encode(&parse(&source).unwrap(), &source, None)
+                };
+
+                // The statement and the literal count too.
+                assert!(CachedScript::decode(&nested(MAX_TREE_DEPTH - 2)).is_ok());
+                assert_eq!(
+                    CachedScript::decode(&nested(MAX_TREE_DEPTH - 1)).err(),
+                    Some(String::from("Syntax tree nested too deeply."))
+                );
+            })
+            .unwrap();
+
+        test.join().unwrap();
+    }
+
+    #[test]
+    fn ast_cache_stale_source_test() {
+        let path = std::env::temp_dir().join(format!("lox_cache_test_{}.lox", std::process::id()));
+        std::fs::write(&path, "print 1;").unwrap();
+
+        let bytes = CachedScript::encode(&parse("print 1;").unwrap(), "print 1;", Some(&path));
+        let script = CachedScript::decode(&bytes).unwrap();
+        assert_eq!(script.source_path.as_ref(), Some(&path));
+        assert_eq!(script.current_source(), Ok(Some(String::from("print 1;"))));
+
+        std::fs::write(&path, "print 2;").unwrap();
+        assert!(script
+            .current_source()
+            .err()
+            .unwrap()
+            .ends_with("has changed since it was compiled. Compile it again."));
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(script.current_source(), Ok(None));
+    }
+
+    #[test]
+    fn optimizer_test() {
+        let input = vec![
+            ("print 1 + 2 * 3;", "(print 7)"),
+            ("print -(4) / 2 - 0.5;", "(print -2.5)"),
+            ("print \"con\" + \"cat\";", "(print \"concat\")"),
+            ("print 1 < 2 == !nil;", "(print true)"),
+            ("print (x);", "(print x)"),
+            ("print x + 1 + 2;", "(print (+ (+ x 1) 2))"),
+            ("print nil or x; print 1 and x;", "(print x)\n(print x)"),
+            (
+                "print false and x; print \"a\" or x;",
+                "(print false)\n(print \"a\")",
+            ),
+            ("print x or 1 + 1;", "(print (or x 2))"),
+            // Operations that fail are left for run time.
+            ("print -\"a\";", "(print (- \"a\"))"),
+            ("print 1 + \"a\";", "(print (+ 1 \"a\"))"),
+            ("print 1 < nil;", "(print (< 1 nil))"),
+            ("if (1 > 2) print a; else print b;", "(print b)"),
+            ("if (nil) print a; print b;", "(print b)"),
+            (
+                "if (x) print a; else if (true) print b;",
+                "(if x (print a) (print b))",
+            ),
+            ("while (false) print a;", ""),
+            ("while (x) if (false) print a;", "(while x block)"),
+            (
+                "fun f() { { return (1 + 2) * x; } }",
+                "(fun f params (block (return (* 3 x))))",
+            ),
+        ];
+
+        for (source, expected) in input {
+            let statements = optimize(parse(source).unwrap());
+
+            assert_eq!(
+                AstPrinter::new(Layout::Lisp)
+                    .print_statements(&statements)
+                    .trim_end(),
+                expected,
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn optimizer_preserves_behavior_test() {
+        let input = vec![
+            "print 1 / 0; print -0; print 0.1 + 0.2;",
+            "var s = 0; for (var i = 0; i < 3; i = i + 1) s = s + 2 * 3; print s;",
+            "print nil or \"default\"; print (1 == 1) and \"yes\";",
+            "print 1;\nprint -\"a\";",
+            "print 1;\nprint (1 + 2) + \"a\";",
+            "print 1;\nif (true) print 2 < \"b\";",
+            "if (false) { return 1; }",
+            "if (false) { var a = 1; { var a = a; } }",
+        ];
+
+        for source in input {
+            let mut outcomes = Vec::new();
+
+            for backend in &[Backend::TreeWalker, Backend::Vm] {
+                for optimize in &[false, true] {
+                    let output = BufferOutput::new();
+                    let mut engine = Engine::with_output(output.clone())
+                        .with_backend(*backend)
+                        .with_optimizations(*optimize);
+
+                    let error = engine.run(source).err().map(|e| format!("{:?}", e));
+                    outcomes.push((output.out(), error));
+                }
+            }
+
+            for outcome in &outcomes[1..] {
+                assert_eq!(outcome, &outcomes[0], "{}", source);
+            }
+        }
+    }
+}
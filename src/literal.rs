@@ -1,8 +1,8 @@
 use std::fmt;
-#[derive(Debug,PartialEq,Clone, PartialOrd, Eq)]
+#[derive(Debug,PartialEq,Clone, PartialOrd)]
 pub enum Literal {
     String(String),
-    Number(i32),
+    Number(f64),
     None,
     Boolean(bool)
 }
@@ -12,13 +12,23 @@ impl fmt::Display for Literal {
         match self {
             Self::String(s) => write!(f, "{}", s),
             Self::None => write!(f, "nil"),
-            Self::Number(n) => {
-                let x = n.to_string();
-                write!(f, "{}", x)
-            },
+            Self::Number(n) => write!(f, "{}", format_number(*n)),
             Self::Boolean(x) => {
                 write!(f, "{}", x)
             }
         }
     }
 }
+
+/// Formats a number the way Lox prints it: integral values drop the
+/// trailing `.0`, and NaN and the infinities print as `NaN`, `inf` and
+/// `-inf`.
+pub fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        String::from("NaN")
+    } else if n.is_infinite() {
+        String::from(if n > 0.0 { "inf" } else { "-inf" })
+    } else {
+        n.to_string()
+    }
+}
@@ -0,0 +1,16 @@
+use crate::interpreter::*;
+use crate::lox_error::*;
+use crate::object::*;
+use std::rc::Rc;
+
+/// Anything that can be invoked with `callee(arguments)` from Lox code.
+pub trait LoxCallable {
+    /// Number of arguments the callable expects.
+    fn arity(&self) -> usize;
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Object>,
+    ) -> Result<Object, LoxError>;
+}
@@ -0,0 +1,82 @@
+use crate::interpreter::*;
+use crate::lox_callable::*;
+use crate::lox_error::*;
+use crate::lox_function::*;
+use crate::lox_instance::*;
+use crate::object::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+pub struct LoxClass {
+    pub name: String,
+    pub superclass: Option<Rc<LoxClass>>,
+    pub methods: HashMap<String, Rc<LoxFunction>>,
+}
+
+impl LoxClass {
+    pub fn new(
+        name: &str,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<String, Rc<LoxFunction>>,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            superclass,
+            methods,
+        }
+    }
+
+    /// Looks a method up on this class, then up the superclass chain.
+    pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        match self.methods.get(name) {
+            Some(method) => Some(Rc::clone(method)),
+            None => self
+                .superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name)),
+        }
+    }
+}
+
+/// Calling a class creates a new instance and runs `init` on it, if the
+/// class has one.
+impl LoxCallable for LoxClass {
+    fn arity(&self) -> usize {
+        self.find_method("init")
+            .map_or(0, |initializer| initializer.arity())
+    }
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Object>,
+    ) -> Result<Object, LoxError> {
+        let instance = interpreter.heap.instance(LoxInstance::new(&self));
+
+        if let Some(initializer) = self.find_method("init") {
+            Rc::new(initializer.bind(&instance)).call(interpreter, arguments)?;
+        }
+
+        Ok(Object::Instance(instance))
+    }
+}
+
+// Classes are only equal to themselves.
+impl PartialEq for LoxClass {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl fmt::Display for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
@@ -1,27 +1,58 @@
 use crate::token::*;
+use crate::token_type::*;
+use std::io;
 
 #[derive(Debug)]
 pub enum LoxError {
-    RuntimeError(String),
-    BindingError(String, String),
+    // The token the error is reported at and the message.
+    RuntimeError(Token, String),
+    BindingError(Token, String),
+    // Every syntax error in the source, in order. Nothing was run.
+    SyntaxErrors(Vec<ParseError>),
+    // A script file could not be read.
+    IoError(io::Error),
 }
 
 impl std::fmt::Display for LoxError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            LoxError::RuntimeError(message) => write!(f, "RuntimeError: {}", message),
+            LoxError::RuntimeError(token, message) => {
+                write!(f, "[line {}] RuntimeError: {}", token.span.line, message)
+            }
             LoxError::BindingError(token, message) => {
-                write!(f, "BindingError for {}: {}", token, message)
+                write!(f, "BindingError for {}: {}", token.lexeme, message)
+            }
+            LoxError::SyntaxErrors(errors) => {
+                let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", lines.join("\n"))
             }
+            LoxError::IoError(e) => write!(f, "IoError: {}", e),
         }
     }
 }
 
-/// TODO
-/// Improve error handling.
-///
-/// Modify the run function so it now can succesfully handle a parser as well as its
-/// error handling function.
+impl std::error::Error for LoxError {}
+
+/// An error found while parsing, reported at the token the parser was
+/// looking at.
+#[derive(Debug, PartialEq)]
 pub enum ParseError {
     SyntaxError(Token, String),
 }
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::SyntaxError(token, message) if token.of_type == TokenType::EOF => {
+                write!(f, "[line {}] SyntaxError at end: {}", token.span.line, message)
+            }
+            ParseError::SyntaxError(token, message) => write!(
+                f,
+                "[line {}] SyntaxError at '{}': {}",
+                token.span.line, token.lexeme, message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
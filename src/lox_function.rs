@@ -0,0 +1,106 @@
+use crate::environment::*;
+use crate::interpreter::*;
+use crate::lox_callable::*;
+use crate::lox_error::*;
+use crate::lox_instance::*;
+use crate::object::*;
+use crate::statement::*;
+use crate::token::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// A user defined function together with the environment it was declared in.
+pub struct LoxFunction {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Rc<[Box<Statement>]>,
+    pub closure: Rc<RefCell<Environment>>,
+    // Where the resolver put the variables the body uses.
+    pub locals: Locals,
+    // Initializers always hand back `this`, whatever they return.
+    pub is_initializer: bool,
+}
+
+impl LoxFunction {
+    pub fn new(
+        name: &Token,
+        params: &[Token],
+        body: &Rc<[Box<Statement>]>,
+        closure: Rc<RefCell<Environment>>,
+        locals: &Locals,
+        is_initializer: bool,
+    ) -> Self {
+        Self {
+            name: name.clone(),
+            params: params.to_vec(),
+            body: Rc::clone(body),
+            closure,
+            locals: Rc::clone(locals),
+            is_initializer,
+        }
+    }
+
+    /// Returns a copy of this method whose closure defines `this` as
+    /// `instance`.
+    pub fn bind(&self, instance: &Rc<RefCell<LoxInstance>>) -> LoxFunction {
+        let mut environment = Environment::with_ref(Rc::clone(&self.closure));
+        environment.define(Object::Instance(Rc::clone(instance)));
+
+        LoxFunction {
+            name: self.name.clone(),
+            params: self.params.clone(),
+            body: Rc::clone(&self.body),
+            closure: Rc::new(RefCell::new(environment)),
+            locals: Rc::clone(&self.locals),
+            is_initializer: self.is_initializer,
+        }
+    }
+}
+
+impl LoxCallable for LoxFunction {
+    fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Object>,
+    ) -> Result<Object, LoxError> {
+        let mut environment = Environment::with_ref(Rc::clone(&self.closure));
+
+        // The resolver gave the parameters the first slots, in order.
+        for argument in arguments {
+            environment.define(argument);
+        }
+
+        let environment = Rc::new(RefCell::new(environment));
+        let value = interpreter.execute_body(&self.body, environment, &self.locals)?;
+
+        if self.is_initializer {
+            return Ok(self.closure.borrow().values[0].clone());
+        }
+
+        Ok(value.unwrap_or(Object::Nil))
+    }
+}
+
+// Functions are only equal to themselves.
+impl PartialEq for LoxFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.name.lexeme)
+    }
+}
+
+impl fmt::Display for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.name.lexeme)
+    }
+}
@@ -1,305 +1,506 @@
-pub mod environment;
-pub mod expression;
-pub mod interpreter;
-pub mod literal;
-pub mod lox_error;
-pub mod object;
-pub mod parser;
-pub mod scanner;
-pub mod statement;
-pub mod token;
-pub mod token_type;
-use crate::parser::*;
-use interpreter::*;
-use statement::*;
-use scanner::*;
+use lox::ast_cache::*;
+use lox::ast_printer::*;
+use lox::diagnostic::*;
+use lox::engine::*;
+use lox::lox_error::*;
+use lox::repl::*;
+use lox::scanner::*;
+use lox::syntax_json::*;
 use std::env;
 use std::{
     fs,
-    io::{self, Write},
+    io::{self, IsTerminal, Read},
+    path::{Path, PathBuf},
+    process, thread,
 };
-///
-///
-///expression     → equality ;
-///equality       → comparison ( ( "!=" | "==" ) comparison )* ;
-///comparison     → addition ( ( ">" | ">=" | "<" | "<=" ) addition )* ;
-///addition       → multiplication ( ( "-" | "+" ) multiplication )* ;
-///multiplication → unary ( ( "/" | "*" ) unary )* ;
-///unary          → ( "!" | "-" ) unary
-///               | primary ;
-///primary        → NUMBER | STRING | "false" | "true" | "nil"
-///               | "(" expression ")" ;
-///
 
+/// Stack for the thread scripts run on. The tree walker recurses on the
+/// native stack, and a debug build needs tens of megabytes to reach
+/// `FRAMES_MAX` calls. Only what is used gets committed.
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+
+/// Exit codes, following the BSD `sysexits.h` conventions.
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_IOERR: i32 = 74;
+const EX_SOFTWARE: i32 = 70;
+
+/// Command line front end: runs scripts or an interactive prompt on top of
+/// an `Engine` and prints whatever goes wrong.
 pub struct Lox {
-    had_error: bool,
-    had_rundtime_error: bool,
-    interpreter: Interpreter,
+    engine: Engine,
+    // What to do with the scripts given.
+    action: Action,
+    // Name shown in diagnostics for the source being run.
+    file_name: String,
+    // Whether diagnostics are colored with ANSI escapes.
+    color: bool,
 }
 
 impl Lox {
     pub fn new() -> Self {
         Self {
-            had_error: false,
-            had_rundtime_error: false,
-            interpreter: Interpreter::new(),
+            engine: Engine::new(),
+            action: Action::Run,
+            file_name: String::from("<repl>"),
+            color: false,
         }
     }
-    #[allow(dead_code)]
-    fn run_file(&mut self, file: &String) {
-        let bytes = fs::read_to_string(file).expect("Error reading external file.");
 
-        self.run(&bytes);
+    /// Runs a whole script, or one made by `lox compile`, and returns the
+    /// process exit code. A `-` path reads the script from stdin.
+    fn run_file(&mut self, file: &str) -> io::Result<i32> {
+        let mut bytes = Vec::new();
+
+        if file == "-" {
+            io::stdin().read_to_end(&mut bytes)?;
+            self.file_name = String::from("<stdin>");
+        } else {
+            bytes = fs::read(file)?;
+            self.file_name = file.to_string();
+        }
+
+        if CachedScript::is_cached(&bytes) {
+            return Ok(self.run_cached(&bytes));
+        }
+
+        let text =
+            String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let source = skip_shebang(&text);
+        let result = match self.action.clone() {
+            Action::Run => self.engine.run(source),
+            Action::Compile { output } => self.compile(&text, file, &output),
+            Action::DumpAst(layout) => self.dump(source, layout),
+            Action::ParseJson => self.parse_json(source),
+            Action::Tokens { json } => self.tokens(source, json),
+            Action::Disassemble => self.disassemble(source),
+        };
+        if let Err(e) = &result {
+            self.report(e, source);
+        }
+
+        Ok(exit_code(&result))
     }
 
-    fn run_prompt(&mut self, ) {
-        let buffer = io::stdin();
-        let mut stdout = io::stdout();
-        let mut source = String::new();
-        loop {
-            print!("> ");
-            stdout.flush();
-            source.clear();
-            buffer.read_line(&mut source).expect("Error handling input");
-
-            self.run(&source);
+    /// Runs a script made by `lox compile`. Diagnostics point into its
+    /// source when that is still around and unchanged; a changed source
+    /// means the script is stale and it is not run at all.
+    fn run_cached(&mut self, bytes: &[u8]) -> i32 {
+        let loaded = match self.action {
+            Action::Run => CachedScript::decode(bytes).and_then(|script| {
+                let source = script.current_source()?.unwrap_or_default();
+                Ok((script, source))
+            }),
+            _ => Err(String::from("Compiled scripts can only be run.")),
+        };
+
+        let (result, source) = match loaded {
+            Ok((script, source)) => (self.engine.run_statements(&script.statements), source),
+            Err(message) => {
+                let error = io::Error::new(io::ErrorKind::InvalidData, message);
+                (Err(LoxError::IoError(error)), String::new())
+            }
+        };
+        if let Err(e) = &result {
+            self.report(e, skip_shebang(&source));
         }
+
+        exit_code(&result)
+    }
+
+    /// Parses and resolves `text`, the whole of `file`, and saves the
+    /// statements to `output` so they can be run without parsing again.
+    fn compile(&mut self, text: &str, file: &str, output: &Path) -> Result<(), LoxError> {
+        let statements = self.engine.parse(skip_shebang(text))?;
+        let source_path = fs::canonicalize(file).ok();
+
+        let bytes = CachedScript::encode(&statements, text, source_path.as_deref());
+        fs::write(output, bytes).map_err(LoxError::IoError)
+    }
+
+    fn dump(&mut self, source: &str, layout: Layout) -> Result<(), LoxError> {
+        let statements = parse(source)?;
+        let tree = AstPrinter::new(layout).print_statements(&statements);
+        self.write_out(&tree)
     }
 
-    fn run(&mut self, source: &String) {
+    fn parse_json(&mut self, source: &str) -> Result<(), LoxError> {
+        let statements = parse(source)?;
+        let json = JsonWriter::statements(&statements);
+        self.write_out(&format!("{}\n", json))
+    }
+
+    fn disassemble(&mut self, source: &str) -> Result<(), LoxError> {
+        let listing = self.engine.disassemble(source)?;
+        self.write_out(&listing)
+    }
+
+    fn tokens(&mut self, source: &str, json: bool) -> Result<(), LoxError> {
         let mut scanner = Scanner::new(source.to_string());
         let tokens = scanner.scan_tokens();
-        let mut parser: Parser = Parser::new(tokens.to_vec());
-
-        match parser.parse() {
-            Ok(statements) => {
-                let mut did_evaluate_single_expression = false;
-                if statements.len() == 1 {
-                    let first = statements[0].clone();
-                    match *first {
-                        Statement::Expression { expression } => {
-                            did_evaluate_single_expression = true;
-
-                            match self.interpreter.evaluate(&expression) {
-                                Ok(r) => println!("{}", r),
-                                Err(_) => {
-                                    self.had_rundtime_error = true;
-                                }
-                            }
-                        }
-
-                        _ => (),
-                    }
-                }
 
-                if !did_evaluate_single_expression {
-                    match self.interpreter.interpret(&statements) {
-                        Ok(()) => (),
-                        Err(_) => {
-                            self.had_rundtime_error = true;
-                        }
-                    }
-                }
-            }
-
-            Err(e) => {
-                eprintln!("Error: {}", e);
-                self.had_error = true;
-            }
+        let listing = if json {
+            format!("{}\n", JsonWriter::tokens(tokens))
+        } else {
+            tokens
+                .iter()
+                .map(|token| {
+                    format!(
+                        "{}:{} {:?} {}\n",
+                        token.span.line, token.span.column, token.of_type, token.lexeme
+                    )
+                })
+                .collect()
+        };
+
+        self.write_out(&listing)?;
+
+        if !scanner.errors.is_empty() {
+            return Err(LoxError::SyntaxErrors(scanner.errors));
         }
+
+        Ok(())
     }
-}
 
-fn main() {
-    let args = env::args().collect::<Vec<String>>();
-    let mut lox = Lox::new();
+    fn write_out(&mut self, text: &str) -> Result<(), LoxError> {
+        self.engine
+            .output()
+            .write_out(text)
+            .map_err(LoxError::IoError)
+    }
 
-    match args.len() {
-        2 => todo!(), // FIXME Implement file handling.
-        1 => {
-            let _ = lox.run_prompt();
+    fn run_prompt(&mut self) -> io::Result<()> {
+        let mut repl = Repl::new(std::mem::take(&mut self.engine)).with_color(self.color);
+        if let Some(path) = history_file() {
+            repl = repl.with_history(path);
         }
-        _ => (), 
+
+        repl.run(io::stdin().lock())
+    }
+
+    fn report(&mut self, error: &LoxError, source: &str) {
+        let rendered = render_error(error, &self.file_name, source, self.color);
+        let _ = self.engine.output().write_err(&rendered);
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::environment::*;
-    use crate::literal::*;
-    use crate::object::*;
-    use crate::token::*;
-    use crate::token_type::*;
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-    #[test]
-    fn define_test() {
-        let mut env = Environment::new();
-
-        let definitions = vec![
-            (
-                Token::new(TokenType::IDENTIFIER, String::from("a"), None, 1),
-                Object::Number(10),
-            ),
-            (
-                Token::new(TokenType::IDENTIFIER, String::from("b"), None, 1),
-                Object::Str(String::from("Hello world")),
-            ),
-            (
-                Token::new(TokenType::IDENTIFIER, String::from("c"), None, 1),
-                Object::Boolean(false),
-            ),
-        ];
+/// Where REPL history is kept: `$LOX_HISTORY`, or `~/.lox_history`.
+fn history_file() -> Option<PathBuf> {
+    env::var_os("LOX_HISTORY")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".lox_history")))
+}
 
-        for (name, value) in definitions {
-            env.define(&name.lexeme, &value);
-            assert_eq!(env.get(name), value);
-        }
+/// The process exit code for the outcome of running a script.
+fn exit_code(result: &Result<(), LoxError>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(LoxError::SyntaxErrors(_)) | Err(LoxError::BindingError(_, _)) => EX_DATAERR,
+        // Compiled scripts that are corrupted or stale.
+        Err(LoxError::IoError(e)) if e.kind() == io::ErrorKind::InvalidData => EX_DATAERR,
+        Err(LoxError::IoError(_)) => EX_NOINPUT,
+        Err(LoxError::RuntimeError(_, _)) => EX_SOFTWARE,
     }
+}
 
-    #[test]
-    fn scanner_test() {
-        let input = "(+);".to_string();
-
-        let mut scanner = Scanner::new(input);
+const USAGE: &str = "\
+Usage: lox [--backend=tree|vm] [-O0|-O1] [--gc-stats] [--dump-ast[=tree] | --disassemble] [script]
+       lox tokens [--json] script
+       lox parse [--json] script
+       lox compile script [-o output]";
+
+/// What to do with a script.
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    Run,
+    /// Print the syntax tree in this layout instead of running.
+    DumpAst(Layout),
+    /// Print the syntax tree as JSON instead of running.
+    ParseJson,
+    /// Print the tokens, as JSON or one per line, instead of running.
+    Tokens {
+        json: bool,
+    },
+    /// Print the bytecode the script compiles to instead of running.
+    Disassemble,
+    /// Save the parsed script to `output` instead of running.
+    Compile {
+        output: PathBuf,
+    },
+}
 
-        scanner.scan_tokens();
+/// What the command line asked for.
+#[derive(Debug, PartialEq)]
+struct Options {
+    action: Action,
+    backend: Backend,
+    // Whether the optimizer runs, as set by `-O1` and `-O0`.
+    optimize: bool,
+    // Whether to print what the garbage collector did once the script ends.
+    gc_stats: bool,
+    script: Option<String>,
+}
 
-        assert_eq!(
-            scanner.tokens_helper(),
-            vec!(
-                Token {
-                    of_type: TokenType::LEFT_PAREN,
-                    lexeme: String::from("("),
-                    literal: None,
-                    line: 1,
-                },
-                Token {
-                    of_type: TokenType::PLUS,
-                    lexeme: String::from("+"),
-                    literal: None,
-                    line: 1,
-                },
-                Token {
-                    of_type: TokenType::RIGHT_PAREN,
-                    lexeme: String::from(")"),
-                    literal: None,
-                    line: 1,
-                },
-                Token {
-                    of_type: TokenType::SEMICOLON,
-                    lexeme: String::from(";"),
-                    literal: None,
-                    line: 1,
-                },
-                Token {
-                    of_type: TokenType::EOF,
-                    lexeme: String::from(""),
-                    literal: None,
-                    line: 1,
-                },
-            )
-        );
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            action: Action::Run,
+            backend: Backend::TreeWalker,
+            optimize: true,
+            gc_stats: false,
+            script: None,
+        }
     }
+}
 
-    #[test]
-    fn free_form_code_test() {
-        let input = "/".to_string();
-        let mut scanner = Scanner::new(input);
+/// Parses the arguments after the program name.
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut json = false;
+    let mut output = None;
+
+    let subcommand = match args.first().map(|arg| arg.as_str()) {
+        Some(name @ "tokens") | Some(name @ "parse") | Some(name @ "compile") => Some(name),
+        _ => None,
+    };
+    let rest = if subcommand.is_some() {
+        &args[1..]
+    } else {
+        args
+    };
+
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--dump-ast" | "--dump-ast=lisp" => options.action = Action::DumpAst(Layout::Lisp),
+            "--dump-ast=tree" => options.action = Action::DumpAst(Layout::Tree),
+            "--disassemble" => options.action = Action::Disassemble,
+            "--backend=tree" => options.backend = Backend::TreeWalker,
+            "--backend=vm" => options.backend = Backend::Vm,
+            "-O0" => options.optimize = false,
+            "-O1" => options.optimize = true,
+            "--gc-stats" => options.gc_stats = true,
+            "--json" if subcommand.is_some() => json = true,
+            "-o" if subcommand == Some("compile") => match rest.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => return Err(String::from("Expected a file after '-o'.")),
+            },
+            flag if flag.starts_with('-') && flag != "-" => {
+                return Err(format!("Unknown option '{}'.", flag))
+            }
+            _ if options.script.is_some() => return Err(String::from("Expected one script.")),
+            script => options.script = Some(script.to_string()),
+        }
+    }
 
-        scanner.scan_tokens();
+    match subcommand {
+        Some("tokens") => options.action = Action::Tokens { json },
+        Some("compile") => {
+            let output = match (output, &options.script) {
+                (Some(output), _) => output,
+                (None, Some(script)) if script != "-" => Path::new(script).with_extension("loxc"),
+                (None, Some(_)) => {
+                    return Err(String::from("Expected '-o' with a script from stdin."))
+                }
+                (None, None) => return Err(String::from("Expected a script.")),
+            };
+            options.action = Action::Compile { output };
+        }
+        Some(_) if json => options.action = Action::ParseJson,
+        Some(_) if options.action == Action::Run => options.action = Action::DumpAst(Layout::Lisp),
+        _ => {}
+    }
 
-        assert_eq!(
-            scanner.tokens_helper(),
-            vec!(
-                Token {
-                    of_type: TokenType::SLASH,
-                    lexeme: String::from("/"),
-                    literal: None,
-                    line: 1,
-                },
-                Token {
-                    of_type: TokenType::EOF,
-                    lexeme: String::from(""),
-                    literal: None,
-                    line: 1
-                },
-            )
-        );
+    if options.action != Action::Run && options.script.is_none() {
+        return Err(String::from("Expected a script."));
     }
 
-    #[test]
-    fn number_parsing_test() {
-        let input = "1+2".to_string();
-        let mut scanner = Scanner::new(input);
+    Ok(options)
+}
 
-        scanner.scan_tokens();
+fn main() {
+    let lox = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .expect("Could not start the interpreter thread.");
 
-        assert_eq!(
-            scanner.tokens_helper(),
-            vec!(
-                Token {
-                    of_type: TokenType::NUMBER,
-                    lexeme: String::from("1"),
-                    literal: Some(Literal::Number(1)),
-                    line: 1
-                },
-                Token {
-                    of_type: TokenType::PLUS,
-                    lexeme: String::from("+"),
-                    literal: None,
-                    line: 1
-                },
-                Token {
-                    of_type: TokenType::NUMBER,
-                    lexeme: String::from("2"),
-                    literal: Some(Literal::Number(2)),
-                    line: 1
-                },
-                Token {
-                    of_type: TokenType::EOF,
-                    lexeme: String::from(""),
-                    literal: None,
-                    line: 1
-                },
-            )
-        );
+    if let Err(panic) = lox.join() {
+        std::panic::resume_unwind(panic);
     }
-    #[test]
-    fn evaluation_test() {
-        let input = vec![("1+2*3", Object::Number(7))];
+}
 
-        for (expression, expected_result) in input {
-            let mut scanner = Scanner::new(expression.to_string());
-            let tokens = scanner.scan_tokens();
+fn run() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            process::exit(EX_USAGE);
+        }
+    };
+
+    let mut lox = Lox::new();
+    lox.engine = Engine::new()
+        .with_backend(options.backend)
+        .with_optimizations(options.optimize);
+    lox.color = io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+    lox.action = options.action;
+
+    match options.script {
+        Some(script) => match lox.run_file(&script) {
+            Ok(code) => {
+                if options.gc_stats {
+                    eprintln!("gc: {}", lox.engine.gc_stats());
+                }
+                process::exit(code)
+            }
+            Err(e) => {
+                eprintln!("Error reading {}: {}", script, e);
+                process::exit(EX_NOINPUT);
+            }
+        },
+        None => {
+            if let Err(e) = lox.run_prompt() {
+                eprintln!("Error: {}", e);
+                process::exit(EX_IOERR);
+            }
+        }
+    }
+}
 
-            let mut parser = Parser::new(tokens.to_vec());
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            let expr = parser.parse_expression().unwrap();
+    #[test]
+    fn exit_code_test() {
+        let input = vec![
+            ("var a = 1;", 0),
+            ("var a = (1;", EX_DATAERR),
+            ("print this;", EX_DATAERR),
+            ("var a = 1 + \"one\";", EX_SOFTWARE),
+        ];
 
-            let mut interpreter = Interpreter::new();
-            let result = interpreter.evaluate(&expr).unwrap();
+        for (source, expected_code) in input {
+            let mut engine = Engine::new();
 
-            assert_eq!(result, expected_result);
+            assert_eq!(exit_code(&engine.run(source)), expected_code);
         }
     }
 
     #[test]
-    fn expected_keywords_test() {
-        let mut scanner = Scanner::new("and for if while".to_string());
-
-        let tokens = scanner.scan_tokens();
-        let token_types: Vec<TokenType> = tokens.into_iter().map(|t| t.of_type).collect();
+    fn parse_args_test() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
 
+        assert_eq!(parse_args(&args(&[])), Ok(Options::default()));
+        assert_eq!(
+            parse_args(&args(&["-"])),
+            Ok(Options {
+                script: Some(String::from("-")),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["--dump-ast=tree", "a.lox"])),
+            Ok(Options {
+                action: Action::DumpAst(Layout::Tree),
+                script: Some(String::from("a.lox")),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["--backend=vm", "a.lox"])),
+            Ok(Options {
+                backend: Backend::Vm,
+                script: Some(String::from("a.lox")),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["-O0", "--backend=vm", "a.lox"])),
+            Ok(Options {
+                backend: Backend::Vm,
+                optimize: false,
+                script: Some(String::from("a.lox")),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["a.lox", "--gc-stats"])),
+            Ok(Options {
+                gc_stats: true,
+                script: Some(String::from("a.lox")),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["--disassemble", "a.lox"])),
+            Ok(Options {
+                action: Action::Disassemble,
+                script: Some(String::from("a.lox")),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["tokens", "--json", "a.lox"])),
+            Ok(Options {
+                action: Action::Tokens { json: true },
+                script: Some(String::from("a.lox")),
+                ..Options::default()
+            })
+        );
         assert_eq!(
-            token_types,
-            vec![
-                TokenType::AND,
-                TokenType::FOR,
-                TokenType::IF,
-                TokenType::WHILE,
-                TokenType::EOF
-            ]
+            parse_args(&args(&["parse", "a.lox", "--json"])),
+            Ok(Options {
+                action: Action::ParseJson,
+                script: Some(String::from("a.lox")),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["parse", "a.lox"])),
+            Ok(Options {
+                action: Action::DumpAst(Layout::Lisp),
+                script: Some(String::from("a.lox")),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["compile", "a.lox"])),
+            Ok(Options {
+                action: Action::Compile {
+                    output: PathBuf::from("a.loxc")
+                },
+                script: Some(String::from("a.lox")),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["compile", "-", "-o", "b.loxc"])),
+            Ok(Options {
+                action: Action::Compile {
+                    output: PathBuf::from("b.loxc")
+                },
+                script: Some(String::from("-")),
+                ..Options::default()
+            })
         );
+        assert!(parse_args(&args(&["compile", "-"])).is_err());
+        assert!(parse_args(&args(&["compile", "a.lox", "-o"])).is_err());
+        assert!(parse_args(&args(&["-o", "b.loxc", "a.lox"])).is_err());
+        assert!(parse_args(&args(&["--json", "a.lox"])).is_err());
+        assert!(parse_args(&args(&["tokens"])).is_err());
+        assert!(parse_args(&args(&["a.lox", "b.lox"])).is_err());
+        assert!(parse_args(&args(&["--dump-ast"])).is_err());
+        assert!(parse_args(&args(&["--frobnicate", "a.lox"])).is_err());
+        assert!(parse_args(&args(&["--backend=jit", "a.lox"])).is_err());
+        assert!(parse_args(&args(&["-O2", "a.lox"])).is_err());
     }
 }
@@ -0,0 +1,121 @@
+use crate::interpreter::*;
+use crate::object::*;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Signature of a built-in implemented in Rust. Errors are plain messages;
+/// the interpreter reports them at the call site.
+pub type NativeFn = fn(&mut Interpreter, &[Object]) -> Result<Object, String>;
+
+/// A function implemented in Rust and callable from Lox code.
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl NativeFunction {
+    pub fn new(name: &'static str, arity: usize, function: NativeFn) -> Self {
+        Self {
+            name,
+            arity,
+            function,
+        }
+    }
+
+    pub fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[Object],
+    ) -> Result<Object, String> {
+        (self.function)(interpreter, arguments)
+    }
+}
+
+/// The built-ins every interpreter starts with.
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("clock", 0, clock),
+        NativeFunction::new("type", 1, type_of),
+        NativeFunction::new("str", 1, str),
+        NativeFunction::new("num", 1, num),
+        NativeFunction::new("len", 1, len),
+        NativeFunction::new("gc", 0, gc),
+    ]
+}
+
+/// Seconds since the Unix epoch.
+fn clock(_interpreter: &mut Interpreter, _arguments: &[Object]) -> Result<Object, String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?;
+
+    Ok(Object::Number(now.as_secs_f64()))
+}
+
+/// Name of the argument's type, e.g. `"number"`.
+fn type_of(_interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object, String> {
+    let name = match &arguments[0] {
+        Object::Boolean(_) => "boolean",
+        Object::Number(_) => "number",
+        Object::Str(_) => "string",
+        Object::Function(_) | Object::Native(_) | Object::Closure(_) | Object::BoundMethod(_) => {
+            "function"
+        }
+        Object::Class(_) | Object::VmClass(_) => "class",
+        Object::Instance(_) | Object::VmInstance(_) => "instance",
+        Object::Nil => "nil",
+    };
+
+    Ok(Object::Str(String::from(name)))
+}
+
+/// The argument as `print` would show it.
+fn str(_interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object, String> {
+    Ok(Object::Str(arguments[0].to_string()))
+}
+
+/// Parses a string into a number. Numbers are returned unchanged.
+fn num(_interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object, String> {
+    match &arguments[0] {
+        Object::Number(x) => Ok(Object::Number(*x)),
+        Object::Str(s) => s
+            .trim()
+            .parse::<f64>()
+            .map(Object::Number)
+            .map_err(|_| format!("Can't convert '{}' to a number.", s)),
+        _ => Err(String::from("Argument must be a number or a string.")),
+    }
+}
+
+/// Number of characters in a string.
+fn len(_interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object, String> {
+    match &arguments[0] {
+        Object::Str(s) => Ok(Object::Number(s.chars().count() as f64)),
+        _ => Err(String::from("Argument must be a string.")),
+    }
+}
+
+/// Collects garbage right away and returns how many environments and
+/// objects were freed.
+fn gc(interpreter: &mut Interpreter, _arguments: &[Object]) -> Result<Object, String> {
+    Ok(Object::Number(interpreter.heap.collect() as f64))
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
@@ -1,15 +1,30 @@
 use std::ops::{Add, Div, Mul, Neg, Not, Sub};
-use std::cmp::{Ordering, PartialOrd};
+use std::cmp::Ordering;
 use std::fmt;
 use crate::literal::*;
-use crate::lox_error::*;
+use crate::lox_class::*;
+use crate::lox_function::*;
+use crate::lox_instance::*;
+use crate::native_function::*;
+use crate::vm::*;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Object {
     Boolean(bool),
-    Number(i32),
+    Number(f64),
     Str(String),
+    Function(Rc<LoxFunction>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    // What the bytecode VM makes of functions, classes and instances.
+    Closure(Rc<Closure>),
+    BoundMethod(Rc<BoundMethod>),
+    VmClass(Rc<VmClass>),
+    VmInstance(Rc<RefCell<VmInstance>>),
     Nil,
 }
 
@@ -17,38 +32,27 @@ impl fmt::Display for Object {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::Boolean(b) => write!(f, "{}", b),
-            Self::Number(x) => write!(f, "{}", x),
+            Self::Number(x) => write!(f, "{}", format_number(*x)),
             Self::Str(s) => write!(f, "{}", s),
-            Self::Nil=> write!(f,"Nil"),
+            Self::Function(function) => write!(f, "{}", function),
+            Self::Native(function) => write!(f, "{}", function),
+            Self::Class(class) => write!(f, "{}", class),
+            Self::Instance(instance) => write!(f, "{}", instance.borrow()),
+            Self::Closure(closure) => write!(f, "{}", closure),
+            Self::BoundMethod(method) => write!(f, "{}", method),
+            Self::VmClass(class) => write!(f, "{}", class),
+            Self::VmInstance(instance) => write!(f, "{}", instance.borrow()),
+            Self::Nil => write!(f, "nil"),
         }
     }
 }
 
-impl PartialOrd for Object {
-    fn partial_cmp(&self, other: &Object) -> Option<Ordering> {
-        match self {
-            Object::Nil => match other {
-                Object::Nil => Some(Ordering::Equal),
-                _ => Some(Ordering::Greater),
-            },
-            Object::Number(value) => match other {
-                Object::Number(other_value) => value.partial_cmp(other_value),
-                _ => panic!("Can't compare a number with this value"),
-            },
-            _ => panic!("Can't compare these two types"),
-        }
-    }
-}
-
-
 impl Neg for Object {
     type Output = Result<Object, String>;
     fn neg(self) -> Result<Object, String> {
         match self {
-            Object::Nil => Ok(Object::Boolean(true)),
             Object::Number(x) => Ok(Object::Number(-x)),
-            Object::Boolean(_) => Err(String::from("Operation not supported")),
-            Object::Str(_) => Err(String::from("Operation not supported"))
+            _ => Err(String::from("Operand must be a number.")),
         }
     }
 }
@@ -66,72 +70,58 @@ impl Not for Object {
 }
 
 impl Div for Object {
-    type Output = Object;
+    type Output = Result<Object, String>;
 
-    fn div(self, rhs: Object) -> Object {
-        match self {
-            Object::Number(value) => match rhs {
-                Object::Number(rhs_value) => Object::Number(value / rhs_value),
-                _ => panic!("Can't divide these two values"),
-            },
-            _ => panic!("Can't divide these two values"),
+    fn div(self, rhs: Object) -> Result<Object, String> {
+        match (self, rhs) {
+            (Object::Number(value), Object::Number(rhs_value)) => {
+                Ok(Object::Number(value / rhs_value))
+            }
+            _ => Err(String::from("Operands must be numbers.")),
         }
     }
 }
 
+impl Sub for Object {
+    type Output = Result<Object, String>;
 
-impl Sub for Object{
-    type Output = Object;
-
-    fn sub(self, rhs: Object) -> Object {
-        match self {
-            Object::Number(value) => match rhs {
-                Object::Number(rhs_value) => Object::Number(value - rhs_value),
-                _ => panic!("Can't subtract these two values"),
-            },
-            _ => panic!("Can't subtract these two values"),
+    fn sub(self, rhs: Object) -> Result<Object, String> {
+        match (self, rhs) {
+            (Object::Number(value), Object::Number(rhs_value)) => {
+                Ok(Object::Number(value - rhs_value))
+            }
+            _ => Err(String::from("Operands must be numbers.")),
         }
     }
 }
 
-impl Mul for Object{
-    type Output = Object;
+impl Mul for Object {
+    type Output = Result<Object, String>;
 
-    fn mul(self, rhs: Object) -> Object {
-        match self {
-            Object::Number(value) => match rhs {
-                Object::Number(rhs_value) => Object::Number(value * rhs_value),
-                _ => panic!("Can't multiply these two values"),
-            },
-            _ => panic!("Can't multiply these two values"),
+    fn mul(self, rhs: Object) -> Result<Object, String> {
+        match (self, rhs) {
+            (Object::Number(value), Object::Number(rhs_value)) => {
+                Ok(Object::Number(value * rhs_value))
+            }
+            _ => Err(String::from("Operands must be numbers.")),
         }
     }
 }
 
 impl Add for Object {
-    type Output = Result<Object, LoxError>;
+    type Output = Result<Object, String>;
 
-    fn add(self, rhs: Object) -> Result<Object, LoxError> {
-        match self {
-            Object::Number(value) => match rhs {
-                Object::Number(rhs_value) => Ok(Object::Number(value + rhs_value)),
-                _ => Err(LoxError::RuntimeError(
-                    "right hand side must also be a number".to_string(),
-                )),
-            },
-            Object::Str(value) => match rhs {
-                Object::Str(rhs_value) => {
-                    let mut new_str = value.clone();
-                    new_str.push_str(&rhs_value);
-                    Ok(Object::Str(new_str))
-                }
-                _ => panic!("TypeError: Can't add a string to a number."),
-            },
-            Object::Boolean(_value) => Err(LoxError::RuntimeError(
-                "Cannot add value to boolean.".to_string(),
-            )),
-            Object::Nil => Err(LoxError::RuntimeError(
-                "Cannot add value to nil.".to_string(),
+    fn add(self, rhs: Object) -> Result<Object, String> {
+        match (self, rhs) {
+            (Object::Number(value), Object::Number(rhs_value)) => {
+                Ok(Object::Number(value + rhs_value))
+            }
+            (Object::Str(mut value), Object::Str(rhs_value)) => {
+                value.push_str(&rhs_value);
+                Ok(Object::Str(value))
+            }
+            _ => Err(String::from(
+                "Operands must be two numbers or two strings.",
             )),
         }
     }
@@ -147,6 +137,38 @@ impl Object {
         }
     }
 
+    /// Orders two numbers. `None` means the numbers are unordered, which only
+    /// happens with NaN; comparing anything other than numbers is an error.
+    pub fn compare(&self, rhs: &Object) -> Result<Option<Ordering>, String> {
+        match (self, rhs) {
+            (Object::Number(value), Object::Number(rhs_value)) => {
+                Ok(value.partial_cmp(rhs_value))
+            }
+            _ => Err(String::from("Operands must be numbers.")),
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Object::Number(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Object::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Object::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
     pub fn is_truthy(&mut self) -> bool {
         match self {
             Object::Nil => false,
@@ -155,3 +177,33 @@ impl Object {
         }
     }
 }
+
+impl From<f64> for Object {
+    fn from(value: f64) -> Self {
+        Object::Number(value)
+    }
+}
+
+impl From<bool> for Object {
+    fn from(value: bool) -> Self {
+        Object::Boolean(value)
+    }
+}
+
+impl From<&str> for Object {
+    fn from(value: &str) -> Self {
+        Object::Str(value.to_string())
+    }
+}
+
+impl From<String> for Object {
+    fn from(value: String) -> Self {
+        Object::Str(value)
+    }
+}
+
+impl From<()> for Object {
+    fn from(_: ()) -> Self {
+        Object::Nil
+    }
+}
@@ -0,0 +1,271 @@
+use crate::expression::*;
+use crate::interpreter::*;
+use crate::literal::*;
+use crate::object::*;
+use crate::statement::*;
+use crate::token::*;
+use crate::token_type::*;
+
+/// Simplifies a parsed script without changing what it does.
+///
+/// Arithmetic, comparisons, string concatenation, `!`, `and` and `or` on
+/// literals are folded into a single literal, groupings are dropped, and
+/// `if` and `while` statements whose condition is a literal lose the
+/// branches that can never run. Operations that would fail are left alone
+/// so the error is still raised when, and if, they run.
+///
+/// Run it after the resolver so scripts with binding errors in dead code
+/// are still rejected; folding keeps the ids the resolver recorded.
+#[allow(clippy::vec_box)]
+pub fn optimize(statements: Vec<Box<Statement>>) -> Vec<Box<Statement>> {
+    statements
+        .into_iter()
+        .filter_map(|statement| fold_statement(*statement))
+        .map(Box::new)
+        .collect()
+}
+
+/// Folds `statement`, or returns `None` when none of it can ever run.
+fn fold_statement(statement: Statement) -> Option<Statement> {
+    let folded = match statement {
+        Statement::Print { expression, span } => Statement::Print {
+            expression: fold(expression),
+            span,
+        },
+        Statement::Expression { expression, span } => Statement::Expression {
+            expression: fold(expression),
+            span,
+        },
+        Statement::Variable {
+            name,
+            initializer,
+            span,
+        } => Statement::Variable {
+            name,
+            initializer: initializer.map(fold),
+            span,
+        },
+        Statement::Block { statements, span } => Statement::Block {
+            statements: optimize(statements),
+            span,
+        },
+        Statement::If {
+            condition,
+            then_branch,
+            else_branch,
+            span,
+        } => {
+            let condition = fold(condition);
+
+            if let Some(mut value) = constant(&condition) {
+                return if value.is_truthy() {
+                    fold_statement(*then_branch)
+                } else {
+                    else_branch.and_then(|branch| fold_statement(*branch))
+                };
+            }
+
+            Statement::If {
+                condition,
+                then_branch: fold_branch(*then_branch),
+                else_branch: else_branch
+                    .and_then(|branch| fold_statement(*branch))
+                    .map(Box::new),
+                span,
+            }
+        }
+        Statement::While {
+            condition,
+            body,
+            span,
+        } => {
+            let condition = fold(condition);
+
+            if let Some(mut value) = constant(&condition) {
+                if !value.is_truthy() {
+                    return None;
+                }
+            }
+
+            Statement::While {
+                condition,
+                body: fold_branch(*body),
+                span,
+            }
+        }
+        Statement::Function {
+            name,
+            params,
+            body,
+            span,
+        } => Statement::Function {
+            name,
+            params,
+            body: optimize(body.to_vec()).into(),
+            span,
+        },
+        Statement::Return {
+            keyword,
+            value,
+            span,
+        } => Statement::Return {
+            keyword,
+            value: value.map(fold),
+            span,
+        },
+        Statement::Class {
+            name,
+            superclass,
+            methods,
+            span,
+        } => Statement::Class {
+            name,
+            superclass: superclass.map(fold),
+            methods: optimize(methods),
+            span,
+        },
+    };
+
+    Some(folded)
+}
+
+/// Folds a statement that has to stay, like a loop body, into an empty
+/// block when none of it can run.
+fn fold_branch(statement: Statement) -> Box<Statement> {
+    let span = statement.span();
+    let folded = fold_statement(statement).unwrap_or(Statement::Block {
+        statements: Vec::new(),
+        span,
+    });
+
+    Box::new(folded)
+}
+
+// Folds into the same allocation.
+fn fold(mut expression: Box<Expr>) -> Box<Expr> {
+    *expression = fold_expression(*expression);
+    expression
+}
+
+fn fold_expression(expression: Expr) -> Expr {
+    match expression {
+        Expr::Grouping { expression, .. } => fold_expression(*expression),
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => {
+            let left = fold_expression(*left);
+            let right = fold_expression(*right);
+
+            if let (Some(a), Some(b)) = (constant(&left), constant(&right)) {
+                if let Some(literal) = binary_operation(a, operator.of_type, b)
+                    .ok()
+                    .and_then(to_literal)
+                {
+                    let span = left.span().to(&right.span());
+                    return Expr::Literal { literal, span };
+                }
+            }
+
+            Expr::Binary {
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+            }
+        }
+        Expr::Unary { operator, right } => {
+            let right = fold_expression(*right);
+
+            if let Some(value) = constant(&right) {
+                if let Some(literal) = unary_operation(&operator, value).and_then(to_literal) {
+                    let span = operator.span.to(&right.span());
+                    return Expr::Literal { literal, span };
+                }
+            }
+
+            Expr::Unary {
+                operator,
+                right: Box::new(right),
+            }
+        }
+        Expr::Logical {
+            left,
+            operator,
+            right,
+        } => {
+            let left = fold_expression(*left);
+            let right = fold_expression(*right);
+
+            match constant(&left).map(|mut value| value.is_truthy()) {
+                // `or` stops at a truthy left operand and `and` at a falsy
+                // one, evaluating to it.
+                Some(truthy) if truthy == (operator.of_type == TokenType::OR) => left,
+                Some(_) => right,
+                None => Expr::Logical {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+            }
+        }
+        Expr::Assign { id, name, value } => Expr::Assign {
+            id,
+            name,
+            value: fold(value),
+        },
+        Expr::Call {
+            callee,
+            paren,
+            arguments,
+        } => Expr::Call {
+            callee: fold(callee),
+            paren,
+            arguments: arguments.into_iter().map(fold).collect(),
+        },
+        Expr::Get { object, name } => Expr::Get {
+            object: fold(object),
+            name,
+        },
+        Expr::Set {
+            object,
+            name,
+            value,
+        } => Expr::Set {
+            object: fold(object),
+            name,
+            value: fold(value),
+        },
+        Expr::Literal { .. } | Expr::Variable { .. } | Expr::This { .. } | Expr::Super { .. } => {
+            expression
+        }
+    }
+}
+
+/// The value of `expression` when it is a literal.
+fn constant(expression: &Expr) -> Option<Object> {
+    match expression {
+        Expr::Literal { literal, .. } => Some(Object::from_literal(literal)),
+        _ => None,
+    }
+}
+
+fn to_literal(value: Object) -> Option<Literal> {
+    match value {
+        Object::Number(x) => Some(Literal::Number(x)),
+        Object::Str(s) => Some(Literal::String(s)),
+        Object::Boolean(b) => Some(Literal::Boolean(b)),
+        Object::Nil => Some(Literal::None),
+        _ => None,
+    }
+}
+
+/// Mirrors `Interpreter::visit_unary_expression`, with `None` for a
+/// negated non-number.
+fn unary_operation(operator: &Token, mut value: Object) -> Option<Object> {
+    match operator.of_type {
+        TokenType::MINUS => (-value).ok(),
+        TokenType::BANG => Some(Object::Boolean(!value.is_truthy())),
+        _ => None,
+    }
+}
@@ -0,0 +1,72 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// Where the interpreter sends text meant for the user: `print` output on
+/// one side and diagnostics on the other.
+pub trait Output {
+    fn write_out(&mut self, text: &str) -> io::Result<()>;
+
+    fn write_err(&mut self, text: &str) -> io::Result<()>;
+
+    /// Pushes out anything buffered, e.g. a prompt with no newline yet.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes to the process's stdout and stderr.
+#[derive(Default)]
+pub struct StdOutput;
+
+impl Output for StdOutput {
+    fn write_out(&mut self, text: &str) -> io::Result<()> {
+        io::stdout().write_all(text.as_bytes())
+    }
+
+    fn write_err(&mut self, text: &str) -> io::Result<()> {
+        io::stderr().write_all(text.as_bytes())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// Collects everything written into strings.
+///
+/// Clones share the same buffers, so keep one clone to read back what a
+/// script printed after handing another to the interpreter.
+#[derive(Clone, Default)]
+pub struct BufferOutput {
+    out: Rc<RefCell<String>>,
+    err: Rc<RefCell<String>>,
+}
+
+impl BufferOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written to the output stream so far.
+    pub fn out(&self) -> String {
+        self.out.borrow().clone()
+    }
+
+    /// Everything written to the error stream so far.
+    pub fn err(&self) -> String {
+        self.err.borrow().clone()
+    }
+}
+
+impl Output for BufferOutput {
+    fn write_out(&mut self, text: &str) -> io::Result<()> {
+        self.out.borrow_mut().push_str(text);
+        Ok(())
+    }
+
+    fn write_err(&mut self, text: &str) -> io::Result<()> {
+        self.err.borrow_mut().push_str(text);
+        Ok(())
+    }
+}
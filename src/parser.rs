@@ -1,118 +1,243 @@
 use crate::expression::*;
 use crate::literal::*;
 use crate::lox_error::*;
+use crate::span::*;
 use crate::statement::*;
 use crate::token::*;
 use crate::token_type::*;
 
+/// Upper bound on the number of parameters and call arguments.
+pub const MAX_ARGUMENTS: usize = 255;
+
 #[derive(PartialEq, Debug)]
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    // Syntax errors recorded so far; parsing carries on after each one.
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            current: 0,
+            errors: Vec::new(),
+        }
     }
 
-    // pub fn or(&mut self) -> Result<Box<Expr>, LoxError> {
-    //     let mut expr = self.and()?;
+    ///
+    ///logic_or → logic_and ( "or" logic_and )* ;
+    ///
+    pub fn or(&mut self) -> Result<Box<Expr>, ParseError> {
+        let mut expr = self.and()?;
 
-    //     while self.if_match(&[TokenType::OR]) {
-    //         let operator = self.previous();
-    //         let right = self.and()?;
-    //         expr = Box::new(Expr::Logical {
-    //             left: expr.clone(),
-    //             operator,
-    //             right,
-    //         });
-    //     }
+        while self.if_match(&[TokenType::OR]) {
+            let operator = self.previous();
+            let right = self.and()?;
+            expr = Box::new(Expr::Logical {
+                left: expr,
+                operator,
+                right,
+            });
+        }
 
-    //     Ok(expr)
-    // }
+        Ok(expr)
+    }
 
-    pub fn parse_expression(&mut self) -> Result<Box<Expr>, LoxError> {
+    pub fn parse_expression(&mut self) -> Result<Box<Expr>, ParseError> {
         self.expression()
     }
 
-    //pub fn and(&mut self) -> Result<Box<Expr>, LoxError> {
-    //    let mut expr = self.equality()?;
+    ///
+    ///logic_and → equality ( "and" equality )* ;
+    ///
+    pub fn and(&mut self) -> Result<Box<Expr>, ParseError> {
+        let mut expr = self.equality()?;
 
-    //    while self.if_match(&[TokenType::AND]) {
-    //        let operator = self.previous();
-    //        let right = self.equality()?;
-    //            expr = Box::new(Expr::Logical {
-    //            left: expr.clone(),
-    //            operator,
-    //            right,
-    //        });
-    //    }
+        while self.if_match(&[TokenType::AND]) {
+            let operator = self.previous();
+            let right = self.equality()?;
+            expr = Box::new(Expr::Logical {
+                left: expr,
+                operator,
+                right,
+            });
+        }
 
-    //    Ok(expr)
-    //}
+        Ok(expr)
+    }
 
-    pub fn assignment(&mut self) -> Result<Box<Expr>, LoxError> {
-        let expr = self.equality()?;
+    pub fn assignment(&mut self) -> Result<Box<Expr>, ParseError> {
+        let expr = self.or()?;
 
         if self.if_match(&[TokenType::EQUAL]) {
-            let _equals = self.previous();
+            let equals = self.previous();
             let value = self.assignment()?;
 
             match *expr {
-                Expr::Variable { name } => {
-                    return Ok(Box::new(Expr::Assign { name, value }));
+                Expr::Variable { name, .. } => {
+                    return Ok(Box::new(Expr::Assign {
+                        id: Expr::next_id(),
+                        name,
+                        value,
+                    }));
+                }
+                Expr::Get { object, name } => {
+                    return Ok(Box::new(Expr::Set {
+                        object,
+                        name,
+                        value,
+                    }));
                 }
                 _ => {
-                    return Err(LoxError::RuntimeError(String::from(
-                        "Invalid assignment target",
-                    )))
+                    return Err(ParseError::SyntaxError(
+                        equals,
+                        String::from("Invalid assignment target."),
+                    ))
                 }
             }
         }
 
         Ok(expr)
     }
-    pub fn expression(&mut self) -> Result<Box<Expr>, LoxError> {
+    pub fn expression(&mut self) -> Result<Box<Expr>, ParseError> {
         self.assignment()
     }
 
-    pub fn parse_statement(&mut self) -> Result<Vec<Box<Statement>>, LoxError> {
-        let mut statements = Vec::new();
-
-        while !self.is_at_end() {
-            statements.push(self.declaration()?);
-        }
-
-        return Ok(statements);
-    }
-
-    pub fn _declaration(&mut self) -> Result<Box<Statement>, LoxError> {
-        if self.if_match(&[TokenType::VAR]) {
+    pub fn _declaration(&mut self) -> Result<Box<Statement>, ParseError> {
+        if self.if_match(&[TokenType::CLASS]) {
+            self.class_declaration()
+        } else if self.if_match(&[TokenType::FUN]) {
+            self.function("function")
+        } else if self.if_match(&[TokenType::VAR]) {
             self.var_declaration()
         } else {
             self.statement()
         }
     }
 
-    fn declaration(&mut self) -> Result<Box<Statement>, LoxError> {
+    /// Parses a declaration, or records the syntax error and skips ahead to
+    /// the next statement boundary so the rest of the input still gets
+    /// checked.
+    fn declaration(&mut self) -> Option<Box<Statement>> {
         match self._declaration() {
-            Ok(r) => Ok(r),
+            Ok(r) => Some(r),
             Err(e) => {
+                self.errors.push(e);
                 self.synchronize();
-                Err(e)
+                None
             }
         }
     }
 
-    pub fn var_declaration(&mut self) -> Result<Box<Statement>, LoxError> {
+    ///
+    ///classDecl → "class" IDENTIFIER ( "<" IDENTIFIER )? "{" function* "}" ;
+    ///
+    pub fn class_declaration(&mut self) -> Result<Box<Statement>, ParseError> {
+        let keyword = self.previous();
+        let name = self.consume(TokenType::IDENTIFIER, String::from("Expect class name."))?;
+
+        let superclass = if self.if_match(&[TokenType::LESS]) {
+            let name = self.consume(
+                TokenType::IDENTIFIER,
+                String::from("Expect superclass name."),
+            )?;
+            Some(Box::new(Expr::Variable {
+                id: Expr::next_id(),
+                name,
+            }))
+        } else {
+            None
+        };
+
+        self.consume(
+            TokenType::LEFT_BRACE,
+            String::from("Expect '{' before class body."),
+        )?;
+
+        let mut methods = Vec::new();
+        while !self.check(&TokenType::RIGHT_BRACE) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+
+        self.consume(
+            TokenType::RIGHT_BRACE,
+            String::from("Expect '}' after class body."),
+        )?;
+
+        Ok(Box::new(Statement::Class {
+            name,
+            superclass,
+            methods,
+            span: self.span_from(&keyword),
+        }))
+    }
+
+    ///
+    ///function → IDENTIFIER "(" parameters? ")" block ;
+    ///
+    /// `kind` names what is being declared in error messages.
+    pub fn function(&mut self, kind: &str) -> Result<Box<Statement>, ParseError> {
+        // Methods have no `fun` keyword in front of them.
+        let first = if self.previous().of_type == TokenType::FUN {
+            self.previous()
+        } else {
+            self.peek().clone()
+        };
+        let name = self.consume(TokenType::IDENTIFIER, format!("Expect {} name.", kind))?;
+
+        self.consume(
+            TokenType::LEFT_PAREN,
+            format!("Expect '(' after {} name.", kind),
+        )?;
+
+        let mut params = Vec::new();
+
+        if !self.check(&TokenType::RIGHT_PAREN) {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    return Err(ParseError::SyntaxError(
+                        self.peek().clone(),
+                        format!("Can't have more than {} parameters.", MAX_ARGUMENTS),
+                    ));
+                }
+
+                params.push(self.consume(
+                    TokenType::IDENTIFIER,
+                    String::from("Expect parameter name."),
+                )?);
+
+                if !self.if_match(&[TokenType::COMMA]) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(
+            TokenType::RIGHT_PAREN,
+            String::from("Expect ')' after parameters."),
+        )?;
+        self.consume(
+            TokenType::LEFT_BRACE,
+            format!("Expect '{{' before {} body.", kind),
+        )?;
+
+        let body = self.block()?;
+
+        Ok(Box::new(Statement::Function {
+            name,
+            params,
+            body: body.into(),
+            span: self.span_from(&first),
+        }))
+    }
+
+    pub fn var_declaration(&mut self) -> Result<Box<Statement>, ParseError> {
+        let keyword = self.previous();
         let name = self.consume(
             TokenType::IDENTIFIER,
-            String::from(
-                "
-                Expect variable name.
-                ",
-            ),
+            String::from("Expect variable name."),
         )?;
 
         let mut initializer: Option<Box<Expr>> = None;
@@ -126,10 +251,15 @@ impl Parser {
             String::from("Expect ';' after variable declaration"),
         )?;
 
-        Ok(Box::new(Statement::Variable { name, initializer }))
+        Ok(Box::new(Statement::Variable {
+            name,
+            initializer,
+            span: self.span_from(&keyword),
+        }))
     }
 
-    pub fn print_statement(&mut self) -> Result<Box<Statement>, LoxError> {
+    pub fn print_statement(&mut self) -> Result<Box<Statement>, ParseError> {
+        let keyword = self.previous();
         let value = self.expression()?;
 
         self.consume(
@@ -137,142 +267,193 @@ impl Parser {
             String::from("Expect ';' after value."),
         )?;
 
-        return Ok(Box::new(Statement::Print { expression: value }));
-    }
-
-    //pub fn while_statement(&mut self) -> Result<Box<Statement>, LoxError> {
-    //    self.consume(
-    //        TokenType::LEFT_PAREN,
-    //        String::from("Expect ( after while statement"),
-    //    )?;
-
-    //    let condition = self.expression()?;
-
-    //    self.consume(
-    //        TokenType::RIGHT_PAREN,
-    //        String::from("Expect ) after while statement"),
-    //    )?;
-
-    //    let body = self.statement()?;
-
-    //    return Ok(Box::new(Statement::While { condition, body }));
-    //}
-    //pub fn for_statement(&mut self) -> Result<Box<Statement>, LoxError> {
-    //    self.consume(TokenType::LEFT_PAREN, String::from("Expect ( for ."))?;
-
-    //    let initializer = if self.if_match(&[TokenType::SEMICOLON]) {
-    //        None
-    //    } else if self.if_match(&[TokenType::VAR]) {
-    //        Some(self.var_declaration()?)
-    //    } else {
-    //        Some(self.expression_statement()?)
-    //    };
-
-    //    let condition = if !self.if_match(&[TokenType::SEMICOLON]) {
-    //        Some(self.expression()?)
-    //    } else {
-    //        None
-    //    };
-
-    //    self.consume(TokenType::SEMICOLON, String::from("Expec ; after loop"))?;
-
-    //    let increment = if self.if_match(&[TokenType::RIGHT_PAREN]) {
-    //        Some(self.expression()?)
-    //    } else {
-    //        None
-    //    };
-
-    //    self.consume(
-    //        TokenType::RIGHT_PAREN,
-    //        String::from("Expect ) after for clauses."),
-    //    )?;
-
-    //    let mut body = self.statement()?;
-
-    //    if let Some(increment) = increment {
-    //        body = Box::new(Statement::Block {
-    //            statements: vec![
-    //                body,
-    //                Box::new(Statement::Expression {
-    //                    expression: increment,
-    //                }),
-    //            ],
-    //        })
-    //    }
-
-    //    if let Some(condition) = condition {
-    //        body = Box::new(Statement::While { condition, body });
-    //    } else {
-    //        body = Box::new(Statement::While {
-    //            condition: Box::new(Expr::Literal {
-    //                literal: Literal::Boolean(true),
-    //            }),
-    //            body,
-    //        });
-    //    }
-
-    //    if let Some(initializer) = initializer {
-    //        body = Box::new(Statement::Block {
-    //            statements: vec![initializer, body],
-    //        });
-    //    }
-    //    Ok(body)
-    //}
-
-    pub fn statement(&mut self) -> Result<Box<Statement>, LoxError> {
-        //if self.if_match(&[TokenType::IF]) {
-        //    return self.if_statement();
-        //}
-
-        //if self.if_match(&[TokenType::FOR]) {
-        //    return self.for_statement();
-        //}
-
-        //if self.if_match(&[TokenType::WHILE]) {
-        //    return self.while_statement();
-        //}
+        Ok(Box::new(Statement::Print {
+            expression: value,
+            span: self.span_from(&keyword),
+        }))
+    }
+
+    pub fn return_statement(&mut self) -> Result<Box<Statement>, ParseError> {
+        let keyword = self.previous();
+
+        let value = if !self.check(&TokenType::SEMICOLON) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(
+            TokenType::SEMICOLON,
+            String::from("Expect ';' after return value."),
+        )?;
+
+        Ok(Box::new(Statement::Return {
+            span: self.span_from(&keyword),
+            keyword,
+            value,
+        }))
+    }
+
+    pub fn while_statement(&mut self) -> Result<Box<Statement>, ParseError> {
+        let keyword = self.previous();
+        self.consume(
+            TokenType::LEFT_PAREN,
+            String::from("Expect '(' after 'while'."),
+        )?;
+
+        let condition = self.expression()?;
+
+        self.consume(
+            TokenType::RIGHT_PAREN,
+            String::from("Expect ')' after condition."),
+        )?;
+
+        let body = self.statement()?;
+
+        Ok(Box::new(Statement::While {
+            condition,
+            body,
+            span: self.span_from(&keyword),
+        }))
+    }
+
+    /// Desugars `for (init; cond; incr) body` into
+    /// `{ init; while (cond) { body; incr; } }`.
+    pub fn for_statement(&mut self) -> Result<Box<Statement>, ParseError> {
+        let keyword = self.previous();
+        self.consume(
+            TokenType::LEFT_PAREN,
+            String::from("Expect '(' after 'for'."),
+        )?;
+
+        let initializer = if self.if_match(&[TokenType::SEMICOLON]) {
+            None
+        } else if self.if_match(&[TokenType::VAR]) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if !self.check(&TokenType::SEMICOLON) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(
+            TokenType::SEMICOLON,
+            String::from("Expect ';' after loop condition."),
+        )?;
+
+        let increment = if !self.check(&TokenType::RIGHT_PAREN) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(
+            TokenType::RIGHT_PAREN,
+            String::from("Expect ')' after for clauses."),
+        )?;
+
+        let mut body = self.statement()?;
+        // The statements the loop turns into all cover the whole loop.
+        let span = self.span_from(&keyword);
+
+        if let Some(increment) = increment {
+            body = Box::new(Statement::Block {
+                statements: vec![
+                    body,
+                    Box::new(Statement::Expression {
+                        span: increment.span(),
+                        expression: increment,
+                    }),
+                ],
+                span,
+            })
+        }
+
+        let condition = condition.unwrap_or_else(|| {
+            Box::new(Expr::Literal {
+                literal: Literal::Boolean(true),
+                span: keyword.span,
+            })
+        });
+        body = Box::new(Statement::While {
+            condition,
+            body,
+            span,
+        });
+
+        if let Some(initializer) = initializer {
+            body = Box::new(Statement::Block {
+                statements: vec![initializer, body],
+                span,
+            });
+        }
+        Ok(body)
+    }
+
+    pub fn statement(&mut self) -> Result<Box<Statement>, ParseError> {
+        if self.if_match(&[TokenType::IF]) {
+            return self.if_statement();
+        }
+
+        if self.if_match(&[TokenType::FOR]) {
+            return self.for_statement();
+        }
+
+        if self.if_match(&[TokenType::WHILE]) {
+            return self.while_statement();
+        }
         if self.if_match(&[TokenType::PRINT]) {
-            return self.print_statement();
+            self.print_statement()
+        } else if self.if_match(&[TokenType::RETURN]) {
+            self.return_statement()
         } else if self.if_match(&[TokenType::LEFT_BRACE]) {
-            return Ok(Box::new(Statement::Block {
+            let brace = self.previous();
+            Ok(Box::new(Statement::Block {
                 statements: self.block()?,
-            }));
+                span: self.span_from(&brace),
+            }))
         } else {
-            return self.expression_statement();
+            self.expression_statement()
         }
     }
-    //pub fn if_statement(&mut self) -> Result<Box<Statement>, LoxError> {
-    //    self.consume(
-    //        TokenType::LEFT_PAREN,
-    //        String::from("Expect '(' after 'if' keyword."),
-    //    )?;
-
-    //    let condition = self.expression()?;
+    pub fn if_statement(&mut self) -> Result<Box<Statement>, ParseError> {
+        let keyword = self.previous();
+        self.consume(
+            TokenType::LEFT_PAREN,
+            String::from("Expect '(' after 'if' keyword."),
+        )?;
 
-    //    self.consume(
-    //        TokenType::RIGHT_PAREN,
-    //        String::from("Expect ')' after 'if' condition."),
-    //    )?;
+        let condition = self.expression()?;
 
-    //    let then_branch = self.statement()?;
+        self.consume(
+            TokenType::RIGHT_PAREN,
+            String::from("Expect ')' after 'if' condition."),
+        )?;
 
-    //    let else_branch = if self.if_match(&[TokenType::ELSE]) {
-    //        Some(self.statement()?)
-    //    } else {
-    //        None
-    //    };
+        let then_branch = self.statement()?;
 
-    //    return Ok(Box::new(Statement::If {
-    //        condition,
-    //        then_branch,
-    //        else_branch,
-    //    }));
-    //}
-    pub fn block(&mut self) -> Result<Vec<Box<Statement>>, LoxError> {
+        let else_branch = if self.if_match(&[TokenType::ELSE]) {
+            Some(self.statement()?)
+        } else {
+            None
+        };
+
+        Ok(Box::new(Statement::If {
+            condition,
+            then_branch,
+            else_branch,
+            span: self.span_from(&keyword),
+        }))
+    }
+    pub fn block(&mut self) -> Result<Vec<Box<Statement>>, ParseError> {
         let mut statements = Vec::new();
 
         while !self.check(&TokenType::RIGHT_BRACE) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            statements.extend(self.declaration());
         }
 
         self.consume(
@@ -283,7 +464,8 @@ impl Parser {
         Ok(statements)
     }
 
-    pub fn expression_statement(&mut self) -> Result<Box<Statement>, LoxError> {
+    pub fn expression_statement(&mut self) -> Result<Box<Statement>, ParseError> {
+        let first = self.peek().clone();
         let expr = self.expression()?;
 
         self.consume(
@@ -291,26 +473,34 @@ impl Parser {
             String::from("Expect ';' after expression"),
         )?;
 
-        return Ok(Box::new(Statement::Expression { expression: expr }));
+        Ok(Box::new(Statement::Expression {
+            expression: expr,
+            span: self.span_from(&first),
+        }))
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Box<Statement>>, LoxError> {
+    /// Parses the whole token stream.
+    ///
+    /// Returns every statement that parsed cleanly together with all the
+    /// syntax errors found along the way, in source order. The statements
+    /// are only safe to run when the error list is empty.
+    pub fn parse(&mut self) -> (Vec<Box<Statement>>, Vec<ParseError>) {
         let mut statements: Vec<Box<Statement>> = vec![];
 
         while !self.is_at_end() {
-            statements.push(self.declaration()?);
+            statements.extend(self.declaration());
         }
 
-        return Ok(statements);
+        (statements, std::mem::take(&mut self.errors))
     }
 
     ///
     ///equality → comparison ( ( "!=" | "==" ) comparison )* ;
     ///
-    pub fn equality(&mut self) -> Result<Box<Expr>, LoxError> {
+    pub fn equality(&mut self) -> Result<Box<Expr>, ParseError> {
         let mut expr = self.comparison()?;
 
-        while self.if_match(&vec![TokenType::BANG_EQUAL, TokenType::EQUAL_EQUAL]) {
+        while self.if_match(&[TokenType::BANG_EQUAL, TokenType::EQUAL_EQUAL]) {
             let operator = self.previous().clone();
             let right = self.comparison()?;
 
@@ -360,7 +550,12 @@ impl Parser {
     pub fn previous(&self) -> Token {
         self.tokens[self.current - 1].clone()
     }
-    pub fn comparison(&mut self) -> Result<Box<Expr>, LoxError> {
+
+    /// The span from `first` to the last consumed token.
+    fn span_from(&self, first: &Token) -> Span {
+        first.span.to(&self.tokens[self.current - 1].span)
+    }
+    pub fn comparison(&mut self) -> Result<Box<Expr>, ParseError> {
         let mut expr = self.addition()?;
 
         while self.if_match(&[
@@ -373,9 +568,9 @@ impl Parser {
             let right = self.addition()?;
 
             expr = Box::new(Expr::Binary {
-                right: expr,
+                left: expr,
                 operator,
-                left: right,
+                right,
             });
         }
         Ok(expr)
@@ -391,65 +586,160 @@ impl Parser {
     /// then we grab the token and recursively call unary() to
     /// parse the operand. Finally, wrap that all up in an unary
     /// expression syntax tree.
-    pub fn unary(&mut self) -> Result<Box<Expr>, LoxError> {
-        if self.if_match(&vec![TokenType::BANG, TokenType::MINUS]) {
+    pub fn unary(&mut self) -> Result<Box<Expr>, ParseError> {
+        if self.if_match(&[TokenType::BANG, TokenType::MINUS]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
 
             return Ok(Box::new(Expr::Unary { operator, right }));
         }
-        self.primary()
+        self.call()
+    }
+
+    ///
+    ///call → primary ( "(" arguments? ")" | "." IDENTIFIER )* ;
+    ///
+    pub fn call(&mut self) -> Result<Box<Expr>, ParseError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.if_match(&[TokenType::LEFT_PAREN]) {
+                expr = self.finish_call(expr)?;
+            } else if self.if_match(&[TokenType::DOT]) {
+                let name = self.consume(
+                    TokenType::IDENTIFIER,
+                    String::from("Expect property name after '.'."),
+                )?;
+                expr = Box::new(Expr::Get { object: expr, name });
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
     }
 
-    pub fn primary(&mut self) -> Result<Box<Expr>, LoxError> {
-        if self.if_match(&vec![TokenType::FALSE]) {
+    fn finish_call(&mut self, callee: Box<Expr>) -> Result<Box<Expr>, ParseError> {
+        let mut arguments = Vec::new();
+
+        if !self.check(&TokenType::RIGHT_PAREN) {
+            loop {
+                if arguments.len() >= MAX_ARGUMENTS {
+                    return Err(ParseError::SyntaxError(
+                        self.peek().clone(),
+                        format!("Can't have more than {} arguments.", MAX_ARGUMENTS),
+                    ));
+                }
+
+                arguments.push(self.expression()?);
+
+                if !self.if_match(&[TokenType::COMMA]) {
+                    break;
+                }
+            }
+        }
+
+        let paren = self.consume(
+            TokenType::RIGHT_PAREN,
+            String::from("Expect ')' after arguments."),
+        )?;
+
+        Ok(Box::new(Expr::Call {
+            callee,
+            paren,
+            arguments,
+        }))
+    }
+
+    pub fn primary(&mut self) -> Result<Box<Expr>, ParseError> {
+        if self.if_match(&[TokenType::FALSE]) {
             return Ok(Box::new(Expr::Literal {
                 literal: Literal::Boolean(false),
+                span: self.previous().span,
             }));
         }
-        if self.if_match(&vec![TokenType::TRUE]) {
+        if self.if_match(&[TokenType::TRUE]) {
             return Ok(Box::new(Expr::Literal {
                 literal: Literal::Boolean(true),
+                span: self.previous().span,
             }));
         }
-        if self.if_match(&vec![TokenType::NIL]) {
+        if self.if_match(&[TokenType::NIL]) {
             return Ok(Box::new(Expr::Literal {
                 literal: Literal::None,
+                span: self.previous().span,
             }));
         }
-        if self.if_match(&vec![TokenType::NUMBER, TokenType::STRING]) {
-            if let Some(l) = self.previous().clone().literal {
-                return Ok(Box::new(Expr::Literal { literal: l }));
+        if self.if_match(&[TokenType::NUMBER, TokenType::STRING]) {
+            let token = self.previous();
+            if let Some(l) = token.literal {
+                return Ok(Box::new(Expr::Literal {
+                    literal: l,
+                    span: token.span,
+                }));
             }
         }
 
-        if self.if_match(&vec![TokenType::IDENTIFIER]) {
+        if self.if_match(&[TokenType::SUPER]) {
+            let keyword = self.previous();
+            self.consume(TokenType::DOT, String::from("Expect '.' after 'super'."))?;
+            let method = self.consume(
+                TokenType::IDENTIFIER,
+                String::from("Expect superclass method name."),
+            )?;
+
+            return Ok(Box::new(Expr::Super {
+                id: Expr::next_id(),
+                keyword,
+                method,
+            }));
+        }
+
+        if self.if_match(&[TokenType::THIS]) {
+            return Ok(Box::new(Expr::This {
+                id: Expr::next_id(),
+                keyword: self.previous(),
+            }));
+        }
+
+        if self.if_match(&[TokenType::IDENTIFIER]) {
             return Ok(Box::new(Expr::Variable {
+                id: Expr::next_id(),
                 name: self.previous().clone(),
             }));
         }
 
-        if self.if_match(&vec![TokenType::LEFT_PAREN]) {
-            let expr = self.expression();
-
-            self.consume(TokenType::RIGHT_PAREN, String::from("Expected ')'"))?;
-            return Ok(Box::new(Expr::Grouping { expression: expr? }));
+        if self.if_match(&[TokenType::LEFT_PAREN]) {
+            let left_paren = self.previous();
+            let expr = self.expression()?;
+
+            let right_paren = self.consume(
+                TokenType::RIGHT_PAREN,
+                String::from("Expect ')' after expression."),
+            )?;
+            return Ok(Box::new(Expr::Grouping {
+                expression: expr,
+                span: left_paren.span.to(&right_paren.span),
+            }));
         }
-        Err(LoxError::RuntimeError(String::from("Expected expression.")))
+        Err(ParseError::SyntaxError(
+            self.peek().clone(),
+            String::from("Expected expression."),
+        ))
     }
 
-    pub fn consume(&mut self, token_type: TokenType, message: String) -> Result<Token, LoxError> {
+    pub fn consume(&mut self, token_type: TokenType, message: String) -> Result<Token, ParseError> {
         if self.check(&token_type) {
             Ok(self.advance())
         } else {
-            Err(LoxError::RuntimeError(message))
+            Err(ParseError::SyntaxError(self.peek().clone(), message))
         }
     }
 
-    pub fn multiplication(&mut self) -> Result<Box<Expr>, LoxError> {
+    pub fn multiplication(&mut self) -> Result<Box<Expr>, ParseError> {
         let mut expr = self.unary()?;
 
-        while self.if_match(&vec![TokenType::SLASH, TokenType::STAR]) {
+        while self.if_match(&[TokenType::SLASH, TokenType::STAR]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
 
@@ -488,10 +778,10 @@ impl Parser {
         }
     }
 
-    pub fn addition(&mut self) -> Result<Box<Expr>, LoxError> {
+    pub fn addition(&mut self) -> Result<Box<Expr>, ParseError> {
         let mut expr = self.multiplication()?;
 
-        while self.if_match(&vec![TokenType::PLUS, TokenType::MINUS]) {
+        while self.if_match(&[TokenType::PLUS, TokenType::MINUS]) {
             let operator = self.previous().clone();
             let right = self.multiplication()?;
 
@@ -0,0 +1,272 @@
+use crate::ast_printer::*;
+use crate::diagnostic::*;
+use crate::engine::*;
+use crate::lox_error::*;
+use crate::scanner::*;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+const HELP: &str = "\
+:tokens <source>  show the tokens <source> scans to
+:ast <source>     show the syntax tree <source> parses to
+:tree <source>    same as :ast, one node per line
+:env              list the global variables
+:reset            forget everything defined so far
+:load <file>      run a script in this session
+:history          show previous entries
+:help             show this message
+:quit             leave (Ctrl-D works too)
+";
+
+/// Interactive session on top of an `Engine`.
+///
+/// Input is read a line at a time. A line that leaves a bracket, brace or
+/// string open is continued on the next one before anything runs. Lines
+/// starting with `:` are commands for the REPL itself rather than Lox code.
+/// Both prompts and results go through the engine's `Output`.
+pub struct Repl {
+    engine: Engine,
+    // File entries are appended to, and read back from on start-up.
+    history_file: Option<PathBuf>,
+    history: Vec<String>,
+    color: bool,
+}
+
+impl Repl {
+    pub fn new(engine: Engine) -> Self {
+        Self {
+            engine,
+            history_file: None,
+            history: Vec::new(),
+            color: false,
+        }
+    }
+
+    /// Keeps history in `path`, loading whatever it already holds.
+    pub fn with_history(mut self, path: PathBuf) -> Self {
+        if let Ok(contents) = fs::read_to_string(&path) {
+            self.history = contents.lines().map(String::from).collect();
+        }
+        self.history_file = Some(path);
+        self
+    }
+
+    /// Colors diagnostics with ANSI escapes.
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Reads and runs entries from `input` until it runs dry or `:quit`.
+    ///
+    /// Only failures to read input or write output are returned; errors in
+    /// the entries themselves are reported and the session carries on.
+    pub fn run<R: BufRead>(&mut self, mut input: R) -> io::Result<()> {
+        let mut entry = String::new();
+
+        loop {
+            let prompt = if entry.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION_PROMPT
+            };
+            self.engine.output().write_out(prompt)?;
+            self.engine.output().flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                // Ctrl-D. Whatever was left unfinished still gets a chance
+                // to run, if only to report why it is incomplete.
+                self.engine.output().write_out("\n")?;
+                if !entry.trim().is_empty() {
+                    self.eval(&entry)?;
+                }
+                return Ok(());
+            }
+
+            entry.push_str(&line);
+            if is_incomplete(&entry) {
+                continue;
+            }
+
+            let source = std::mem::take(&mut entry);
+            if source.trim().is_empty() {
+                continue;
+            }
+
+            self.remember(&source)?;
+
+            if let Some(command) = source.trim().strip_prefix(':') {
+                if !self.command(command)? {
+                    return Ok(());
+                }
+            } else {
+                self.eval(&source)?;
+            }
+        }
+    }
+
+    /// Runs a `:command`. Returns false when the session should end.
+    fn command(&mut self, command: &str) -> io::Result<bool> {
+        let (name, argument) = match command.find(char::is_whitespace) {
+            Some(i) => (&command[..i], command[i..].trim()),
+            None => (command, ""),
+        };
+
+        match name {
+            "quit" | "q" => return Ok(false),
+            "help" => self.engine.output().write_out(HELP)?,
+            "tokens" => {
+                let mut scanner = Scanner::new(argument.to_string());
+                let mut listing = String::new();
+                for token in scanner.scan_tokens() {
+                    listing.push_str(&format!(
+                        "{}:{} {:?} {}\n",
+                        token.span.line, token.span.column, token.of_type, token.lexeme
+                    ));
+                }
+                self.engine.output().write_out(&listing)?;
+            }
+            "ast" | "tree" => {
+                let layout = if name == "ast" {
+                    Layout::Lisp
+                } else {
+                    Layout::Tree
+                };
+
+                let tree = match parse(argument) {
+                    Ok(statements) => AstPrinter::new(layout).print_statements(&statements),
+                    // A bare expression, with no `;`, is shown on its own.
+                    Err(e) => match parse_expression(argument) {
+                        Ok(expression) => {
+                            AstPrinter::new(layout).print_expression(&expression) + "\n"
+                        }
+                        Err(_) => {
+                            self.report(&e, "<repl>", argument)?;
+                            return Ok(true);
+                        }
+                    },
+                };
+                self.engine.output().write_out(&tree)?;
+            }
+            "env" => {
+                let mut listing = String::new();
+                for (name, value) in self.engine.globals() {
+                    listing.push_str(&format!("{} = {}\n", name, value));
+                }
+                self.engine.output().write_out(&listing)?;
+            }
+            "reset" => self.engine.reset(),
+            "load" if !argument.is_empty() => match fs::read_to_string(argument) {
+                Ok(source) => {
+                    let source = skip_shebang(&source);
+                    if let Err(e) = self.engine.run(source) {
+                        self.report(&e, argument, source)?;
+                    }
+                }
+                Err(e) => self
+                    .engine
+                    .output()
+                    .write_err(&format!("Error reading {}: {}\n", argument, e))?,
+            },
+            "history" => {
+                let listing: String = self
+                    .history
+                    .iter()
+                    .map(|line| format!("{}\n", line))
+                    .collect();
+                self.engine.output().write_out(&listing)?;
+            }
+            _ => self
+                .engine
+                .output()
+                .write_err(&format!("Unknown command ':{}'. Try :help.\n", command))?,
+        }
+
+        Ok(true)
+    }
+
+    fn eval(&mut self, source: &str) -> io::Result<()> {
+        match self.engine.eval(source) {
+            Ok(Some(value)) => self.engine.output().write_out(&format!("{}\n", value)),
+            Ok(None) => Ok(()),
+            Err(e) => self.report(&e, "<repl>", source),
+        }
+    }
+
+    fn report(&mut self, error: &LoxError, file_name: &str, source: &str) -> io::Result<()> {
+        let rendered = render_error(error, file_name, source, self.color);
+        self.engine.output().write_err(&rendered)
+    }
+
+    /// Adds `entry` to the history. If the history file can't be written
+    /// the session goes on without it.
+    fn remember(&mut self, entry: &str) -> io::Result<()> {
+        let lines: Vec<String> = entry.lines().map(String::from).collect();
+
+        if let Some(path) = &self.history_file {
+            let saved = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| lines.iter().try_for_each(|line| writeln!(file, "{}", line)));
+
+            if let Err(e) = saved {
+                let warning = format!("Not saving history to {}: {}\n", path.display(), e);
+                self.history_file = None;
+                self.engine.output().write_err(&warning)?;
+            }
+        }
+
+        self.history.extend(lines);
+        Ok(())
+    }
+}
+
+/// Whether `source` stops inside a string or with more `(` or `{` opened
+/// than closed, meaning the user has not finished typing yet.
+pub fn is_incomplete(source: &str) -> bool {
+    let mut depth: i32 = 0;
+    let mut in_string = false;
+    // Raw strings, `r"..."`, have no escapes. As in the scanner, the `r`
+    // has to start a token; the one ending `letter"` is part of a name.
+    let mut raw = false;
+    let mut raw_prefix = false;
+    let mut in_identifier = false;
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            match c {
+                '"' => in_string = false,
+                '\\' if !raw => {
+                    chars.next();
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let starts_token = !(in_identifier && (c.is_alphanumeric() || c == '_'));
+        in_identifier = !starts_token || c.is_alphabetic() || c == '_';
+
+        match c {
+            '"' => {
+                in_string = true;
+                raw = raw_prefix;
+            }
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|&c| c != '\n').is_some() {},
+            _ => {}
+        }
+        raw_prefix = starts_token && c == 'r';
+    }
+
+    in_string || depth > 0
+}
@@ -0,0 +1,478 @@
+use crate::expression::Visitable;
+use crate::expression::*;
+use crate::interpreter::*;
+use crate::literal::*;
+use crate::lox_error::*;
+use crate::statement::Visitable as VisitableStatement;
+use crate::statement::*;
+use crate::token::*;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+/// A variable declared in a local scope.
+struct Local {
+    // False while the variable is declared but its initializer has not
+    // been resolved yet.
+    defined: bool,
+    // Where the interpreter keeps it in the scope's environment.
+    slot: usize,
+}
+
+/// Static pass run between parsing and interpreting.
+///
+/// Works out how many scopes separate every local variable use from its
+/// declaration, and which slot of that scope holds it, and hands both to
+/// the interpreter, so lookups no longer depend on what happens to be
+/// defined at run time. Variables it cannot find in any enclosing block are
+/// left alone and treated as globals.
+pub struct Resolver<'a> {
+    interpreter: &'a mut Interpreter,
+    // One map per enclosing block.
+    scopes: Vec<HashMap<String, Local>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+}
+
+impl<'a> Resolver<'a> {
+    /// Starts a resolver that records what it finds in a fresh table of
+    /// `interpreter`'s, which the next script run there uses.
+    pub fn new(interpreter: &'a mut Interpreter) -> Self {
+        interpreter.begin_resolution();
+
+        Self {
+            interpreter,
+            scopes: Vec::new(),
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &[Box<Statement>]) -> Result<(), LoxError> {
+        for statement in statements {
+            self.resolve_statement(statement)?;
+        }
+
+        Ok(())
+    }
+
+    fn resolve_statement(&mut self, statement: &Statement) -> Result<(), LoxError> {
+        statement.accept(self)
+    }
+
+    fn resolve_expression(&mut self, expression: &Expr) -> Result<(), LoxError> {
+        expression.accept(self)
+    }
+
+    fn resolve_function(
+        &mut self,
+        params: &[Token],
+        body: &[Box<Statement>],
+        kind: FunctionType,
+    ) -> Result<(), LoxError> {
+        let enclosing_function = self.current_function;
+        self.current_function = kind;
+
+        self.begin_scope();
+        let result = params
+            .iter()
+            .try_for_each(|param| {
+                self.declare(param)?;
+                self.define(param);
+                Ok(())
+            })
+            .and_then(|_| self.resolve(body));
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+        result
+    }
+
+    fn resolve_local(&mut self, id: usize, name: &Token) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(local) = scope.get(&name.lexeme) {
+                self.interpreter.resolve(id, depth, local.slot);
+                return;
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) -> Result<(), LoxError> {
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.contains_key(&name.lexeme) {
+                return Err(LoxError::BindingError(
+                    name.clone(),
+                    String::from("Already a variable with this name in this scope."),
+                ));
+            }
+
+            let slot = scope.len();
+            scope.insert(
+                name.lexeme.clone(),
+                Local {
+                    defined: false,
+                    slot,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.lexeme))
+        {
+            local.defined = true;
+        }
+    }
+
+    /// Declares and defines `this` or `super` in the innermost scope.
+    fn define_hidden(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            let slot = scope.len();
+            scope.insert(
+                name.to_string(),
+                Local {
+                    defined: true,
+                    slot,
+                },
+            );
+        }
+    }
+}
+
+impl StmtVisitor for Resolver<'_> {
+    type Value = ();
+    type Error = LoxError;
+
+    fn visit_block_statement(
+        &mut self,
+        _stmt: &Statement,
+        statements: &[Box<Statement>],
+    ) -> Result<(), LoxError> {
+        self.begin_scope();
+        let result = self.resolve(statements);
+        self.end_scope();
+
+        result
+    }
+
+    fn visit_expression_stmt(
+        &mut self,
+        _stmt: &Statement,
+        expression: &Expr,
+    ) -> Result<(), LoxError> {
+        self.resolve_expression(expression)?;
+        Ok(())
+    }
+
+    fn visit_print_stmt(&mut self, _stmt: &Statement, expression: &Expr) -> Result<(), LoxError> {
+        self.resolve_expression(expression)?;
+        Ok(())
+    }
+
+    fn visit_var_stmt(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        initializer: &Option<Box<Expr>>,
+    ) -> Result<(), LoxError> {
+        self.declare(name)?;
+
+        if let Some(initializer) = initializer {
+            self.resolve_expression(initializer)?;
+        }
+
+        self.define(name);
+        Ok(())
+    }
+
+    fn visit_if_statement(
+        &mut self,
+        _stmt: &Statement,
+        condition: &Expr,
+        then_branch: &Statement,
+        else_branch: &Option<Box<Statement>>,
+    ) -> Result<(), LoxError> {
+        self.resolve_expression(condition)?;
+        self.resolve_statement(then_branch)?;
+
+        if let Some(else_branch) = else_branch {
+            self.resolve_statement(else_branch)?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_while_statement(
+        &mut self,
+        _stmt: &Statement,
+        condition: &Expr,
+        body: &Statement,
+    ) -> Result<(), LoxError> {
+        self.resolve_expression(condition)?;
+        self.resolve_statement(body)?;
+        Ok(())
+    }
+
+    fn visit_function_statement(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        params: &[Token],
+        body: &Rc<[Box<Statement>]>,
+    ) -> Result<(), LoxError> {
+        // Defined before the body is resolved so the function can recurse.
+        self.declare(name)?;
+        self.define(name);
+
+        self.resolve_function(params, body, FunctionType::Function)?;
+        Ok(())
+    }
+
+    fn visit_return_statement(
+        &mut self,
+        _stmt: &Statement,
+        keyword: &Token,
+        value: &Option<Box<Expr>>,
+    ) -> Result<(), LoxError> {
+        if self.current_function == FunctionType::None {
+            return Err(LoxError::BindingError(
+                keyword.clone(),
+                String::from("Can't return from top-level code."),
+            ));
+        }
+
+        if let Some(value) = value {
+            if self.current_function == FunctionType::Initializer {
+                return Err(LoxError::BindingError(
+                    keyword.clone(),
+                    String::from("Can't return a value from an initializer."),
+                ));
+            }
+
+            self.resolve_expression(value)?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_class_statement(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        superclass: &Option<Box<Expr>>,
+        methods: &[Box<Statement>],
+    ) -> Result<(), LoxError> {
+        self.declare(name)?;
+        self.define(name);
+
+        let enclosing_class = self.current_class;
+        self.current_class = ClassType::Class;
+
+        if let Some(superclass) = superclass {
+            if let Expr::Variable {
+                name: superclass_name,
+                ..
+            } = superclass.as_ref()
+            {
+                if superclass_name.lexeme == name.lexeme {
+                    self.current_class = enclosing_class;
+                    return Err(LoxError::BindingError(
+                        superclass_name.clone(),
+                        String::from("A class can't inherit from itself."),
+                    ));
+                }
+            }
+
+            self.current_class = ClassType::Subclass;
+            self.resolve_expression(superclass)?;
+
+            self.begin_scope();
+            self.define_hidden("super");
+        }
+
+        // Methods close over a scope holding nothing but `this`.
+        self.begin_scope();
+        self.define_hidden("this");
+
+        let result = methods.iter().try_for_each(|method| match method.as_ref() {
+            Statement::Function {
+                name, params, body, ..
+            } => {
+                let kind = if name.lexeme == "init" {
+                    FunctionType::Initializer
+                } else {
+                    FunctionType::Method
+                };
+                self.resolve_function(params, body, kind)
+            }
+            _ => Ok(()),
+        });
+
+        self.end_scope();
+        if superclass.is_some() {
+            self.end_scope();
+        }
+        self.current_class = enclosing_class;
+
+        result
+    }
+}
+
+impl ExprVisitor for Resolver<'_> {
+    type Value = ();
+    type Error = LoxError;
+
+    fn visit_assign_expression(
+        &mut self,
+        id: usize,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<(), LoxError> {
+        self.resolve_expression(value)?;
+        self.resolve_local(id, name);
+        Ok(())
+    }
+
+    fn visit_binary_expression(
+        &mut self,
+        left: &Expr,
+        _operator: &Token,
+        right: &Expr,
+    ) -> Result<(), LoxError> {
+        self.resolve_expression(left)?;
+        self.resolve_expression(right)?;
+        Ok(())
+    }
+
+    fn visit_logical_expression(
+        &mut self,
+        left: &Expr,
+        _operator: &Token,
+        right: &Expr,
+    ) -> Result<(), LoxError> {
+        self.resolve_expression(left)?;
+        self.resolve_expression(right)?;
+        Ok(())
+    }
+
+    fn visit_call_expression(
+        &mut self,
+        callee: &Expr,
+        _paren: &Token,
+        arguments: &[Box<Expr>],
+    ) -> Result<(), LoxError> {
+        self.resolve_expression(callee)?;
+
+        for argument in arguments {
+            self.resolve_expression(argument)?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_get_expression(&mut self, object: &Expr, _name: &Token) -> Result<(), LoxError> {
+        self.resolve_expression(object)?;
+        Ok(())
+    }
+
+    fn visit_set_expression(
+        &mut self,
+        object: &Expr,
+        _name: &Token,
+        value: &Expr,
+    ) -> Result<(), LoxError> {
+        self.resolve_expression(value)?;
+        self.resolve_expression(object)?;
+        Ok(())
+    }
+
+    fn visit_this_expression(&mut self, id: usize, keyword: &Token) -> Result<(), LoxError> {
+        if self.current_class == ClassType::None {
+            return Err(LoxError::BindingError(
+                keyword.clone(),
+                String::from("Can't use 'this' outside of a class."),
+            ));
+        }
+
+        self.resolve_local(id, keyword);
+        Ok(())
+    }
+
+    fn visit_super_expression(
+        &mut self,
+        id: usize,
+        keyword: &Token,
+        _method: &Token,
+    ) -> Result<(), LoxError> {
+        let message = match self.current_class {
+            ClassType::None => Some("Can't use 'super' outside of a class."),
+            ClassType::Class => Some("Can't use 'super' in a class with no superclass."),
+            ClassType::Subclass => None,
+        };
+
+        if let Some(message) = message {
+            return Err(LoxError::BindingError(
+                keyword.clone(),
+                String::from(message),
+            ));
+        }
+
+        self.resolve_local(id, keyword);
+        Ok(())
+    }
+
+    fn visit_group_expression(&mut self, content: &Expr) -> Result<(), LoxError> {
+        self.resolve_expression(content)?;
+        Ok(())
+    }
+
+    fn visit_literal_expression(&mut self, _literal: &Literal) -> Result<(), LoxError> {
+        Ok(())
+    }
+
+    fn visit_unary_expression(&mut self, _operator: &Token, right: &Expr) -> Result<(), LoxError> {
+        self.resolve_expression(right)?;
+        Ok(())
+    }
+
+    fn visit_variable_expression(&mut self, id: usize, name: &Token) -> Result<(), LoxError> {
+        if let Some(scope) = self.scopes.last() {
+            if scope.get(&name.lexeme).is_some_and(|local| !local.defined) {
+                return Err(LoxError::BindingError(
+                    name.clone(),
+                    String::from("Can't read local variable in its own initializer."),
+                ));
+            }
+        }
+
+        self.resolve_local(id, name);
+        Ok(())
+    }
+}
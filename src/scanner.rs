@@ -1,14 +1,31 @@
 use super::token::*;
 use super::literal::*;
+use super::lox_error::*;
+use super::span::*;
 use super::token_type::*;
 
 
+/// Splits source text into tokens in a single pass.
+///
+/// `start` and `current` are byte offsets into `source`, always on a
+/// character boundary, so tokens can be sliced straight out of it. Columns
+/// count characters, not bytes.
+///
+/// Malformed strings and characters that start no token are collected in
+/// `errors` and scanning carries on, so the parser can report its own
+/// errors alongside them.
 pub struct Scanner {
     pub source: String,
     pub tokens: Vec<Token>,
+    pub errors: Vec<ParseError>,
     pub start: usize,
     pub current: usize,
     pub line: usize,
+    // Column of the character at `current`.
+    pub column: usize,
+    // Where the token being scanned starts.
+    pub start_line: usize,
+    pub start_column: usize,
 }
 
 impl Scanner {
@@ -16,9 +33,13 @@ impl Scanner {
         Self {
             source,
             tokens: Vec::new(),
+            errors: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
         }
     }
 
@@ -26,9 +47,22 @@ impl Scanner {
         self.current >= self.source.len()
     }
 
+    /// Consumes the next character, keeping track of lines and columns.
+    /// Returns `'\0'` at the end.
     pub fn advance(&mut self) -> char {
-        self.current += 1;
-        self.source.chars().nth(self.current - 1).unwrap()
+        let c = self.peek();
+
+        if !self.is_at_end() {
+            self.current += c.len_utf8();
+
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        c
     }
 
     pub fn add_token(&mut self, of_type: TokenType, literal: Option<Literal>) {
@@ -37,18 +71,17 @@ impl Scanner {
 
     pub fn add_token_val(&mut self, of_type: TokenType, literal: Option<Literal>) {
         let text = &self.source[self.start..self.current];
+        let span = Span::new(self.start, self.current, self.start_line, self.start_column);
 
         self.tokens
-            .push(Token::new(of_type, text.to_string(), literal, self.line))
+            .push(Token::with_span(of_type, text.to_string(), literal, span))
     }
 
     pub fn advance_if_then(&mut self, next: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        } else if self.source.chars().nth(self.current).unwrap() != next {
-            return false;
+        if self.is_at_end() || self.peek() != next {
+            false
         } else {
-            self.current += 1;
+            self.advance();
             true
         }
     }
@@ -66,14 +99,11 @@ impl Scanner {
             '+' => self.add_token(TokenType::PLUS, None),
             ';' => self.add_token(TokenType::SEMICOLON, None),
             '*' => self.add_token(TokenType::STAR, None),
-            'o' => {
-                if self.advance_if_then('r') {
-                    self.add_token(TokenType::OR, None)
-                }
-            }
             '!' => {
                 if self.advance_if_then('=') {
                     self.add_token(TokenType::BANG_EQUAL, None)
+                } else {
+                    self.add_token(TokenType::BANG, None)
                 }
             }
             '=' => {
@@ -86,11 +116,15 @@ impl Scanner {
             '<' => {
                 if self.advance_if_then('=') {
                     self.add_token(TokenType::LESS_EQUAL, None)
+                } else {
+                    self.add_token(TokenType::LESS, None)
                 }
             }
             '>' => {
                 if self.advance_if_then('=') {
                     self.add_token(TokenType::GREATER_EQUAL, None)
+                } else {
+                    self.add_token(TokenType::GREATER, None)
                 }
             }
             '/' => {
@@ -102,18 +136,19 @@ impl Scanner {
                     self.add_token(TokenType::SLASH, None);
                 }
             }
-            ' ' | '\r' | '\t' => (),
-            '\n' => self.line += 1,
+            ' ' | '\r' | '\t' | '\n' => (),
             '"' => {
                 self.string();
             }
+            'r' if self.peek() == '"' => self.raw_string(),
             _ => {
                 if self.is_digit(c) {
                     self.number();
                 } else if self.is_apha(c) {
                     self.identifier()
                 } else {
-                    print!("Unexpected character.")
+                    let span = Span::new(self.start, self.current, self.start_line, self.start_column);
+                    self.error(span, String::from("Unexpected character."));
                 }
             }
         }
@@ -124,7 +159,7 @@ impl Scanner {
             self.advance();
         }
 
-        let identifier  = self.source[self.start..self.current].trim();
+        let identifier = &self.source[self.start..self.current];
         let token_type = self.match_identifier(identifier.to_string());
         match token_type {
             Some(token_type) => self.add_token(token_type, None),
@@ -162,16 +197,20 @@ impl Scanner {
         }
     }
 
-    pub fn is_apha(&mut self, c: char) -> bool {
-        c >= 'a' && c <= 'z' || c >= 'A' && c <= 'Z' || c == '_'
+    /// Whether `c` can start an identifier: `_` or any letter, so names
+    /// can be written in any script. This is close to Unicode's XID_Start.
+    pub fn is_apha(&self, c: char) -> bool {
+        c.is_alphabetic() || c == '_'
     }
 
-    pub fn is_alphanumeric(&mut self, c: char) -> bool {
-        self.is_apha(c) || self.is_digit(c)
+    /// Whether `c` can continue an identifier, close to XID_Continue.
+    pub fn is_alphanumeric(&self, c: char) -> bool {
+        c.is_alphanumeric() || c == '_'
     }
 
-    pub fn is_digit(&mut self, c: char) -> bool {
-        c >= '0' && c <= '9'
+    /// Number literals are only ever made of ASCII digits.
+    pub fn is_digit(&self, c: char) -> bool {
+        c.is_ascii_digit()
     }
 
     pub fn number(&mut self) {
@@ -188,70 +227,166 @@ impl Scanner {
             }
         }
 
-        let lexeme = self.source[self.start..self.current]
-            .trim()
-            .chars()
-            .collect::<String>();
+        let lexeme = &self.source[self.start..self.current];
 
-        // Parses the lexeme to an i32 type.
-        //
-        // TODO: f32 as well as f64 support.
-
-        let parsed_lexeme = lexeme.parse::<i32>().expect("Unexpected parsing behaviour");
+        // The lexeme is only ever digits with an optional fractional part,
+        // which always parses as an f64.
+        let parsed_lexeme = lexeme
+            .parse::<f64>()
+            .expect("Number lexeme should be a valid f64");
         self.add_token(TokenType::NUMBER, Some(Literal::Number(parsed_lexeme)));
     }
 
-    pub fn peek_next(&mut self) -> char {
-        if self.current + 1 >= self.source.len() {
-            let character = '\0';
-            return character;
-        } else {
-            self.source.chars().nth(self.current + 1).unwrap()
-        }
+    /// The character after the next one, or `'\0'` past the end.
+    pub fn peek_next(&self) -> char {
+        let mut chars = self.source[self.current..].chars();
+        chars.next();
+        chars.next().unwrap_or('\0')
     }
 
+    /// Scans a string, which may span lines, replacing escape sequences
+    /// with the characters they stand for.
     pub fn string(&mut self) {
+        let mut value = String::new();
+
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() != '\n' {
-                self.line += 1;
+            if self.peek() != '\\' {
+                value.push(self.advance());
+                continue;
             }
+
+            let (start, line, column) = (self.current, self.line, self.column);
             self.advance();
+
+            match self.escape() {
+                Ok(c) => value.push(c),
+                Err(message) => {
+                    let span = Span::new(start, self.current, line, column);
+                    self.error(span, message);
+                }
+            }
         }
 
+        self.close_string();
+        self.add_token(TokenType::STRING, Some(Literal::String(value)));
+    }
+
+    /// Scans a raw string, `r"..."`, whose text is kept as written:
+    /// backslashes are not escapes, which suits regexes and Windows paths.
+    pub fn raw_string(&mut self) {
+        self.advance();
+        let content_start = self.current;
+
+        while self.peek() != '"' && !self.is_at_end() {
+            self.advance();
+        }
+
+        let value = self.source[content_start..self.current].to_string();
+        self.close_string();
+        self.add_token(TokenType::STRING, Some(Literal::String(value)));
+    }
+
+    /// Consumes the closing quote of a string, reporting at the opening
+    /// one when the source ends first.
+    fn close_string(&mut self) {
         if self.is_at_end() {
-            print!("Error handling");
+            let quote = self.source[self.start..].find('"').unwrap_or(0);
+            let span = Span::new(
+                self.start + quote,
+                self.start + quote + 1,
+                self.start_line,
+                self.start_column + quote,
+            );
+            self.error(span, String::from("Unterminated string."));
+        } else {
+            self.advance();
         }
+    }
+
+    /// Reads the escape sequence after a backslash: `\n`, `\t`, `\r`, `\0`,
+    /// `\"`, `\\`, or `\u{...}` with the hex code of any Unicode character.
+    fn escape(&mut self) -> Result<char, String> {
+        let escaped = match self.peek() {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '"' => '"',
+            '\\' => '\\',
+            'u' => {
+                self.advance();
+                return self.unicode_escape();
+            }
+            _ => {
+                // Leave a closing quote or the end of the source for
+                // `string` to deal with.
+                if self.peek() != '"' && !self.is_at_end() {
+                    self.advance();
+                }
+                return Err(String::from("Invalid escape sequence."));
+            }
+        };
 
         self.advance();
+        Ok(escaped)
+    }
 
-        let value = self.source[self.start + 1..self.current - 1]
-            .chars()
-            .collect::<String>();
-        self.add_token(TokenType::STRING, Some(Literal::String(value)));
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let invalid = || String::from("Invalid Unicode escape, expected 1 to 6 hex digits in '\\u{...}'.");
+
+        if !self.advance_if_then('{') {
+            return Err(invalid());
+        }
+
+        let digits_start = self.current;
+        while self.peek().is_ascii_hexdigit() {
+            self.advance();
+        }
+        let digits_end = self.current;
+
+        if digits_end == digits_start || digits_end - digits_start > 6 || !self.advance_if_then('}')
+        {
+            return Err(invalid());
+        }
+
+        u32::from_str_radix(&self.source[digits_start..digits_end], 16)
+            .ok()
+            .and_then(std::char::from_u32)
+            .ok_or_else(|| String::from("Invalid Unicode escape, not a Unicode character."))
+    }
+
+    fn error(&mut self, span: Span, message: String) {
+        let lexeme = self.source[span.start..span.end].to_string();
+        let token = Token::with_span(TokenType::STRING, lexeme, None, span);
+
+        self.errors.push(ParseError::SyntaxError(token, message));
     }
 
     // advance()-like function, but doesn't consumes the character.
     // returns a reference to the next character.
     pub fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        } else {
-            self.source.chars().nth(self.current).unwrap()
-        }
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
     
     pub fn scan_tokens(&mut self) -> &Vec<Token> {
         while !self.is_at_end() {
             self.start = self.current;
+            self.mark_token_start();
             self.scan_token();
         }
 
-        self.tokens
-            .push(Token::new(TokenType::EOF, "".to_string(), None, self.line));
+        self.start = self.current;
+        self.mark_token_start();
+        self.add_token(TokenType::EOF, None);
 
         &self.tokens
     }
 
+    fn mark_token_start(&mut self) {
+        self.start_line = self.line;
+        self.start_column = self.column;
+    }
+
     // Test purposes. Returns the current token.
     pub fn tokens_helper(self) -> Vec<Token> {
         self.tokens
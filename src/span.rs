@@ -0,0 +1,40 @@
+/// A region of the source text.
+///
+/// `start` and `end` are byte offsets into the source, `end` being
+/// exclusive. `line` and `column` are 1-based and locate `start`, with the
+/// column counted in characters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Self {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// A span that only knows its line, for tokens that were not scanned
+    /// from any source.
+    pub fn at_line(line: usize) -> Self {
+        Self {
+            line,
+            ..Self::default()
+        }
+    }
+
+    /// The span from the start of `self` to the end of `other`.
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..*self
+        }
+    }
+}
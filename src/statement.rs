@@ -1,61 +1,184 @@
 use crate::expression::*;
-use crate::object::*;
+use crate::span::*;
 use crate::token::*;
+use std::rc::Rc;
 
 #[derive(Clone)]
 pub enum Statement {
     Print {
         expression: Box<Expr>,
+        span: Span,
     },
     Expression {
         expression: Box<Expr>,
+        span: Span,
     },
     Variable {
         name: Token,
         initializer: Option<Box<Expr>>,
+        span: Span,
     },
 
     Block {
         statements: Vec<Box<Statement>>,
+        span: Span,
     },
+
+    If {
+        condition: Box<Expr>,
+        then_branch: Box<Statement>,
+        else_branch: Option<Box<Statement>>,
+        span: Span,
+    },
+
+    While {
+        condition: Box<Expr>,
+        body: Box<Statement>,
+        span: Span,
+    },
+
+    // The body is shared with every function value the declaration
+    // creates.
+    Function {
+        name: Token,
+        params: Vec<Token>,
+        body: Rc<[Box<Statement>]>,
+        span: Span,
+    },
+
+    Return {
+        keyword: Token,
+        value: Option<Box<Expr>>,
+        span: Span,
+    },
+
+    // The superclass is always an `Expr::Variable` and every method is a
+    // `Statement::Function`.
+    Class {
+        name: Token,
+        superclass: Option<Box<Expr>>,
+        methods: Vec<Box<Statement>>,
+        span: Span,
+    },
+}
+
+impl Statement {
+    /// The region of source this statement covers, from its first token to
+    /// its last.
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Print { span, .. }
+            | Statement::Expression { span, .. }
+            | Statement::Variable { span, .. }
+            | Statement::Block { span, .. }
+            | Statement::If { span, .. }
+            | Statement::While { span, .. }
+            | Statement::Function { span, .. }
+            | Statement::Return { span, .. }
+            | Statement::Class { span, .. } => *span,
+        }
+    }
 }
 
+/// A pass over statements, the counterpart of `ExprVisitor`.
+///
+/// The interpreter's `Value` is `Option<Object>`, where `Some` carries a
+/// `return` out of the enclosing function; other passes pick whatever
+/// suits them.
 pub trait StmtVisitor {
     type Value;
+    type Error;
+
     fn visit_var_stmt(
         &mut self,
         stmt: &Statement,
         name: &Token,
         initializer: &Option<Box<Expr>>,
-    ) -> Option<Self::Value>;
-    fn visit_expression_stmt(&mut self, stmt: &Statement, expr: &Box<Expr>) -> Option<Self::Value>;
-    fn visit_print_stmt(&mut self, stmt: &Statement, expr: &Box<Expr>) -> Option<Self::Value>;
+    ) -> Result<Self::Value, Self::Error>;
+    fn visit_expression_stmt(
+        &mut self,
+        stmt: &Statement,
+        expr: &Expr,
+    ) -> Result<Self::Value, Self::Error>;
+    fn visit_print_stmt(
+        &mut self,
+        stmt: &Statement,
+        expr: &Expr,
+    ) -> Result<Self::Value, Self::Error>;
     fn visit_block_statement(
         &mut self,
         _stmt: &Statement,
-        statements: &Vec<Box<Statement>>,
-    ) -> Option<Self::Value>;
+        statements: &[Box<Statement>],
+    ) -> Result<Self::Value, Self::Error>;
+    fn visit_if_statement(
+        &mut self,
+        stmt: &Statement,
+        condition: &Expr,
+        then_branch: &Statement,
+        else_branch: &Option<Box<Statement>>,
+    ) -> Result<Self::Value, Self::Error>;
+    fn visit_while_statement(
+        &mut self,
+        stmt: &Statement,
+        condition: &Expr,
+        body: &Statement,
+    ) -> Result<Self::Value, Self::Error>;
+    fn visit_function_statement(
+        &mut self,
+        stmt: &Statement,
+        name: &Token,
+        params: &[Token],
+        body: &Rc<[Box<Statement>]>,
+    ) -> Result<Self::Value, Self::Error>;
+    fn visit_return_statement(
+        &mut self,
+        stmt: &Statement,
+        keyword: &Token,
+        value: &Option<Box<Expr>>,
+    ) -> Result<Self::Value, Self::Error>;
+    fn visit_class_statement(
+        &mut self,
+        stmt: &Statement,
+        name: &Token,
+        superclass: &Option<Box<Expr>>,
+        methods: &[Box<Statement>],
+    ) -> Result<Self::Value, Self::Error>;
 }
 
 pub trait Visitable {
-    fn accept(&self, expr: &mut dyn StmtVisitor<Value = Object>) -> Option<Object>;
+    fn accept<V: StmtVisitor + ?Sized>(&self, visitor: &mut V) -> Result<V::Value, V::Error>;
 }
 
 impl Visitable for Statement {
-    fn accept(&self, visitor: &mut dyn StmtVisitor<Value = Object>) -> Option<Object> {
+    fn accept<V: StmtVisitor + ?Sized>(&self, visitor: &mut V) -> Result<V::Value, V::Error> {
         match self {
-            Self::Expression { expression } => visitor.visit_expression_stmt(&self, &expression),
-            Self::Print { expression } => visitor.visit_print_stmt(&self, &expression),
-            Self::Variable { name, initializer } => {
-                visitor.visit_var_stmt(&self, &name, &initializer)
+            Self::Expression { expression, .. } => visitor.visit_expression_stmt(self, expression),
+            Self::Print { expression, .. } => visitor.visit_print_stmt(self, expression),
+            Self::Variable {
+                name, initializer, ..
+            } => visitor.visit_var_stmt(self, name, initializer),
+            Self::Block { statements, .. } => visitor.visit_block_statement(self, statements),
+            Self::While {
+                condition, body, ..
+            } => visitor.visit_while_statement(self, condition, body),
+            Self::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => visitor.visit_if_statement(self, condition, then_branch, else_branch),
+            Self::Function {
+                name, params, body, ..
+            } => visitor.visit_function_statement(self, name, params, body),
+            Self::Return { keyword, value, .. } => {
+                visitor.visit_return_statement(self, keyword, value)
             }
-            Self::Block { statements } => visitor.visit_block_statement(&self, statements),
-            //Self::While { condition, body } => visitor.visit_while_statement(&self, condition, body),
-            // Self::If {
-            //     condition,
-            //     then_branch,
-            //     else_branch,
-            // } => visitor.visit_if_statement(&self, condition, then_branch, else_branch),
+            Self::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => visitor.visit_class_statement(self, name, superclass, methods),
         }
     }
 }
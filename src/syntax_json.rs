@@ -0,0 +1,597 @@
+use crate::expression::Visitable;
+use crate::expression::*;
+use crate::json::*;
+use crate::literal::*;
+use crate::span::*;
+use crate::statement::Visitable as VisitableStatement;
+use crate::statement::*;
+use crate::token::*;
+use crate::token_type::*;
+use std::convert::Infallible;
+use std::rc::Rc;
+
+/// Converts tokens and syntax trees to JSON for tools written in other
+/// languages, and back again.
+///
+/// Every node is an object with a `"kind"` naming the `Expr` or `Statement`
+/// variant, one field per variant field and the `"span"` of source it
+/// covers. Tokens carry their `"type"`, `"lexeme"`, `"span"` and, when they
+/// have one, `"literal"`. Optional children are `null` when missing.
+/// Resolver ids are not exported; nodes read back get fresh ones.
+pub struct JsonWriter;
+
+impl JsonWriter {
+    pub fn tokens(tokens: &[Token]) -> Json {
+        Json::Array(tokens.iter().map(token_to_json).collect())
+    }
+
+    pub fn expression(expression: &Expr) -> Json {
+        let json = expression
+            .accept(&mut JsonWriter)
+            .unwrap_or_else(|never| match never {});
+
+        with_span(json, &expression.span())
+    }
+
+    pub fn statements(statements: &[Box<Statement>]) -> Json {
+        Json::Array(
+            statements
+                .iter()
+                .map(|statement| JsonWriter::statement(statement))
+                .collect(),
+        )
+    }
+
+    pub fn statement(statement: &Statement) -> Json {
+        let json = statement
+            .accept(&mut JsonWriter)
+            .unwrap_or_else(|never| match never {});
+
+        with_span(json, &statement.span())
+    }
+
+    fn optional_expression(expression: &Option<Box<Expr>>) -> Json {
+        expression
+            .as_ref()
+            .map_or(Json::Null, |expression| JsonWriter::expression(expression))
+    }
+
+    fn expressions(expressions: &[Box<Expr>]) -> Json {
+        Json::Array(
+            expressions
+                .iter()
+                .map(|expression| JsonWriter::expression(expression))
+                .collect(),
+        )
+    }
+}
+
+fn with_span(mut json: Json, span: &Span) -> Json {
+    if let Json::Object(fields) = &mut json {
+        fields.push((String::from("span"), span_to_json(span)));
+    }
+    json
+}
+
+fn token_to_json(token: &Token) -> Json {
+    let mut fields = vec![
+        ("type", Json::String(format!("{:?}", token.of_type))),
+        ("lexeme", Json::String(token.lexeme.clone())),
+    ];
+    if let Some(literal) = &token.literal {
+        fields.push(("literal", literal_to_json(literal)));
+    }
+    fields.push(("span", span_to_json(&token.span)));
+
+    Json::object(fields)
+}
+
+fn literal_to_json(literal: &Literal) -> Json {
+    match literal {
+        Literal::String(s) => Json::String(s.clone()),
+        Literal::Number(x) => Json::Number(*x),
+        Literal::Boolean(b) => Json::Bool(*b),
+        Literal::None => Json::Null,
+    }
+}
+
+fn span_to_json(span: &Span) -> Json {
+    Json::object(vec![
+        ("start", Json::Number(span.start as f64)),
+        ("end", Json::Number(span.end as f64)),
+        ("line", Json::Number(span.line as f64)),
+        ("column", Json::Number(span.column as f64)),
+    ])
+}
+
+impl ExprVisitor for JsonWriter {
+    type Value = Json;
+    type Error = Infallible;
+
+    fn visit_assign_expression(
+        &mut self,
+        _id: usize,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Assign"))),
+            ("name", token_to_json(name)),
+            ("value", JsonWriter::expression(value)),
+        ]))
+    }
+
+    fn visit_binary_expression(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Binary"))),
+            ("left", JsonWriter::expression(left)),
+            ("operator", token_to_json(operator)),
+            ("right", JsonWriter::expression(right)),
+        ]))
+    }
+
+    fn visit_logical_expression(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Logical"))),
+            ("left", JsonWriter::expression(left)),
+            ("operator", token_to_json(operator)),
+            ("right", JsonWriter::expression(right)),
+        ]))
+    }
+
+    fn visit_call_expression(
+        &mut self,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Box<Expr>],
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Call"))),
+            ("callee", JsonWriter::expression(callee)),
+            ("paren", token_to_json(paren)),
+            ("arguments", JsonWriter::expressions(arguments)),
+        ]))
+    }
+
+    fn visit_get_expression(&mut self, object: &Expr, name: &Token) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Get"))),
+            ("object", JsonWriter::expression(object)),
+            ("name", token_to_json(name)),
+        ]))
+    }
+
+    fn visit_set_expression(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Set"))),
+            ("object", JsonWriter::expression(object)),
+            ("name", token_to_json(name)),
+            ("value", JsonWriter::expression(value)),
+        ]))
+    }
+
+    fn visit_this_expression(&mut self, _id: usize, keyword: &Token) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("This"))),
+            ("keyword", token_to_json(keyword)),
+        ]))
+    }
+
+    fn visit_super_expression(
+        &mut self,
+        _id: usize,
+        keyword: &Token,
+        method: &Token,
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Super"))),
+            ("keyword", token_to_json(keyword)),
+            ("method", token_to_json(method)),
+        ]))
+    }
+
+    fn visit_group_expression(&mut self, content: &Expr) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Grouping"))),
+            ("expression", JsonWriter::expression(content)),
+        ]))
+    }
+
+    fn visit_literal_expression(&mut self, literal: &Literal) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Literal"))),
+            ("value", literal_to_json(literal)),
+        ]))
+    }
+
+    fn visit_unary_expression(
+        &mut self,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Unary"))),
+            ("operator", token_to_json(operator)),
+            ("right", JsonWriter::expression(right)),
+        ]))
+    }
+
+    fn visit_variable_expression(&mut self, _id: usize, name: &Token) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Variable"))),
+            ("name", token_to_json(name)),
+        ]))
+    }
+}
+
+impl StmtVisitor for JsonWriter {
+    type Value = Json;
+    type Error = Infallible;
+
+    fn visit_var_stmt(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        initializer: &Option<Box<Expr>>,
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Var"))),
+            ("name", token_to_json(name)),
+            ("initializer", JsonWriter::optional_expression(initializer)),
+        ]))
+    }
+
+    fn visit_expression_stmt(
+        &mut self,
+        _stmt: &Statement,
+        expr: &Expr,
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Expression"))),
+            ("expression", JsonWriter::expression(expr)),
+        ]))
+    }
+
+    fn visit_print_stmt(&mut self, _stmt: &Statement, expr: &Expr) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Print"))),
+            ("expression", JsonWriter::expression(expr)),
+        ]))
+    }
+
+    fn visit_block_statement(
+        &mut self,
+        _stmt: &Statement,
+        statements: &[Box<Statement>],
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Block"))),
+            ("statements", JsonWriter::statements(statements)),
+        ]))
+    }
+
+    fn visit_if_statement(
+        &mut self,
+        _stmt: &Statement,
+        condition: &Expr,
+        then_branch: &Statement,
+        else_branch: &Option<Box<Statement>>,
+    ) -> Result<Json, Infallible> {
+        let else_branch = else_branch
+            .as_ref()
+            .map_or(Json::Null, |branch| JsonWriter::statement(branch));
+
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("If"))),
+            ("condition", JsonWriter::expression(condition)),
+            ("then_branch", JsonWriter::statement(then_branch)),
+            ("else_branch", else_branch),
+        ]))
+    }
+
+    fn visit_while_statement(
+        &mut self,
+        _stmt: &Statement,
+        condition: &Expr,
+        body: &Statement,
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("While"))),
+            ("condition", JsonWriter::expression(condition)),
+            ("body", JsonWriter::statement(body)),
+        ]))
+    }
+
+    fn visit_function_statement(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        params: &[Token],
+        body: &Rc<[Box<Statement>]>,
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Function"))),
+            ("name", token_to_json(name)),
+            ("params", JsonWriter::tokens(params)),
+            ("body", JsonWriter::statements(body)),
+        ]))
+    }
+
+    fn visit_return_statement(
+        &mut self,
+        _stmt: &Statement,
+        keyword: &Token,
+        value: &Option<Box<Expr>>,
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Return"))),
+            ("keyword", token_to_json(keyword)),
+            ("value", JsonWriter::optional_expression(value)),
+        ]))
+    }
+
+    fn visit_class_statement(
+        &mut self,
+        _stmt: &Statement,
+        name: &Token,
+        superclass: &Option<Box<Expr>>,
+        methods: &[Box<Statement>],
+    ) -> Result<Json, Infallible> {
+        Ok(Json::object(vec![
+            ("kind", Json::String(String::from("Class"))),
+            ("name", token_to_json(name)),
+            ("superclass", JsonWriter::optional_expression(superclass)),
+            ("methods", JsonWriter::statements(methods)),
+        ]))
+    }
+}
+
+/// Reads back what `JsonWriter` wrote. Errors name the first field that is
+/// missing or malformed.
+pub struct JsonReader;
+
+impl JsonReader {
+    pub fn tokens(json: &Json) -> Result<Vec<Token>, String> {
+        array(json, "tokens")?.iter().map(token_from_json).collect()
+    }
+
+    pub fn expression(json: &Json) -> Result<Box<Expr>, String> {
+        let expression = match kind(json)? {
+            "Assign" => Expr::Assign {
+                id: Expr::next_id(),
+                name: token_from_json(field(json, "name")?)?,
+                value: JsonReader::expression(field(json, "value")?)?,
+            },
+            "Binary" => Expr::Binary {
+                left: JsonReader::expression(field(json, "left")?)?,
+                operator: operator_from_json(json, "binary", TokenType::is_binary_operator)?,
+                right: JsonReader::expression(field(json, "right")?)?,
+            },
+            "Logical" => Expr::Logical {
+                left: JsonReader::expression(field(json, "left")?)?,
+                operator: operator_from_json(json, "logical", TokenType::is_logical_operator)?,
+                right: JsonReader::expression(field(json, "right")?)?,
+            },
+            "Call" => Expr::Call {
+                callee: JsonReader::expression(field(json, "callee")?)?,
+                paren: token_from_json(field(json, "paren")?)?,
+                arguments: array(field(json, "arguments")?, "arguments")?
+                    .iter()
+                    .map(JsonReader::expression)
+                    .collect::<Result<_, _>>()?,
+            },
+            "Get" => Expr::Get {
+                object: JsonReader::expression(field(json, "object")?)?,
+                name: token_from_json(field(json, "name")?)?,
+            },
+            "Set" => Expr::Set {
+                object: JsonReader::expression(field(json, "object")?)?,
+                name: token_from_json(field(json, "name")?)?,
+                value: JsonReader::expression(field(json, "value")?)?,
+            },
+            "This" => Expr::This {
+                id: Expr::next_id(),
+                keyword: token_from_json(field(json, "keyword")?)?,
+            },
+            "Super" => Expr::Super {
+                id: Expr::next_id(),
+                keyword: token_from_json(field(json, "keyword")?)?,
+                method: token_from_json(field(json, "method")?)?,
+            },
+            "Grouping" => Expr::Grouping {
+                expression: JsonReader::expression(field(json, "expression")?)?,
+                span: span_from_json(field(json, "span")?)?,
+            },
+            "Literal" => Expr::Literal {
+                literal: literal_from_json(field(json, "value")?)?,
+                span: span_from_json(field(json, "span")?)?,
+            },
+            "Unary" => Expr::Unary {
+                operator: operator_from_json(json, "unary", TokenType::is_unary_operator)?,
+                right: JsonReader::expression(field(json, "right")?)?,
+            },
+            "Variable" => Expr::Variable {
+                id: Expr::next_id(),
+                name: token_from_json(field(json, "name")?)?,
+            },
+            other => return Err(format!("Unknown expression kind '{}'.", other)),
+        };
+
+        Ok(Box::new(expression))
+    }
+
+    #[allow(clippy::vec_box)]
+    pub fn statements(json: &Json) -> Result<Vec<Box<Statement>>, String> {
+        array(json, "statements")?
+            .iter()
+            .map(JsonReader::statement)
+            .collect()
+    }
+
+    pub fn statement(json: &Json) -> Result<Box<Statement>, String> {
+        let span = span_from_json(field(json, "span")?)?;
+        let statement = match kind(json)? {
+            "Print" => Statement::Print {
+                expression: JsonReader::expression(field(json, "expression")?)?,
+                span,
+            },
+            "Expression" => Statement::Expression {
+                expression: JsonReader::expression(field(json, "expression")?)?,
+                span,
+            },
+            "Var" => Statement::Variable {
+                name: token_from_json(field(json, "name")?)?,
+                initializer: optional_expression(json, "initializer")?,
+                span,
+            },
+            "Block" => Statement::Block {
+                statements: JsonReader::statements(field(json, "statements")?)?,
+                span,
+            },
+            "If" => Statement::If {
+                condition: JsonReader::expression(field(json, "condition")?)?,
+                then_branch: JsonReader::statement(field(json, "then_branch")?)?,
+                else_branch: match field(json, "else_branch")? {
+                    Json::Null => None,
+                    branch => Some(JsonReader::statement(branch)?),
+                },
+                span,
+            },
+            "While" => Statement::While {
+                condition: JsonReader::expression(field(json, "condition")?)?,
+                body: JsonReader::statement(field(json, "body")?)?,
+                span,
+            },
+            "Function" => Statement::Function {
+                name: token_from_json(field(json, "name")?)?,
+                params: JsonReader::tokens(field(json, "params")?)?,
+                body: JsonReader::statements(field(json, "body")?)?.into(),
+                span,
+            },
+            "Return" => Statement::Return {
+                keyword: token_from_json(field(json, "keyword")?)?,
+                value: optional_expression(json, "value")?,
+                span,
+            },
+            "Class" => Statement::Class {
+                name: token_from_json(field(json, "name")?)?,
+                superclass: optional_expression(json, "superclass")?,
+                methods: JsonReader::statements(field(json, "methods")?)?,
+                span,
+            },
+            other => return Err(format!("Unknown statement kind '{}'.", other)),
+        };
+
+        Ok(Box::new(statement))
+    }
+}
+
+fn field<'a>(json: &'a Json, key: &str) -> Result<&'a Json, String> {
+    json.get(key)
+        .ok_or_else(|| format!("Missing field '{}'.", key))
+}
+
+fn kind(json: &Json) -> Result<&str, String> {
+    field(json, "kind")?
+        .as_str()
+        .ok_or_else(|| String::from("Field 'kind' must be a string."))
+}
+
+fn array<'a>(json: &'a Json, what: &str) -> Result<&'a [Json], String> {
+    json.as_array()
+        .ok_or_else(|| format!("Expected an array of {}.", what))
+}
+
+fn string(json: &Json, key: &str) -> Result<String, String> {
+    field(json, key)?
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| format!("Field '{}' must be a string.", key))
+}
+
+fn number(json: &Json, key: &str) -> Result<usize, String> {
+    match field(json, key)?.as_f64() {
+        Some(x) if x >= 0.0 && x.fract() == 0.0 => Ok(x as usize),
+        _ => Err(format!("Field '{}' must be a non-negative integer.", key)),
+    }
+}
+
+fn optional_expression(json: &Json, key: &str) -> Result<Option<Box<Expr>>, String> {
+    match field(json, key)? {
+        Json::Null => Ok(None),
+        expression => JsonReader::expression(expression).map(Some),
+    }
+}
+
+/// Reads the `operator` of a `kind` expression, rejecting tokens the
+/// backends can't apply.
+fn operator_from_json(
+    json: &Json,
+    kind: &str,
+    is_operator: fn(TokenType) -> bool,
+) -> Result<Token, String> {
+    let operator = token_from_json(field(json, "operator")?)?;
+
+    if is_operator(operator.of_type) {
+        Ok(operator)
+    } else {
+        Err(format!(
+            "{:?} is not a {} operator.",
+            operator.of_type, kind
+        ))
+    }
+}
+
+fn token_from_json(json: &Json) -> Result<Token, String> {
+    let name = string(json, "type")?;
+    let of_type =
+        TokenType::from_name(&name).ok_or_else(|| format!("Unknown token type '{}'.", name))?;
+
+    let literal = match json.get("literal") {
+        Some(literal) => Some(literal_from_json(literal)?),
+        None => None,
+    };
+
+    Ok(Token::with_span(
+        of_type,
+        string(json, "lexeme")?,
+        literal,
+        span_from_json(field(json, "span")?)?,
+    ))
+}
+
+fn literal_from_json(json: &Json) -> Result<Literal, String> {
+    match json {
+        Json::String(s) => Ok(Literal::String(s.clone())),
+        Json::Number(x) => Ok(Literal::Number(*x)),
+        Json::Bool(b) => Ok(Literal::Boolean(*b)),
+        Json::Null => Ok(Literal::None),
+        _ => Err(String::from(
+            "A literal must be a string, number, boolean or null.",
+        )),
+    }
+}
+
+fn span_from_json(json: &Json) -> Result<Span, String> {
+    Ok(Span::new(
+        number(json, "start")?,
+        number(json, "end")?,
+        number(json, "line")?,
+        number(json, "column")?,
+    ))
+}
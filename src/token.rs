@@ -1,13 +1,14 @@
 use super::literal::*;
+use super::span::*;
 use super::token_type::*;
 use std::fmt;
 
-#[derive(Eq, Clone,PartialEq, PartialOrd)]
+#[derive(Clone,PartialEq, PartialOrd)]
 pub struct Token {
     pub of_type: TokenType,
     pub lexeme: String,
     pub literal: Option<Literal>,
-    pub line: usize,
+    pub span: Span,
 }
 
 impl fmt::Debug for Token {
@@ -17,16 +18,27 @@ impl fmt::Debug for Token {
 }
 
 impl Token {
+    /// Creates a token that was not scanned from any source, so only its
+    /// line is known.
     pub fn new(of_type: TokenType, lexeme: String, literal: Option<Literal>, line: usize) -> Self {
+        Self::with_span(of_type, lexeme, literal, Span::at_line(line))
+    }
+
+    pub fn with_span(
+        of_type: TokenType,
+        lexeme: String,
+        literal: Option<Literal>,
+        span: Span,
+    ) -> Self {
         Self {
             of_type,
             lexeme,
             literal,
-            line,
+            span,
         }
     }
 
     pub fn lexeme(&self) -> String {
-        return self.lexeme.clone()
+        self.lexeme.clone()
     }
 }
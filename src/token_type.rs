@@ -1,4 +1,5 @@
-#[derive(Debug, Eq, Clone,Copy, PartialEq, PartialOrd)]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Eq, Clone, Copy, PartialEq, PartialOrd)]
 pub enum TokenType {
     // Single-character tokens.
     LEFT_PAREN,
@@ -47,3 +48,83 @@ pub enum TokenType {
     WHILE,
     EOF,
 }
+
+impl TokenType {
+    /// Every token type, in declaration order.
+    pub const ALL: [TokenType; 39] = [
+        TokenType::LEFT_PAREN,
+        TokenType::RIGHT_PAREN,
+        TokenType::LEFT_BRACE,
+        TokenType::RIGHT_BRACE,
+        TokenType::COMMA,
+        TokenType::DOT,
+        TokenType::MINUS,
+        TokenType::PLUS,
+        TokenType::SEMICOLON,
+        TokenType::SLASH,
+        TokenType::STAR,
+        TokenType::BANG,
+        TokenType::BANG_EQUAL,
+        TokenType::EQUAL,
+        TokenType::EQUAL_EQUAL,
+        TokenType::GREATER,
+        TokenType::GREATER_EQUAL,
+        TokenType::LESS,
+        TokenType::LESS_EQUAL,
+        TokenType::IDENTIFIER,
+        TokenType::STRING,
+        TokenType::NUMBER,
+        TokenType::AND,
+        TokenType::CLASS,
+        TokenType::ELSE,
+        TokenType::FALSE,
+        TokenType::FUN,
+        TokenType::FOR,
+        TokenType::IF,
+        TokenType::NIL,
+        TokenType::OR,
+        TokenType::PRINT,
+        TokenType::RETURN,
+        TokenType::SUPER,
+        TokenType::THIS,
+        TokenType::TRUE,
+        TokenType::VAR,
+        TokenType::WHILE,
+        TokenType::EOF,
+    ];
+
+    /// The token type whose `Debug` name is `name`, e.g. `"LEFT_PAREN"`.
+    pub fn from_name(name: &str) -> Option<TokenType> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|of_type| format!("{:?}", of_type) == name)
+    }
+
+    /// Whether this is the operator of an `Expr::Binary`.
+    pub fn is_binary_operator(self) -> bool {
+        matches!(
+            self,
+            TokenType::MINUS
+                | TokenType::PLUS
+                | TokenType::SLASH
+                | TokenType::STAR
+                | TokenType::BANG_EQUAL
+                | TokenType::EQUAL_EQUAL
+                | TokenType::GREATER
+                | TokenType::GREATER_EQUAL
+                | TokenType::LESS
+                | TokenType::LESS_EQUAL
+        )
+    }
+
+    /// Whether this is the operator of an `Expr::Unary`.
+    pub fn is_unary_operator(self) -> bool {
+        matches!(self, TokenType::MINUS | TokenType::BANG)
+    }
+
+    /// Whether this is the operator of an `Expr::Logical`.
+    pub fn is_logical_operator(self) -> bool {
+        matches!(self, TokenType::AND | TokenType::OR)
+    }
+}
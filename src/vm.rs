@@ -0,0 +1,625 @@
+use crate::chunk::*;
+use crate::interpreter::*;
+use crate::lox_callable::*;
+use crate::lox_error::*;
+use crate::object::*;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Calls nested deeper than this fail with a stack overflow, on either
+/// backend. The script itself takes up the first frame.
+pub const FRAMES_MAX: usize = 4096;
+
+/// A variable captured by a closure. It points into the stack while the
+/// function that declared it is running and holds the value itself once
+/// that function returns or the variable goes out of scope.
+pub enum Upvalue {
+    Open(usize),
+    Closed(Object),
+}
+
+/// A compiled function together with the variables it captured.
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// A class created by the VM. Methods are added one at a time after the
+/// class itself, and inherited ones are copied in up front.
+pub struct VmClass {
+    pub name: String,
+    pub methods: RefCell<HashMap<String, Rc<Closure>>>,
+}
+
+pub struct VmInstance {
+    pub class: Rc<VmClass>,
+    pub fields: HashMap<String, Object>,
+}
+
+/// A method looked up on an instance, remembering the instance so `this`
+/// refers to it when the method is called.
+pub struct BoundMethod {
+    pub receiver: Object,
+    pub method: Rc<Closure>,
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    // Offset of the next instruction to run.
+    ip: usize,
+    // Stack index of slot 0, the callee or `this`.
+    slots: usize,
+}
+
+/// Stack machine running functions built by the `Compiler`.
+///
+/// Globals, natives and `print` output are those of the interpreter it is
+/// given, so both backends share what a session has defined.
+pub struct Vm<'a> {
+    interpreter: &'a mut Interpreter,
+    stack: Vec<Object>,
+    frames: Vec<CallFrame>,
+    // Upvalues still pointing into the stack, so closures created in the
+    // same scope share them.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(interpreter: &'a mut Interpreter) -> Self {
+        Self {
+            interpreter,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
+        }
+    }
+
+    /// Runs a compiled script and returns the value it returns: nil for a
+    /// whole script, the value of the expression for one compiled by
+    /// `Compiler::compile_expression`.
+    pub fn run(&mut self, function: Rc<Function>) -> Result<Object, LoxError> {
+        let closure = Rc::new(Closure::new(function, Vec::new()));
+        self.stack.push(Object::Closure(Rc::clone(&closure)));
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: 0,
+        });
+
+        let result = self.execute();
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+
+        result
+    }
+
+    fn execute(&mut self) -> Result<Object, LoxError> {
+        loop {
+            let frame = self.frames.last_mut().expect("a frame is always running");
+            let op = frame.closure.function.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            match op {
+                OpCode::Constant(index) => {
+                    let value = self.constant(index);
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Object::Nil),
+                OpCode::True => self.stack.push(Object::Boolean(true)),
+                OpCode::False => self.stack.push(Object::Boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal(slot) => {
+                    let value = self.stack[self.frame().slots + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal(slot) => {
+                    let slot = self.frame().slots + slot as usize;
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal(index) => {
+                    let function = self.function();
+                    let name = name_of(&function, index);
+                    let value = self.interpreter.globals.values.get(name).cloned();
+                    match value {
+                        Some(value) => self.stack.push(value),
+                        None => return Err(self.undefined_variable(name)),
+                    }
+                }
+                OpCode::DefineGlobal(index) => {
+                    let function = self.function();
+                    let name = name_of(&function, index);
+                    let value = self.pop();
+                    self.interpreter.globals.define(name, &value);
+                }
+                OpCode::SetGlobal(index) => {
+                    let function = self.function();
+                    let name = name_of(&function, index);
+                    let value = self.peek(0).clone();
+                    match self.interpreter.globals.values.get_mut(name) {
+                        Some(slot) => *slot = value,
+                        None => return Err(self.undefined_variable(name)),
+                    }
+                }
+                OpCode::GetUpvalue(index) => {
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[index as usize]);
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue(index) => {
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[index as usize]);
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty(index) => {
+                    let function = self.function();
+                    let name = name_of(&function, index);
+                    let instance = match self.pop() {
+                        Object::VmInstance(instance) => instance,
+                        _ => return Err(self.error("Only instances have properties.")),
+                    };
+
+                    let field = instance.borrow().fields.get(name).cloned();
+                    let value = match field {
+                        Some(value) => value,
+                        None => {
+                            let class = Rc::clone(&instance.borrow().class);
+                            self.bind_method(&class, Object::VmInstance(instance), name)?
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetProperty(index) => {
+                    let function = self.function();
+                    let name = name_of(&function, index);
+                    let value = self.pop();
+                    let instance = match self.pop() {
+                        Object::VmInstance(instance) => instance,
+                        _ => return Err(self.error("Only instances have fields.")),
+                    };
+
+                    instance
+                        .borrow_mut()
+                        .fields
+                        .insert(name.to_string(), value.clone());
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper(index) => {
+                    let function = self.function();
+                    let name = name_of(&function, index);
+                    let superclass = self.pop();
+                    let receiver = self.pop();
+                    let value = match superclass {
+                        Object::VmClass(superclass) => {
+                            self.bind_method(&superclass, receiver, name)?
+                        }
+                        _ => unreachable!("'super' is always bound to a class"),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Equal => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Object::Boolean(left == right));
+                }
+                OpCode::Greater => self.compare(|order| order == Some(Ordering::Greater))?,
+                OpCode::GreaterEqual => self
+                    .compare(|order| matches!(order, Some(Ordering::Greater | Ordering::Equal)))?,
+                OpCode::Less => self.compare(|order| order == Some(Ordering::Less))?,
+                OpCode::LessEqual => {
+                    self.compare(|order| matches!(order, Some(Ordering::Less | Ordering::Equal)))?
+                }
+                OpCode::Add => self.arithmetic(|left, right| left + right)?,
+                OpCode::Subtract => self.arithmetic(|left, right| left - right)?,
+                OpCode::Multiply => self.arithmetic(|left, right| left * right)?,
+                OpCode::Divide => self.arithmetic(|left, right| left / right)?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Object::Boolean(!is_truthy(&value)));
+                }
+                OpCode::Negate => {
+                    let value = self.pop();
+                    match -value {
+                        Ok(value) => self.stack.push(value),
+                        Err(message) => return Err(self.error(&message)),
+                    }
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    self.interpreter
+                        .output()
+                        .write_out(&format!("{}\n", value))
+                        .map_err(LoxError::IoError)?;
+                }
+                OpCode::Jump(distance) => self.frame_mut().ip += distance as usize,
+                OpCode::JumpIfFalse(distance) => {
+                    if !is_truthy(self.peek(0)) {
+                        self.frame_mut().ip += distance as usize;
+                    }
+                }
+                OpCode::Loop(distance) => self.frame_mut().ip -= distance as usize,
+                OpCode::Call(count) => {
+                    let callee = self.peek(count as usize).clone();
+                    self.call_value(callee, count as usize)?;
+                }
+                OpCode::Closure(index) => {
+                    let function = Rc::clone(&self.function().chunk.functions[index as usize]);
+
+                    let mut upvalues = Vec::with_capacity(function.captures.len());
+                    for capture in &function.captures {
+                        let upvalue = if capture.is_local {
+                            self.capture_upvalue(self.frame().slots + capture.index as usize)
+                        } else {
+                            Rc::clone(&self.frame().closure.upvalues[capture.index as usize])
+                        };
+                        upvalues.push(upvalue);
+                    }
+
+                    let closure = Closure::new(function, upvalues);
+                    self.stack.push(Object::Closure(Rc::new(closure)));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("a frame is always running");
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+
+                    if self.frames.is_empty() {
+                        return Ok(result);
+                    }
+                    self.stack.push(result);
+                }
+                OpCode::Class(index) => {
+                    let function = self.function();
+                    let name = name_of(&function, index);
+                    let class = self.interpreter.heap.vm_class(VmClass::new(name));
+                    self.stack.push(Object::VmClass(class));
+                }
+                OpCode::Inherit => {
+                    let superclass = match self.peek(1) {
+                        Object::VmClass(superclass) => Rc::clone(superclass),
+                        _ => return Err(self.error("Superclass must be a class.")),
+                    };
+                    if let Object::VmClass(subclass) = self.pop() {
+                        let methods = superclass.methods.borrow().clone();
+                        subclass.methods.borrow_mut().extend(methods);
+                    }
+                }
+                OpCode::Method(index) => {
+                    let function = self.function();
+                    let name = name_of(&function, index);
+                    let method = self.pop();
+                    if let (Object::VmClass(class), Object::Closure(method)) =
+                        (self.peek(0), method)
+                    {
+                        class.methods.borrow_mut().insert(name.to_string(), method);
+                    }
+                }
+            }
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("a frame is always running")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("a frame is always running")
+    }
+
+    fn function(&self) -> Rc<Function> {
+        Rc::clone(&self.frame().closure.function)
+    }
+
+    fn constant(&self, index: u32) -> Object {
+        self.frame().closure.function.chunk.constants[index as usize].clone()
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack
+            .pop()
+            .expect("the compiler keeps the stack balanced")
+    }
+
+    fn peek(&self, distance: usize) -> &Object {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    /// A runtime error reported at the token of the instruction running in
+    /// the innermost frame.
+    fn error(&self, message: &str) -> LoxError {
+        let frame = self.frame();
+        let token = frame.closure.function.chunk.token_at(frame.ip - 1);
+        LoxError::RuntimeError(token, message.to_string())
+    }
+
+    fn undefined_variable(&self, name: &str) -> LoxError {
+        self.error(&format!("Undefined variable '{}'.", name))
+    }
+
+    fn arithmetic<F>(&mut self, operation: F) -> Result<(), LoxError>
+    where
+        F: Fn(Object, Object) -> Result<Object, String>,
+    {
+        let right = self.pop();
+        let left = self.pop();
+        let value = operation(left, right).map_err(|message| self.error(&message))?;
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn compare<F>(&mut self, test: F) -> Result<(), LoxError>
+    where
+        F: Fn(Option<Ordering>) -> bool,
+    {
+        let right = self.pop();
+        let left = self.pop();
+        let order = left
+            .compare(&right)
+            .map_err(|message| self.error(&message))?;
+        self.stack.push(Object::Boolean(test(order)));
+        Ok(())
+    }
+
+    /// Calls `callee`, which sits on the stack under its `count` arguments.
+    fn call_value(&mut self, callee: Object, count: usize) -> Result<(), LoxError> {
+        let callee_slot = self.stack.len() - 1 - count;
+
+        match callee {
+            Object::Closure(closure) => self.call(closure, count),
+            Object::BoundMethod(bound) => {
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call(Rc::clone(&bound.method), count)
+            }
+            Object::VmClass(class) => {
+                let instance = VmInstance::new(&class);
+                self.stack[callee_slot] = Object::VmInstance(self.interpreter.heap.vm_instance(instance));
+
+                let initializer = class.methods.borrow().get("init").cloned();
+                match initializer {
+                    Some(initializer) => self.call(initializer, count),
+                    None => self.check_arity(0, count),
+                }
+            }
+            Object::Native(native) => {
+                self.check_arity(native.arity, count)?;
+                let arguments = self.stack.split_off(callee_slot + 1);
+                let value = native
+                    .call(self.interpreter, &arguments)
+                    .map_err(|message| self.error(&message))?;
+
+                self.stack.truncate(callee_slot);
+                self.stack.push(value);
+                Ok(())
+            }
+            // Functions and classes the tree-walking interpreter created,
+            // should a session mix both backends.
+            Object::Function(function) => self.call_tree_walker(function, count),
+            Object::Class(class) => self.call_tree_walker(class, count),
+            _ => Err(self.error("Can only call functions and classes.")),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, count: usize) -> Result<(), LoxError> {
+        self.check_arity(closure.function.arity, count)?;
+
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.error("Stack overflow."));
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: self.stack.len() - 1 - count,
+        });
+        Ok(())
+    }
+
+    /// Runs a callable from the tree-walking interpreter to completion and
+    /// leaves its result in place of the callee.
+    fn call_tree_walker(
+        &mut self,
+        callable: Rc<dyn LoxCallable>,
+        count: usize,
+    ) -> Result<(), LoxError> {
+        self.check_arity(callable.arity(), count)?;
+
+        let callee_slot = self.stack.len() - 1 - count;
+        let arguments = self.stack.split_off(callee_slot + 1);
+        let value = callable.call(self.interpreter, arguments)?;
+
+        self.stack.truncate(callee_slot);
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn check_arity(&self, arity: usize, count: usize) -> Result<(), LoxError> {
+        if count != arity {
+            return Err(self.error(&format!("Expected {} arguments but got {}.", arity, count)));
+        }
+
+        Ok(())
+    }
+
+    /// Looks `name` up on `class` and binds it to `receiver`.
+    fn bind_method(
+        &self,
+        class: &VmClass,
+        receiver: Object,
+        name: &str,
+    ) -> Result<Object, LoxError> {
+        let method = class.methods.borrow().get(name).cloned();
+
+        match method {
+            Some(method) => Ok(Object::BoundMethod(Rc::new(BoundMethod {
+                receiver,
+                method,
+            }))),
+            None => Err(self.error(&format!("Undefined property '{}'.", name))),
+        }
+    }
+
+    /// The upvalue for stack slot `slot`, shared with any other closure
+    /// that already captured it.
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(&*upvalue.borrow(), Upvalue::Open(open) if *open == slot));
+
+        if let Some(upvalue) = existing {
+            return Rc::clone(upvalue);
+        }
+
+        let upvalue = self.interpreter.heap.upvalue(Upvalue::Open(slot));
+        self.open_upvalues.push(Rc::clone(&upvalue));
+        upvalue
+    }
+
+    /// Moves every captured variable at or above stack slot `last` off the
+    /// stack and into its upvalue.
+    fn close_upvalues(&mut self, last: usize) {
+        let stack = &self.stack;
+
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match &*upvalue.borrow() {
+                Upvalue::Open(slot) if *slot >= last => *slot,
+                _ => return true,
+            };
+
+            *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+            false
+        });
+    }
+}
+
+fn is_truthy(value: &Object) -> bool {
+    match value {
+        Object::Nil => false,
+        Object::Boolean(b) => *b,
+        _ => true,
+    }
+}
+
+/// The name constant `index` of `function`.
+fn name_of(function: &Function, index: u32) -> &str {
+    function.chunk.constants[index as usize]
+        .as_str()
+        .expect("names are string constants")
+}
+
+impl Closure {
+    pub fn new(function: Rc<Function>, upvalues: Vec<Rc<RefCell<Upvalue>>>) -> Self {
+        Self { function, upvalues }
+    }
+}
+
+impl VmClass {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            methods: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl VmInstance {
+    pub fn new(class: &Rc<VmClass>) -> Self {
+        Self {
+            class: Rc::clone(class),
+            fields: HashMap::new(),
+        }
+    }
+}
+
+// Closures, classes, instances and bound methods are only equal to
+// themselves.
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl PartialEq for VmClass {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl PartialEq for VmInstance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl PartialEq for BoundMethod {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
+
+impl fmt::Display for VmClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl fmt::Debug for VmClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl fmt::Display for VmInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+impl fmt::Debug for VmInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+impl fmt::Display for BoundMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.method)
+    }
+}
+
+impl fmt::Debug for BoundMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.method)
+    }
+}